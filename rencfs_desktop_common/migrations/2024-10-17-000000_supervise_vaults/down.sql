@@ -0,0 +1,3 @@
+DROP TABLE vault_actions;
+
+ALTER TABLE vaults DROP COLUMN restart_policy;
@@ -0,0 +1,13 @@
+ALTER TABLE vaults ADD COLUMN restart_policy VARCHAR NOT NULL DEFAULT 'never';
+
+-- what happened to the vault processes, to show why a vault went down
+CREATE TABLE vault_actions
+(
+    id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    vault_id   INTEGER NOT NULL REFERENCES vaults (id) ON DELETE CASCADE,
+    created_at BIGINT  NOT NULL,
+    action     VARCHAR NOT NULL,
+    message    VARCHAR NOT NULL
+);
+
+CREATE INDEX vault_actions_vault_id ON vault_actions (vault_id);
@@ -0,0 +1,2 @@
+ALTER TABLE vaults DROP COLUMN credential_key_id;
+ALTER TABLE vaults DROP COLUMN credential_provider;
@@ -0,0 +1,5 @@
+-- where the password of the vault comes from, see `rencfs_desktop_common::credentials`
+ALTER TABLE vaults ADD COLUMN credential_provider VARCHAR NOT NULL DEFAULT 'prompt';
+ALTER TABLE vaults ADD COLUMN credential_key_id VARCHAR NOT NULL DEFAULT '';
+
+UPDATE vaults SET credential_key_id = 'vault-' || id;
@@ -0,0 +1,2 @@
+-- 0 while InitVault creates the encrypted store, such vaults are not listed
+ALTER TABLE vaults ADD COLUMN ready INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,2 @@
+-- the AEAD cipher rencfs encrypts the vault with, existing vaults used the rencfs default
+ALTER TABLE vaults ADD COLUMN cipher VARCHAR NOT NULL DEFAULT 'chacha20_poly1305';
@@ -0,0 +1,12 @@
+-- data dir moves in progress, so they are resumed after a restart, see `rencfs_desktop_common::data_dir`
+CREATE TABLE data_dir_moves
+(
+    vault_id     INTEGER NOT NULL PRIMARY KEY REFERENCES vaults (id) ON DELETE CASCADE,
+    old_data_dir VARCHAR NOT NULL,
+    new_data_dir VARCHAR NOT NULL,
+    remove_old   INTEGER NOT NULL,
+    was_unlocked INTEGER NOT NULL,
+    -- copying, then switched once vaults.data_dir is the new one
+    phase        VARCHAR NOT NULL,
+    created_at   BIGINT  NOT NULL
+);
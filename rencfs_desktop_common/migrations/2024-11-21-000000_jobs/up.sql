@@ -0,0 +1,21 @@
+-- long operations on a vault run in the background, see `rencfs_desktop_common::jobs`
+CREATE TABLE jobs
+(
+    id          INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    vault_id    INTEGER NOT NULL REFERENCES vaults (id) ON DELETE CASCADE,
+    -- data_dir_move or backup
+    kind        VARCHAR NOT NULL,
+    -- the new data dir or the backup dir
+    target      VARCHAR NOT NULL,
+    -- running, succeeded, failed or cancelled
+    state       VARCHAR NOT NULL,
+    -- the progress is saved when the job ends, the daemon keeps it while it runs
+    phase       VARCHAR NOT NULL,
+    done_bytes  BIGINT  NOT NULL DEFAULT 0,
+    total_bytes BIGINT  NOT NULL DEFAULT 0,
+    error       VARCHAR,
+    created_at  BIGINT  NOT NULL,
+    finished_at BIGINT
+);
+
+CREATE INDEX jobs_vault_id ON jobs (vault_id);
@@ -0,0 +1 @@
+ALTER TABLE vaults DROP COLUMN auto_lock_secs;
@@ -0,0 +1,2 @@
+-- idle seconds after which an unlocked vault is locked, 0 never locks it
+ALTER TABLE vaults ADD COLUMN auto_lock_secs INTEGER NOT NULL DEFAULT 0;
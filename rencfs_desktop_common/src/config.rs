@@ -0,0 +1,246 @@
+//! Daemon settings, from `daemon.toml` in [`get_config_dir`] and `RENCFS_DESKTOP_*` env vars.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{instrument, warn, Level};
+
+use crate::directories::{get_config_dir, get_logs_dir};
+use crate::is_debug;
+
+const FILE_NAME: &str = "daemon.toml";
+/// Env vars named like this followed by the upper case setting name override the file, like
+/// `RENCFS_DESKTOP_LOG_LEVEL`.
+pub const ENV_PREFIX: &str = "RENCFS_DESKTOP_";
+
+static CURRENT: OnceLock<RwLock<Arc<DaemonConfig>>> = OnceLock::new();
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("cannot read {}: {source}", .path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("cannot parse {}: {source}", .path.display())]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid {setting}: {reason}")]
+    Invalid { setting: String, reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// One of `trace`, `debug`, `info`, `warn` and `error`.
+    pub log_level: String,
+    /// Path of the Unix socket on Linux, `host:port` elsewhere.
+    pub listen: String,
+    /// Seconds to wait for rencfs to mount a vault.
+    pub mount_timeout: u64,
+    /// Seconds to wait for each vault to lock when the daemon stops.
+    pub shutdown_timeout: u64,
+    /// If not set, rencfs is looked up as described in [`RencfsBinary::resolve`](crate::rencfs::RencfsBinary::resolve).
+    pub rencfs_path: Option<PathBuf>,
+    /// Where the stdout and stderr of the rencfs processes are written.
+    pub rencfs_logs_dir: PathBuf,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            log_level: if is_debug() { "debug" } else { "info" }.to_string(),
+            listen: default_listen(),
+            mount_timeout: 10,
+            shutdown_timeout: 10,
+            rencfs_path: None,
+            rencfs_logs_dir: get_logs_dir(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn default_listen() -> String {
+    crate::directories::get_socket_path().display().to_string()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_listen() -> String {
+    "[::1]:50051".to_string()
+}
+
+impl DaemonConfig {
+    pub fn path() -> PathBuf {
+        get_config_dir().join(FILE_NAME)
+    }
+
+    /// Reads the file, which is optional, applies the env overrides and validates the result.
+    #[instrument(err)]
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = Self::path();
+        let mut config = match fs::read_to_string(&path) {
+            Ok(content) => {
+                toml::from_str(&content).map_err(|source| ConfigError::Parse { path, source })?
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(source) => return Err(ConfigError::Read { path, source }),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(value) = env_var("log_level") {
+            self.log_level = value;
+        }
+        if let Some(value) = env_var("listen") {
+            self.listen = value;
+        }
+        if let Some(value) = env_var("mount_timeout") {
+            self.mount_timeout = parse_env("mount_timeout", &value)?;
+        }
+        if let Some(value) = env_var("shutdown_timeout") {
+            self.shutdown_timeout = parse_env("shutdown_timeout", &value)?;
+        }
+        // `rencfs_path` has no override, its env var comes after it when looking up the binary
+        if let Some(value) = env_var("rencfs_logs_dir") {
+            self.rencfs_logs_dir = PathBuf::from(value);
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |setting: &str, reason: &str| {
+            Err(ConfigError::Invalid {
+                setting: setting.to_string(),
+                reason: reason.to_string(),
+            })
+        };
+        if Level::from_str(&self.log_level).is_err() {
+            return invalid("log_level", "use trace, debug, info, warn or error");
+        }
+        if self.listen.is_empty() {
+            return invalid("listen", "it can't be empty");
+        }
+        #[cfg(target_os = "linux")]
+        if !std::path::Path::new(&self.listen).is_absolute() {
+            return invalid("listen", "the socket path must be absolute");
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.listen.parse::<std::net::SocketAddr>().is_err() {
+            return invalid("listen", "use host:port");
+        }
+        if self.mount_timeout == 0 {
+            return invalid("mount_timeout", "it must be at least 1 second");
+        }
+        if self.shutdown_timeout == 0 {
+            return invalid("shutdown_timeout", "it must be at least 1 second");
+        }
+        if self
+            .rencfs_path
+            .as_ref()
+            .is_some_and(|p| p.as_os_str().is_empty())
+        {
+            return invalid("rencfs_path", "remove it instead of leaving it empty");
+        }
+        if !self.rencfs_logs_dir.is_absolute() {
+            return invalid("rencfs_logs_dir", "the path must be absolute");
+        }
+        Ok(())
+    }
+
+    pub fn log_level(&self) -> Level {
+        Level::from_str(&self.log_level).unwrap_or(Level::INFO)
+    }
+
+    pub fn mount_timeout(&self) -> Duration {
+        Duration::from_secs(self.mount_timeout)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout)
+    }
+}
+
+fn env_var(setting: &str) -> Option<String> {
+    env::var(format!("{ENV_PREFIX}{}", setting.to_uppercase())).ok()
+}
+
+fn parse_env<T: FromStr>(setting: &str, value: &str) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| ConfigError::Invalid {
+        setting: format!("{ENV_PREFIX}{}", setting.to_uppercase()),
+        reason: format!("cannot parse {value}"),
+    })
+}
+
+/// The settings in use, loaded on first use, falling back to the defaults if they are invalid.
+pub fn current() -> Arc<DaemonConfig> {
+    CURRENT
+        .get_or_init(|| {
+            RwLock::new(Arc::new(DaemonConfig::load().unwrap_or_else(|err| {
+                warn!(err = %err, "Using the default config");
+                DaemonConfig::default()
+            })))
+        })
+        .read()
+        .unwrap()
+        .clone()
+}
+
+/// The settings changed by [`reload`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Changes {
+    /// In use from now on.
+    pub applied: Vec<&'static str>,
+    /// Only used at startup, the current value is kept until the daemon is restarted.
+    pub restart_required: Vec<&'static str>,
+}
+
+/// Loads the settings again and makes them current.
+///
+/// If they are invalid the current ones are kept.
+pub fn reload() -> Result<Changes, ConfigError> {
+    let mut config = DaemonConfig::load()?;
+    let Some(lock) = CURRENT.get() else {
+        CURRENT.get_or_init(|| RwLock::new(Arc::new(config)));
+        return Ok(Changes::default());
+    };
+    let mut current = lock.write().unwrap();
+    let mut changes = Changes::default();
+
+    if config.listen != current.listen {
+        changes.restart_required.push("listen");
+        config.listen.clone_from(&current.listen);
+    }
+    // running processes are still writing to the old one
+    if config.rencfs_logs_dir != current.rencfs_logs_dir {
+        changes.restart_required.push("rencfs_logs_dir");
+        config.rencfs_logs_dir.clone_from(&current.rencfs_logs_dir);
+    }
+    for (setting, changed) in [
+        ("log_level", config.log_level != current.log_level),
+        (
+            "mount_timeout",
+            config.mount_timeout != current.mount_timeout,
+        ),
+        (
+            "shutdown_timeout",
+            config.shutdown_timeout != current.shutdown_timeout,
+        ),
+        ("rencfs_path", config.rencfs_path != current.rencfs_path),
+    ] {
+        if changed {
+            changes.applied.push(setting);
+        }
+    }
+
+    *current = Arc::new(config);
+    Ok(changes)
+}
@@ -0,0 +1,243 @@
+//! Where the vault passwords come from, see [`CredentialProvider`].
+//!
+//! Each vault records the provider its password is kept in and the key id it's stored under.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Once};
+
+use async_trait::async_trait;
+use rand::RngCore;
+use thiserror::Error;
+use tracing::{info, warn};
+use zeroize::Zeroize;
+
+use crate::models::Vault;
+use crate::vault_events::VaultEventHub;
+
+pub mod key_file;
+pub mod prompt;
+#[cfg(target_os = "linux")]
+pub mod secret_service;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum CredentialError {
+    #[error("no password stored under {0}")]
+    NotFound(String),
+    #[error("the password was not provided")]
+    NotProvided,
+    #[error("{0}")]
+    Unavailable(String),
+    #[error("invalid key id {0}")]
+    InvalidKeyId(String),
+}
+
+/// A vault password, locked in RAM so it's not swapped out, wiped when dropped and never printed.
+pub struct Password {
+    // never grows, a reallocation would leave a copy behind
+    buf: Box<[u8]>,
+    locked: bool,
+}
+
+impl Password {
+    /// Takes the password out of `password`, which is wiped.
+    pub fn new(mut password: String) -> Self {
+        let buf = Box::from(password.as_bytes());
+        password.zeroize();
+        Self::from_buf(buf)
+    }
+
+    fn from_buf(buf: Box<[u8]>) -> Self {
+        #[cfg(target_os = "linux")]
+        let locked = match crate::platform::memory::lock(&buf) {
+            Ok(_) => true,
+            Err(err) => {
+                static WARNED: Once = Once::new();
+                WARNED.call_once(|| warn!(err = %err, "Cannot lock passwords in memory"));
+                false
+            }
+        };
+        #[cfg(not(target_os = "linux"))]
+        let locked = false;
+        Self { buf, locked }
+    }
+
+    pub fn expose(&self) -> &str {
+        std::str::from_utf8(&self.buf).expect("passwords are made from strings")
+    }
+}
+
+impl Clone for Password {
+    fn clone(&self) -> Self {
+        Self::from_buf(self.buf.clone())
+    }
+}
+
+impl Drop for Password {
+    fn drop(&mut self) {
+        self.buf.zeroize();
+        #[cfg(target_os = "linux")]
+        if self.locked {
+            crate::platform::memory::unlock(&self.buf);
+        }
+    }
+}
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Password(***)")
+    }
+}
+
+/// Gets and keeps vault passwords, by key id.
+#[async_trait]
+pub trait CredentialProvider: Send + Sync {
+    async fn get(&self, vault_id: u32, key_id: &str) -> Result<Password, CredentialError>;
+
+    /// Replaces the password stored under `key_id`, if any.
+    async fn store(
+        &self,
+        vault_id: u32,
+        key_id: &str,
+        password: &Password,
+    ) -> Result<(), CredentialError>;
+
+    /// It's fine if nothing is stored under `key_id`.
+    async fn delete(&self, key_id: &str) -> Result<(), CredentialError>;
+}
+
+/// The providers a vault can use, stored in `vaults.credential_provider`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProviderKind {
+    /// Asked every time through the app, nothing is stored.
+    #[default]
+    Prompt,
+    /// The freedesktop Secret Service, like GNOME Keyring or KWallet.
+    SecretService,
+    /// Encrypted files in the data dir, see [`key_file`].
+    KeyFile,
+}
+
+impl ProviderKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderKind::Prompt => "prompt",
+            ProviderKind::SecretService => "secret_service",
+            ProviderKind::KeyFile => "key_file",
+        }
+    }
+}
+
+impl FromStr for ProviderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prompt" => Ok(ProviderKind::Prompt),
+            "secret_service" => Ok(ProviderKind::SecretService),
+            "key_file" => Ok(ProviderKind::KeyFile),
+            _ => Err(format!("unknown credential provider {s}")),
+        }
+    }
+}
+
+/// A new random key id, for a new vault.
+pub fn new_key_id() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Key ids end up in file names and keyring attributes.
+fn check_key_id(key_id: &str) -> Result<(), CredentialError> {
+    if key_id.is_empty()
+        || !key_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(CredentialError::InvalidKeyId(key_id.to_string()));
+    }
+    Ok(())
+}
+
+/// All the providers, shared by the vault handlers.
+pub struct Credentials {
+    prompt: Arc<prompt::PromptProvider>,
+    key_file: key_file::KeyFileProvider,
+    #[cfg(target_os = "linux")]
+    secret_service: secret_service::SecretServiceProvider,
+}
+
+impl Credentials {
+    pub fn new(events: VaultEventHub) -> Self {
+        Self {
+            prompt: Arc::new(prompt::PromptProvider::new(events)),
+            key_file: key_file::KeyFileProvider::new(),
+            #[cfg(target_os = "linux")]
+            secret_service: secret_service::SecretServiceProvider,
+        }
+    }
+
+    pub fn provider(&self, kind: ProviderKind) -> Result<&dyn CredentialProvider, CredentialError> {
+        match kind {
+            ProviderKind::Prompt => Ok(self.prompt.as_ref()),
+            ProviderKind::KeyFile => Ok(&self.key_file),
+            #[cfg(target_os = "linux")]
+            ProviderKind::SecretService => Ok(&self.secret_service),
+            #[cfg(not(target_os = "linux"))]
+            ProviderKind::SecretService => Err(CredentialError::Unavailable(
+                "the Secret Service is only available on Linux".to_string(),
+            )),
+        }
+    }
+
+    /// Answers the password requests of [`prompt::PromptProvider`].
+    pub fn prompt(&self) -> &prompt::PromptProvider {
+        &self.prompt
+    }
+
+    /// The password of `vault`, asked for through the app if its provider doesn't have it yet.
+    ///
+    /// It's `true` along with the password if it was asked for, it should be stored with
+    /// [`Credentials::remember`] once it's known to be right.
+    pub async fn password(&self, vault: &Vault) -> Result<(Password, bool), CredentialError> {
+        let kind = kind_of(vault)?;
+        let id = vault.id as u32;
+        match self.provider(kind)?.get(id, &vault.credential_key_id).await {
+            Err(CredentialError::NotFound(_)) if kind != ProviderKind::Prompt => {
+                info!(
+                    id,
+                    provider = kind.as_str(),
+                    "No password stored, asking for it"
+                );
+                let password = self.prompt.get(id, &vault.credential_key_id).await?;
+                Ok((password, true))
+            }
+            res => res.map(|password| (password, false)),
+        }
+    }
+
+    /// Stores the password of `vault` in its provider.
+    pub async fn remember(
+        &self,
+        vault: &Vault,
+        password: &Password,
+    ) -> Result<(), CredentialError> {
+        self.provider(kind_of(vault)?)?
+            .store(vault.id as u32, &vault.credential_key_id, password)
+            .await
+    }
+
+    /// Deletes the password of `vault` from `kind`, used when the vault is deleted or moved to
+    /// another provider.
+    pub async fn forget(&self, vault: &Vault, kind: ProviderKind) -> Result<(), CredentialError> {
+        self.provider(kind)?.delete(&vault.credential_key_id).await
+    }
+}
+
+pub(crate) fn kind_of(vault: &Vault) -> Result<ProviderKind, CredentialError> {
+    vault
+        .credential_provider
+        .parse()
+        .map_err(CredentialError::Unavailable)
+}
@@ -0,0 +1,177 @@
+//! Passwords in `keys/<key_id>.key` in the data dir, encrypted with ChaCha20-Poly1305.
+//!
+//! The key is `credentials.key` in the config dir, readable only by the user. Both are needed
+//! to get the passwords, so a copy of the data dir alone, like in a backup, doesn't give them away.
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{AeadCore, ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use tracing::info;
+use zeroize::Zeroizing;
+
+use super::{check_key_id, CredentialError, CredentialProvider, Password};
+use crate::directories::{get_config_dir, get_data_dir};
+
+const MASTER_KEY_FILE: &str = "credentials.key";
+const KEYS_DIR: &str = "keys";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+pub struct KeyFileProvider {
+    master_key_path: PathBuf,
+    keys_dir: PathBuf,
+}
+
+impl KeyFileProvider {
+    pub fn new() -> Self {
+        Self {
+            master_key_path: get_config_dir().join(MASTER_KEY_FILE),
+            keys_dir: get_data_dir().join(KEYS_DIR),
+        }
+    }
+
+    fn key_path(&self, key_id: &str) -> Result<PathBuf, CredentialError> {
+        check_key_id(key_id)?;
+        Ok(self.keys_dir.join(format!("{key_id}.key")))
+    }
+
+    /// Creates the master key the first time.
+    fn cipher(&self, create: bool) -> Result<ChaCha20Poly1305, CredentialError> {
+        let key = match fs::read(&self.master_key_path) {
+            Ok(key) => Zeroizing::new(key),
+            Err(err) if err.kind() == io::ErrorKind::NotFound && create => {
+                self.create_master_key()?
+            }
+            Err(err) => return Err(io_error(&self.master_key_path, err)),
+        };
+        if key.len() != KEY_LEN {
+            return Err(CredentialError::Unavailable(format!(
+                "{} is not a valid key",
+                self.master_key_path.display()
+            )));
+        }
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+
+    fn create_master_key(&self) -> Result<Zeroizing<Vec<u8>>, CredentialError> {
+        let mut key = Zeroizing::new(vec![0u8; KEY_LEN]);
+        OsRng.fill_bytes(&mut key);
+        info!(path = %self.master_key_path.display(), "Creating credentials key");
+        write_private(&self.master_key_path, &key)?;
+        Ok(key)
+    }
+}
+
+impl Default for KeyFileProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for KeyFileProvider {
+    async fn get(&self, _vault_id: u32, key_id: &str) -> Result<Password, CredentialError> {
+        let path = self.key_path(key_id)?;
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(CredentialError::NotFound(key_id.to_string()))
+            }
+            Err(err) => return Err(io_error(&path, err)),
+        };
+        if content.len() < NONCE_LEN {
+            return Err(corrupted(&path));
+        }
+        let (nonce, ciphertext) = content.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher(false)?
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    // so the files can't be swapped between vaults
+                    aad: key_id.as_bytes(),
+                },
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| corrupted(&path))?;
+        let password = String::from_utf8(plaintext.to_vec()).map_err(|_| corrupted(&path))?;
+        Ok(Password::new(password))
+    }
+
+    async fn store(
+        &self,
+        _vault_id: u32,
+        key_id: &str,
+        password: &Password,
+    ) -> Result<(), CredentialError> {
+        let path = self.key_path(key_id)?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher(true)?
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: password.expose().as_bytes(),
+                    aad: key_id.as_bytes(),
+                },
+            )
+            .map_err(|_| CredentialError::Unavailable("cannot encrypt the password".to_string()))?;
+        let mut content = nonce.to_vec();
+        content.extend_from_slice(&ciphertext);
+        write_private(&path, &content)
+    }
+
+    async fn delete(&self, key_id: &str) -> Result<(), CredentialError> {
+        let path = self.key_path(key_id)?;
+        match fs::remove_file(&path) {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(io_error(&path, err)),
+        }
+    }
+}
+
+/// Writes a file only the user can read, through a temp file so it's never half written.
+fn write_private(path: &Path, content: &[u8]) -> Result<(), CredentialError> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir).map_err(|err| io_error(dir, err))?;
+    let tmp = path.with_extension("tmp");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+    let res = options
+        .open(&tmp)
+        .and_then(|mut file| {
+            file.write_all(content)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, path));
+    if let Err(err) = res {
+        let _ = fs::remove_file(&tmp);
+        return Err(io_error(path, err));
+    }
+    Ok(())
+}
+
+fn io_error(path: &Path, err: io::Error) -> CredentialError {
+    CredentialError::Unavailable(format!("cannot access {}: {err}", path.display()))
+}
+
+fn corrupted(path: &Path) -> CredentialError {
+    CredentialError::Unavailable(format!(
+        "cannot decrypt {}, it's corrupted or {MASTER_KEY_FILE} changed",
+        path.display()
+    ))
+}
@@ -0,0 +1,109 @@
+//! Asks the app for the password, through a [`VaultEvent::PasswordRequested`] the app answers
+//! with the `ProvidePassword` RPC.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use super::{CredentialError, CredentialProvider, Password};
+use crate::vault_events::{VaultEvent, VaultEventHub};
+
+/// How long the user has to type the password.
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+type Answer = oneshot::Sender<Option<Password>>;
+
+pub struct PromptProvider {
+    events: VaultEventHub,
+    apps: Arc<AtomicUsize>,
+    next_request_id: AtomicU64,
+    pending: Mutex<HashMap<u64, Answer>>,
+}
+
+impl PromptProvider {
+    pub fn new(events: VaultEventHub) -> Self {
+        Self {
+            events,
+            apps: Arc::new(AtomicUsize::new(0)),
+            next_request_id: AtomicU64::new(1),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Held while an app watches the vault events, so the password is asked for only if there is
+    /// an app to answer.
+    pub fn app_watching(&self) -> AppWatching {
+        self.apps.fetch_add(1, Ordering::Relaxed);
+        AppWatching(self.apps.clone())
+    }
+
+    /// Answers request `request_id`, `None` if the user cancelled it.
+    ///
+    /// `false` if there is no such request, it timed out or was already answered.
+    pub fn answer(&self, request_id: u64, password: Option<Password>) -> bool {
+        let Some(answer) = self.pending.lock().unwrap().remove(&request_id) else {
+            return false;
+        };
+        answer.send(password).is_ok()
+    }
+}
+
+pub struct AppWatching(Arc<AtomicUsize>);
+
+impl Drop for AppWatching {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for PromptProvider {
+    async fn get(&self, vault_id: u32, _key_id: &str) -> Result<Password, CredentialError> {
+        if self.apps.load(Ordering::Relaxed) == 0 {
+            return Err(CredentialError::Unavailable(
+                "the app is not running to ask for the password".to_string(),
+            ));
+        }
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(request_id, tx);
+        info!(vault_id, request_id, "Asking for the password");
+        self.events.publish(VaultEvent::PasswordRequested {
+            id: vault_id,
+            request_id,
+        });
+
+        let answer = tokio::time::timeout(PROMPT_TIMEOUT, rx).await;
+        self.pending.lock().unwrap().remove(&request_id);
+        match answer {
+            Ok(Ok(Some(password))) => Ok(password),
+            Ok(Ok(None)) | Ok(Err(_)) => {
+                info!(vault_id, request_id, "Password request cancelled");
+                Err(CredentialError::NotProvided)
+            }
+            Err(_) => {
+                warn!(vault_id, request_id, "Password request timed out");
+                Err(CredentialError::NotProvided)
+            }
+        }
+    }
+
+    // asked every time, nothing to keep
+    async fn store(
+        &self,
+        _vault_id: u32,
+        _key_id: &str,
+        _password: &Password,
+    ) -> Result<(), CredentialError> {
+        Ok(())
+    }
+
+    async fn delete(&self, _key_id: &str) -> Result<(), CredentialError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+//! Passwords in the freedesktop Secret Service of the session, like GNOME Keyring or KWallet.
+//!
+//! The items are found by the `application` and `key_id` attributes. Unlocking the keyring might
+//! show a prompt of the keyring itself.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use secret_service::{EncryptionType, Error, SecretService};
+
+use super::{check_key_id, CredentialError, CredentialProvider, Password};
+
+const APPLICATION: &str = "rencfs_desktop";
+const CONTENT_TYPE: &str = "text/plain";
+
+pub struct SecretServiceProvider;
+
+fn attributes(key_id: &str) -> HashMap<&str, &str> {
+    HashMap::from([("application", APPLICATION), ("key_id", key_id)])
+}
+
+async fn connect() -> Result<SecretService<'static>, CredentialError> {
+    SecretService::connect(EncryptionType::Dh)
+        .await
+        .map_err(unavailable)
+}
+
+fn unavailable(err: Error) -> CredentialError {
+    let message = match err {
+        Error::Unavailable => "no Secret Service is running in the session".to_string(),
+        Error::Prompt => "the keyring was not unlocked".to_string(),
+        err => format!("Secret Service error: {err}"),
+    };
+    CredentialError::Unavailable(message)
+}
+
+#[async_trait]
+impl CredentialProvider for SecretServiceProvider {
+    async fn get(&self, _vault_id: u32, key_id: &str) -> Result<Password, CredentialError> {
+        check_key_id(key_id)?;
+        let service = connect().await?;
+        let items = service
+            .search_items(attributes(key_id))
+            .await
+            .map_err(unavailable)?;
+        let item = match (items.unlocked.first(), items.locked.first()) {
+            (Some(item), _) => item,
+            (None, Some(item)) => {
+                item.unlock().await.map_err(unavailable)?;
+                item
+            }
+            (None, None) => return Err(CredentialError::NotFound(key_id.to_string())),
+        };
+        let secret = zeroize::Zeroizing::new(item.get_secret().await.map_err(unavailable)?);
+        let password = String::from_utf8(secret.to_vec()).map_err(|_| {
+            CredentialError::Unavailable(format!("the password stored under {key_id} is not UTF-8"))
+        })?;
+        Ok(Password::new(password))
+    }
+
+    async fn store(
+        &self,
+        vault_id: u32,
+        key_id: &str,
+        password: &Password,
+    ) -> Result<(), CredentialError> {
+        check_key_id(key_id)?;
+        let service = connect().await?;
+        let collection = service
+            .get_default_collection()
+            .await
+            .map_err(unavailable)?;
+        collection.ensure_unlocked().await.map_err(unavailable)?;
+        collection
+            .create_item(
+                &format!("rencfs vault {vault_id}"),
+                attributes(key_id),
+                password.expose().as_bytes(),
+                true,
+                CONTENT_TYPE,
+            )
+            .await
+            .map_err(unavailable)?;
+        Ok(())
+    }
+
+    async fn delete(&self, key_id: &str) -> Result<(), CredentialError> {
+        check_key_id(key_id)?;
+        let service = connect().await?;
+        let items = service
+            .search_items(attributes(key_id))
+            .await
+            .map_err(unavailable)?;
+        for item in items.unlocked.iter().chain(items.locked.iter()) {
+            item.delete().await.map_err(unavailable)?;
+        }
+        Ok(())
+    }
+}
@@ -1,13 +1,14 @@
 use diesel::query_builder::QueryFragment;
 use diesel::sqlite::Sqlite;
 use diesel::{
-    delete, insert_into, update, AsChangeset, Connection, EqAll, QueryDsl, QueryResult,
-    RunQueryDsl, SelectableHelper, SqliteConnection,
+    delete, insert_into, update, AsChangeset, Connection, EqAll, ExpressionMethods, QueryDsl,
+    QueryResult, RunQueryDsl, SelectableHelper, SqliteConnection,
 };
 
-use crate::models::{NewVault, Vault};
+use crate::models::{DataDirMove, Job, NewJob, NewVault, NewVaultAction, Vault, VaultAction};
 use crate::schema::vaults::dsl::vaults;
-use crate::schema::vaults::id;
+use crate::schema::vaults::{id, name, ready};
+use crate::schema::{data_dir_moves, jobs, vault_actions};
 
 pub struct VaultDao<'a>(&'a mut SqliteConnection);
 
@@ -32,6 +33,13 @@ impl<'a> VaultDao<'a> {
         vaults.find(id_v).select(Vault::as_select()).first(self.0)
     }
 
+    pub fn get_by_name(&mut self, name_v: &str) -> QueryResult<Vault> {
+        vaults
+            .filter(name.eq_all(name_v))
+            .select(Vault::as_select())
+            .first(self.0)
+    }
+
     pub fn update<V>(&mut self, id_v: i32, value: V) -> QueryResult<()>
     where
         V: AsChangeset<Target = vaults>,
@@ -42,14 +50,27 @@ impl<'a> VaultDao<'a> {
         Ok(())
     }
 
+    /// The vaults ready to use, without the ones InitVault is still creating.
     pub fn get_all(&mut self, limit: Option<i64>) -> QueryResult<Vec<Vault>> {
+        let query = vaults.filter(ready.eq(1)).select(Vault::as_select());
         if let Some(limit) = limit {
-            vaults.select(Vault::as_select()).limit(limit).load(self.0)
+            query.limit(limit).load(self.0)
         } else {
-            vaults.select(Vault::as_select()).load(self.0)
+            query.load(self.0)
         }
     }
 
+    /// Deletes the vaults left half created when the daemon stopped during InitVault.
+    pub fn delete_unready(&mut self) -> QueryResult<Vec<Vault>> {
+        let unready = vaults
+            .filter(ready.eq(0))
+            .select(Vault::as_select())
+            .load(self.0)?;
+        delete(vaults.filter(ready.eq(0))).execute(self.0)?;
+
+        Ok(unready)
+    }
+
     pub fn transaction<F>(&mut self, f: F) -> QueryResult<usize>
     where
         F: FnOnce(VaultDao) -> QueryResult<usize>,
@@ -57,3 +78,141 @@ impl<'a> VaultDao<'a> {
         self.0.transaction(|conn| f(VaultDao::new(conn)))
     }
 }
+
+pub struct VaultActionDao<'a>(&'a mut SqliteConnection);
+
+impl<'a> VaultActionDao<'a> {
+    pub fn new(conn: &'a mut SqliteConnection) -> Self {
+        VaultActionDao(conn)
+    }
+
+    pub fn insert(&mut self, e: &NewVaultAction) -> QueryResult<()> {
+        insert_into(vault_actions::table)
+            .values(e)
+            .execute(self.0)?;
+
+        Ok(())
+    }
+
+    /// Newest first.
+    pub fn get_by_vault(&mut self, vault_id_v: i32, limit: i64) -> QueryResult<Vec<VaultAction>> {
+        vault_actions::table
+            .filter(vault_actions::vault_id.eq_all(vault_id_v))
+            .order(vault_actions::id.desc())
+            .limit(limit)
+            .select(VaultAction::as_select())
+            .load(self.0)
+    }
+}
+
+pub struct DataDirMoveDao<'a>(&'a mut SqliteConnection);
+
+impl<'a> DataDirMoveDao<'a> {
+    pub fn new(conn: &'a mut SqliteConnection) -> Self {
+        DataDirMoveDao(conn)
+    }
+
+    pub fn insert(&mut self, e: &DataDirMove) -> QueryResult<()> {
+        insert_into(data_dir_moves::table)
+            .values(e)
+            .execute(self.0)?;
+
+        Ok(())
+    }
+
+    pub fn get_all(&mut self) -> QueryResult<Vec<DataDirMove>> {
+        data_dir_moves::table
+            .select(DataDirMove::as_select())
+            .load(self.0)
+    }
+
+    /// Points the vault to the new data dir and moves on to `phase`, both or none.
+    pub fn switch(&mut self, e: &DataDirMove, phase: &str) -> QueryResult<()> {
+        self.0.transaction(|conn| {
+            update(vaults.find(e.vault_id))
+                .set(crate::schema::vaults::data_dir.eq(&e.new_data_dir))
+                .execute(conn)?;
+            update(data_dir_moves::table.find(e.vault_id))
+                .set(data_dir_moves::phase.eq(phase))
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    pub fn delete(&mut self, vault_id_v: i32) -> QueryResult<()> {
+        delete(data_dir_moves::table.find(vault_id_v)).execute(self.0)?;
+
+        Ok(())
+    }
+}
+
+pub struct JobDao<'a>(&'a mut SqliteConnection);
+
+impl<'a> JobDao<'a> {
+    pub fn new(conn: &'a mut SqliteConnection) -> Self {
+        JobDao(conn)
+    }
+
+    /// `None` if the vault has a job in `running_state` already.
+    pub fn insert(&mut self, e: &NewJob, running_state: &str) -> QueryResult<Option<Job>> {
+        self.0.transaction(|conn| {
+            let running: i64 = jobs::table
+                .filter(jobs::vault_id.eq(e.vault_id))
+                .filter(jobs::state.eq(running_state))
+                .count()
+                .get_result(conn)?;
+            if running > 0 {
+                return Ok(None);
+            }
+            insert_into(jobs::table).values(e).execute(conn)?;
+            jobs::table
+                .order(jobs::id.desc())
+                .select(Job::as_select())
+                .first(conn)
+                .map(Some)
+        })
+    }
+
+    pub fn get(&mut self, id_v: i32) -> QueryResult<Job> {
+        jobs::table
+            .find(id_v)
+            .select(Job::as_select())
+            .first(self.0)
+    }
+
+    /// Newest first.
+    pub fn list(&mut self, vault_id_v: Option<i32>, limit: i64) -> QueryResult<Vec<Job>> {
+        let mut query = jobs::table.into_boxed();
+        if let Some(vault_id_v) = vault_id_v {
+            query = query.filter(jobs::vault_id.eq(vault_id_v));
+        }
+        query
+            .order(jobs::id.desc())
+            .limit(limit)
+            .select(Job::as_select())
+            .load(self.0)
+    }
+
+    pub fn get_by_state(&mut self, state_v: &str) -> QueryResult<Vec<Job>> {
+        jobs::table
+            .filter(jobs::state.eq(state_v))
+            .select(Job::as_select())
+            .load(self.0)
+    }
+
+    /// Saves the state and progress of `e`.
+    pub fn save(&mut self, e: &Job) -> QueryResult<()> {
+        update(jobs::table.find(e.id))
+            .set((
+                jobs::state.eq(&e.state),
+                jobs::phase.eq(&e.phase),
+                jobs::done_bytes.eq(e.done_bytes),
+                jobs::total_bytes.eq(e.total_bytes),
+                jobs::error.eq(&e.error),
+                jobs::finished_at.eq(e.finished_at),
+            ))
+            .execute(self.0)?;
+
+        Ok(())
+    }
+}
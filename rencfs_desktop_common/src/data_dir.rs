@@ -0,0 +1,372 @@
+//! Moving the encrypted files of a vault to another data dir, see [`copy_verified`].
+//!
+//! A move is recorded in `data_dir_moves` while it runs, so it's finished after a daemon restart.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::{error, info};
+
+use crate::vault_handler::VaultHandlerError;
+
+const BUF_LEN: usize = 256 * 1024;
+/// What rencfs creates in a new data dir, the key files and where it keeps the encrypted ones.
+const RENCFS_LAYOUT: &[&str] = &["security", "inodes", "contents"];
+
+/// What a move is doing, reported with the bytes done so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovePhase {
+    Copying,
+    /// Reading the copy back and comparing it with the originals.
+    Verifying,
+    RemovingOld,
+}
+
+impl MovePhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MovePhase::Copying => "copying",
+            MovePhase::Verifying => "verifying",
+            MovePhase::RemovingOld => "removing_old",
+        }
+    }
+}
+
+impl FromStr for MovePhase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "copying" => Ok(MovePhase::Copying),
+            "verifying" => Ok(MovePhase::Verifying),
+            "removing_old" => Ok(MovePhase::RemovingOld),
+            _ => Err(format!("unknown copy phase {s}")),
+        }
+    }
+}
+
+/// How far a move got, stored in `data_dir_moves.phase`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveState {
+    /// The vault still uses the old data dir, the copy might be partial.
+    Copying,
+    /// The vault uses the new data dir, only the old copy might be left to remove.
+    Switched,
+}
+
+impl MoveState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MoveState::Copying => "copying",
+            MoveState::Switched => "switched",
+        }
+    }
+}
+
+impl FromStr for MoveState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "copying" => Ok(MoveState::Copying),
+            "switched" => Ok(MoveState::Switched),
+            _ => Err(format!("unknown data dir move phase {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DataDirError {
+    #[error("not enough space in {}, {needed} bytes are needed", .path.display())]
+    NoSpace { path: PathBuf, needed: u64 },
+    #[error("the copy of {} doesn't match the original", .0.display())]
+    Mismatch(PathBuf),
+    #[error("{} is not a file or a dir", .0.display())]
+    Unsupported(PathBuf),
+    #[error("cannot access {}: {}", .0.display(), .1)]
+    Io(PathBuf, io::Error),
+    #[error("cancelled")]
+    Cancelled,
+}
+
+impl DataDirError {
+    /// `other` for the errors the user can't do anything about.
+    pub(crate) fn into_handler_error(self, other: VaultHandlerError) -> VaultHandlerError {
+        if let DataDirError::Cancelled = self {
+            info!("Copying the data dir cancelled");
+            return VaultHandlerError::Cancelled;
+        }
+        error!(err = %self, "Cannot copy the data dir");
+        match self {
+            DataDirError::NoSpace { path, .. } => {
+                VaultHandlerError::NoSpace(path.display().to_string())
+            }
+            _ => other,
+        }
+    }
+}
+
+/// A file or dir to copy, relative to the data dir.
+struct Entry {
+    path: PathBuf,
+    len: u64,
+    is_dir: bool,
+}
+
+/// Copies everything in `from` to `to`, syncs it to disk, then reads it back and checks it against
+/// the SHA-256 of the originals.
+///
+/// Files already in `to` with the content of the originals, left by an interrupted copy, are kept.
+/// `progress` is called with the bytes done and the total, often, it should throttle what it does.
+/// Setting `cancel` stops it at the next block with [`DataDirError::Cancelled`], what was copied is
+/// left in `to`.
+pub(crate) fn copy_verified(
+    from: &Path,
+    to: &Path,
+    cancel: &AtomicBool,
+    progress: &mut dyn FnMut(MovePhase, u64, u64),
+) -> Result<(), DataDirError> {
+    let mut entries = vec![];
+    walk(from, Path::new(""), &mut entries)?;
+    let total: u64 = entries.iter().map(|e| e.len).sum();
+    check_space(to, total)?;
+
+    let no_space = |err: DataDirError| match err {
+        DataDirError::Io(_, ref io_err) if is_no_space(io_err) => DataDirError::NoSpace {
+            path: to.to_path_buf(),
+            needed: total,
+        },
+        err => err,
+    };
+    let mut hashes = Vec::with_capacity(entries.len());
+    let mut done = 0;
+    progress(MovePhase::Copying, done, total);
+    for entry in &entries {
+        let dst = to.join(&entry.path);
+        if entry.is_dir {
+            fs::create_dir_all(&dst).map_err(|err| DataDirError::Io(dst.clone(), err))?;
+            continue;
+        }
+        let hash = copy_file(&from.join(&entry.path), &dst, cancel, &mut |n| {
+            done += n;
+            progress(MovePhase::Copying, done, total);
+        })
+        .map_err(no_space)?;
+        hashes.push((dst, hash));
+    }
+    // the entries of the dirs too, not only the file contents
+    for dir in entries
+        .iter()
+        .filter(|e| e.is_dir)
+        .map(|e| to.join(&e.path))
+        .chain([to.to_path_buf()])
+    {
+        File::open(&dir)
+            .and_then(|f| f.sync_all())
+            .map_err(|err| DataDirError::Io(dir, err))
+            .map_err(no_space)?;
+    }
+
+    let mut done = 0;
+    progress(MovePhase::Verifying, done, total);
+    for (dst, hash) in hashes {
+        let copied = hash_file(&dst, cancel, &mut |n| {
+            done += n;
+            progress(MovePhase::Verifying, done, total);
+        })?;
+        if copied != hash {
+            return Err(DataDirError::Mismatch(dst));
+        }
+    }
+    Ok(())
+}
+
+/// Only files and dirs, rencfs doesn't create anything else.
+fn walk(root: &Path, rel: &Path, entries: &mut Vec<Entry>) -> Result<(), DataDirError> {
+    let dir = root.join(rel);
+    let read_dir = fs::read_dir(&dir).map_err(|err| DataDirError::Io(dir.clone(), err))?;
+    for entry in read_dir {
+        let entry = entry.map_err(|err| DataDirError::Io(dir.clone(), err))?;
+        let path = rel.join(entry.file_name());
+        let metadata = entry
+            .metadata()
+            .map_err(|err| DataDirError::Io(entry.path(), err))?;
+        if metadata.is_dir() {
+            entries.push(Entry {
+                path: path.clone(),
+                len: 0,
+                is_dir: true,
+            });
+            walk(root, &path, entries)?;
+        } else if metadata.is_file() {
+            entries.push(Entry {
+                path,
+                len: metadata.len(),
+                is_dir: false,
+            });
+        } else {
+            return Err(DataDirError::Unsupported(entry.path()));
+        }
+    }
+    Ok(())
+}
+
+/// Fails early instead of filling the disk, the files already copied count as written.
+fn check_space(to: &Path, total: u64) -> Result<(), DataDirError> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut copied = vec![];
+        walk(to, Path::new(""), &mut copied)?;
+        let needed = total.saturating_sub(copied.iter().map(|e| e.len).sum());
+        let available = crate::platform::mounts::available_space(to)
+            .map_err(|err| DataDirError::Io(to.to_path_buf(), err))?;
+        if needed > available {
+            return Err(DataDirError::NoSpace {
+                path: to.to_path_buf(),
+                needed,
+            });
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (to, total);
+    Ok(())
+}
+
+/// Copies `src` to `dst` with its permissions and syncs it, the SHA-256 of `src` is returned.
+fn copy_file(
+    src: &Path,
+    dst: &Path,
+    cancel: &AtomicBool,
+    progress: &mut dyn FnMut(u64),
+) -> Result<[u8; 32], DataDirError> {
+    let src_err = |err| DataDirError::Io(src.to_path_buf(), err);
+    let dst_err = |err| DataDirError::Io(dst.to_path_buf(), err);
+    let src_meta = fs::metadata(src).map_err(src_err)?;
+    let src_len = src_meta.len();
+    if fs::metadata(dst).is_ok_and(|m| m.len() == src_len) {
+        let hash = hash_file(src, cancel, &mut |_| {})?;
+        if hash_file(dst, cancel, &mut |_| {})? == hash {
+            fs::set_permissions(dst, src_meta.permissions()).map_err(dst_err)?;
+            // it might have been left only in the page cache
+            File::open(dst)
+                .and_then(|f| f.sync_all())
+                .map_err(dst_err)?;
+            progress(src_len);
+            return Ok(hash);
+        }
+    }
+
+    let mut reader = File::open(src).map_err(src_err)?;
+    let mut writer = File::create(dst).map_err(dst_err)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; BUF_LEN];
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(DataDirError::Cancelled);
+        }
+        let n = reader.read(&mut buf).map_err(src_err)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        writer.write_all(&buf[..n]).map_err(dst_err)?;
+        progress(n as u64);
+    }
+    // the key files are only readable by the owner, set last so a retry can still write it
+    writer
+        .set_permissions(src_meta.permissions())
+        .map_err(dst_err)?;
+    writer.sync_all().map_err(dst_err)?;
+    Ok(hasher.finalize().into())
+}
+
+fn hash_file(
+    path: &Path,
+    cancel: &AtomicBool,
+    progress: &mut dyn FnMut(u64),
+) -> Result<[u8; 32], DataDirError> {
+    let err = |err| DataDirError::Io(path.to_path_buf(), err);
+    let mut reader = File::open(path).map_err(err)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; BUF_LEN];
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(DataDirError::Cancelled);
+        }
+        let n = reader.read(&mut buf).map_err(err)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        progress(n as u64);
+    }
+    Ok(hasher.finalize().into())
+}
+
+fn is_no_space(err: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    return err.raw_os_error() == Some(libc::ENOSPC);
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = err;
+        false
+    }
+}
+
+/// A new data dir needs to be empty and writable, it's created if missing.
+pub(crate) fn check_empty_data_dir(path: &Path) -> Result<(), VaultHandlerError> {
+    let not_writable = |err: io::Error| {
+        error!(path = %path.display(), err = %err, "Cannot write to the data dir");
+        VaultHandlerError::DataDirNotWritable(path.display().to_string())
+    };
+    fs::create_dir_all(path).map_err(not_writable)?;
+    if fs::read_dir(path).map_err(not_writable)?.next().is_some() {
+        return Err(VaultHandlerError::DataDirNotEmpty(
+            path.display().to_string(),
+        ));
+    }
+    let probe = path.join(".rencfs_desktop_probe");
+    fs::write(&probe, b"")
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(not_writable)
+}
+
+/// If `path` is `data_dir` or inside it, once symlinks and `..` are resolved.
+pub(crate) fn is_inside(path: &Path, data_dir: &Path) -> bool {
+    match (fs::canonicalize(path), fs::canonicalize(data_dir)) {
+        (Ok(path), Ok(data_dir)) => path.starts_with(data_dir),
+        // compared as they are if one of them is gone
+        _ => path.starts_with(data_dir),
+    }
+}
+
+/// Empties `path` if it holds nothing but what rencfs creates in a new data dir, like after a
+/// vault was left half created. `false` if something else is in it, it's left alone then.
+pub fn clear_rencfs_dir(path: &Path) -> io::Result<bool> {
+    for entry in fs::read_dir(path)? {
+        let name = entry?.file_name();
+        if !RENCFS_LAYOUT.iter().any(|n| name == *n) {
+            return Ok(false);
+        }
+    }
+    clear_dir(path)?;
+    Ok(true)
+}
+
+/// Removes everything in `path`, only used on data dirs checked to be empty before.
+pub(crate) fn clear_dir(path: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
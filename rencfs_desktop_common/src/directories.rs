@@ -47,6 +47,26 @@ pub fn get_logs_dir() -> PathBuf {
     }
 }
 
+/// Directory for runtime files like the daemon socket.
+/// Uses `$XDG_RUNTIME_DIR` when available and falls back to the config dir.
+pub fn get_runtime_dir() -> PathBuf {
+    let path = if is_debug() {
+        get_dev_data_dir()
+    } else {
+        let proj_dirs = get_project_dirs();
+        proj_dirs
+            .runtime_dir()
+            .unwrap_or(proj_dirs.config_local_dir())
+            .to_path_buf()
+    };
+    fs::create_dir_all(&path).expect("Cannot create runtime directory");
+    path
+}
+
+pub fn get_socket_path() -> PathBuf {
+    get_runtime_dir().join("daemon.sock")
+}
+
 fn get_dev_data_dir() -> PathBuf {
     let path = PathBuf::from_str(&format!(
         "/tmp/{}",
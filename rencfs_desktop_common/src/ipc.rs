@@ -0,0 +1,28 @@
+use tonic::transport::{Channel, Endpoint, Error};
+use tracing::instrument;
+
+/// Connects to the daemon over the per-user Unix socket set as `listen` in the [`config`](crate::config).
+#[cfg(target_os = "linux")]
+#[instrument(err)]
+pub async fn connect() -> Result<Channel, Error> {
+    use hyper_util::rt::TokioIo;
+    use tokio::net::UnixStream;
+    use tower::service_fn;
+
+    let path = std::path::PathBuf::from(&crate::config::current().listen);
+    // the uri is ignored, the connector always dials the socket
+    Endpoint::try_from("http://[::]:50051")?
+        .connect_with_connector(service_fn(move |_| {
+            let path = path.clone();
+            async move { Ok::<_, std::io::Error>(TokioIo::new(UnixStream::connect(path).await?)) }
+        }))
+        .await
+}
+
+#[cfg(not(target_os = "linux"))]
+#[instrument(err)]
+pub async fn connect() -> Result<Channel, Error> {
+    Endpoint::try_from(format!("http://{}", crate::config::current().listen))?
+        .connect()
+        .await
+}
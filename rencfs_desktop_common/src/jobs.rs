@@ -0,0 +1,329 @@
+//! Long operations on a vault, like moving its data dir, run in the background as jobs.
+//!
+//! Jobs are recorded in the `jobs` table, a vault has at most one running. While a job runs its
+//! progress is kept in memory, for `GetJob` and the `WatchJob` streams, and saved when it ends.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use diesel::SqliteConnection;
+use tokio::sync::{watch, Mutex};
+use tracing::{error, info, warn};
+
+use crate::dao::JobDao;
+use crate::data_dir::MovePhase;
+use crate::models::{Job, NewJob};
+use crate::vault_handler::VaultHandlerError;
+use crate::vault_service_error::VaultServiceError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    /// See [`crate::vault_handler::VaultHandler::change_data_dir`].
+    DataDirMove,
+    /// See [`crate::vault_handler::VaultHandler::backup`].
+    Backup,
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::DataDirMove => "data_dir_move",
+            JobKind::Backup => "backup",
+        }
+    }
+}
+
+impl FromStr for JobKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "data_dir_move" => Ok(JobKind::DataDirMove),
+            "backup" => Ok(JobKind::Backup),
+            _ => Err(format!("unknown job kind {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Running => "running",
+            JobState::Succeeded => "succeeded",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl FromStr for JobState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "running" => Ok(JobState::Running),
+            "succeeded" => Ok(JobState::Succeeded),
+            "failed" => Ok(JobState::Failed),
+            "cancelled" => Ok(JobState::Cancelled),
+            _ => Err(format!("unknown job state {s}")),
+        }
+    }
+}
+
+/// A running job, given to the operation so it reports its progress and sees when it's cancelled.
+#[derive(Clone)]
+pub struct JobContext {
+    id: u32,
+    cancel: Arc<AtomicBool>,
+    status: Arc<watch::Sender<Job>>,
+}
+
+impl JobContext {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub(crate) fn cancel_flag(&self) -> &AtomicBool {
+        &self.cancel
+    }
+
+    /// Cheap, it's fine to call it for every block copied.
+    pub fn report(&self, phase: MovePhase, done_bytes: u64, total_bytes: u64) {
+        self.status.send_modify(|job| {
+            job.phase = phase.as_str().to_string();
+            job.done_bytes = done_bytes as i64;
+            job.total_bytes = total_bytes as i64;
+        });
+    }
+
+    pub fn snapshot(&self) -> Job {
+        self.status.borrow().clone()
+    }
+}
+
+pub struct JobManager {
+    db_conn: Arc<Mutex<SqliteConnection>>,
+    running: std::sync::Mutex<HashMap<u32, JobContext>>,
+}
+
+impl JobManager {
+    pub fn new(db_conn: Arc<Mutex<SqliteConnection>>) -> Self {
+        Self {
+            db_conn,
+            running: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a new running job, fails if the vault has one running already.
+    pub async fn start(
+        &self,
+        vault_id: u32,
+        kind: JobKind,
+        target: String,
+    ) -> Result<JobContext, VaultServiceError> {
+        let new_job = NewJob {
+            vault_id: vault_id as i32,
+            kind: kind.as_str().to_string(),
+            target,
+            state: JobState::Running.as_str().to_string(),
+            phase: MovePhase::Copying.as_str().to_string(),
+            created_at: now(),
+        };
+        let job = {
+            let mut guard = self.db_conn.lock().await;
+            JobDao::new(&mut guard)
+                .insert(&new_job, JobState::Running.as_str())
+                .map_err(db_error)?
+                .ok_or(VaultServiceError::JobRunning(vault_id))?
+        };
+        info!(
+            id = job.id,
+            vault_id,
+            kind = kind.as_str(),
+            target = job.target,
+            "Job started"
+        );
+        Ok(self.track(job))
+    }
+
+    /// Picks up the job of `vault_id` left running by a previous daemon, or records a new one.
+    pub async fn resume(
+        &self,
+        vault_id: u32,
+        kind: JobKind,
+        target: String,
+    ) -> Result<JobContext, VaultServiceError> {
+        let running = {
+            let mut guard = self.db_conn.lock().await;
+            JobDao::new(&mut guard)
+                .get_by_state(JobState::Running.as_str())
+                .map_err(db_error)?
+        };
+        match running
+            .into_iter()
+            .find(|j| j.vault_id == vault_id as i32 && j.kind == kind.as_str())
+        {
+            Some(job) => {
+                info!(id = job.id, vault_id, "Job resumed");
+                Ok(self.track(job))
+            }
+            None => self.start(vault_id, kind, target).await,
+        }
+    }
+
+    /// Marks as failed the jobs left running by a previous daemon and not resumed.
+    pub async fn fail_interrupted(&self) {
+        let mut guard = self.db_conn.lock().await;
+        let mut dao = JobDao::new(&mut guard);
+        let jobs = match dao.get_by_state(JobState::Running.as_str()) {
+            Ok(jobs) => jobs,
+            Err(err) => {
+                error!(err = %err, "Cannot get running jobs");
+                return;
+            }
+        };
+        let running = self.running.lock().unwrap();
+        for mut job in jobs
+            .into_iter()
+            .filter(|j| !running.contains_key(&(j.id as u32)))
+        {
+            warn!(
+                id = job.id,
+                target = job.target,
+                "Job interrupted by a restart"
+            );
+            job.state = JobState::Failed.as_str().to_string();
+            job.error = Some(format!(
+                "interrupted by a daemon restart, what's in {} is incomplete",
+                job.target
+            ));
+            job.finished_at = Some(now());
+            if let Err(err) = dao.save(&job) {
+                error!(id = job.id, err = %err, "Cannot save job");
+            }
+        }
+    }
+
+    /// Saves how the job ended and ends its `WatchJob` streams.
+    pub async fn finish(&self, job: &JobContext, res: &Result<(), VaultHandlerError>) {
+        let state = match res {
+            Ok(_) => JobState::Succeeded,
+            Err(VaultHandlerError::Cancelled) => JobState::Cancelled,
+            Err(_) => JobState::Failed,
+        };
+        job.status.send_modify(|j| {
+            j.state = state.as_str().to_string();
+            j.error = res.as_ref().err().map(|err| err.to_string());
+            j.finished_at = Some(now());
+        });
+        let snapshot = job.snapshot();
+        info!(id = snapshot.id, state = snapshot.state, "Job finished");
+        {
+            let mut guard = self.db_conn.lock().await;
+            if let Err(err) = JobDao::new(&mut guard).save(&snapshot) {
+                error!(id = snapshot.id, err = %err, "Cannot save job");
+            }
+        }
+        self.running.lock().unwrap().remove(&job.id);
+    }
+
+    /// The job stops at the next block it copies, see [`JobManager::finish`].
+    pub async fn cancel(&self, id: u32) -> Result<(), VaultServiceError> {
+        if let Some(job) = self.running.lock().unwrap().get(&id) {
+            info!(id, "Cancelling job");
+            job.cancel.store(true, Ordering::Relaxed);
+            return Ok(());
+        }
+        // it exists but already ended
+        self.db_get(id).await?;
+        Err(VaultServiceError::JobNotRunning(id))
+    }
+
+    pub async fn get(&self, id: u32) -> Result<Job, VaultServiceError> {
+        if let Some(job) = self.running.lock().unwrap().get(&id) {
+            return Ok(job.snapshot());
+        }
+        self.db_get(id).await
+    }
+
+    /// Newest first, with the progress of the running ones.
+    pub async fn list(
+        &self,
+        vault_id: Option<u32>,
+        limit: u32,
+    ) -> Result<Vec<Job>, VaultServiceError> {
+        let jobs = {
+            let mut guard = self.db_conn.lock().await;
+            JobDao::new(&mut guard)
+                .list(vault_id.map(|id| id as i32), limit as i64)
+                .map_err(db_error)?
+        };
+        let running = self.running.lock().unwrap();
+        Ok(jobs
+            .into_iter()
+            .map(|job| match running.get(&(job.id as u32)) {
+                Some(running) => running.snapshot(),
+                None => job,
+            })
+            .collect())
+    }
+
+    /// Sees every change of the job until it ends, a job that ended only once.
+    pub async fn watch(&self, id: u32) -> Result<watch::Receiver<Job>, VaultServiceError> {
+        if let Some(job) = self.running.lock().unwrap().get(&id) {
+            let mut rx = job.status.subscribe();
+            rx.mark_changed();
+            return Ok(rx);
+        }
+        let (tx, mut rx) = watch::channel(self.db_get(id).await?);
+        rx.mark_changed();
+        drop(tx);
+        Ok(rx)
+    }
+
+    fn track(&self, job: Job) -> JobContext {
+        let context = JobContext {
+            id: job.id as u32,
+            cancel: Arc::new(AtomicBool::new(false)),
+            status: Arc::new(watch::channel(job).0),
+        };
+        self.running
+            .lock()
+            .unwrap()
+            .insert(context.id, context.clone());
+        context
+    }
+
+    async fn db_get(&self, id: u32) -> Result<Job, VaultServiceError> {
+        let mut guard = self.db_conn.lock().await;
+        JobDao::new(&mut guard)
+            .get(id as i32)
+            .map_err(|err| match err {
+                diesel::result::Error::NotFound => VaultServiceError::JobNotFound(id),
+                err => db_error(err),
+            })
+    }
+}
+
+fn db_error(err: diesel::result::Error) -> VaultServiceError {
+    error!(err = %err, "Database error");
+    VaultServiceError::Database(err.to_string())
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
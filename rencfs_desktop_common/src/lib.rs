@@ -1,30 +1,55 @@
 use std::panic;
 use std::panic::UnwindSafe;
+use std::sync::OnceLock;
 
 use diesel_migrations::{embed_migrations, EmbeddedMigrations};
 use tracing::{error, instrument, Level};
 use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::EnvFilter;
 
 pub mod app_details;
+pub mod config;
+pub mod credentials;
 pub mod dao;
+pub mod data_dir;
 pub mod directories;
+pub mod ipc;
+pub mod jobs;
 pub mod models;
 pub mod persistence;
+#[cfg(target_os = "linux")]
+pub mod platform;
+pub mod protocol;
+pub mod rencfs;
 pub mod schema;
+pub mod supervisor;
+pub mod vault_events;
 pub mod vault_handler;
 pub mod vault_service_error;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
 
+type SetLogLevel = Box<dyn Fn(Level) -> Result<(), String> + Send + Sync>;
+
+static SET_LOG_LEVEL: OnceLock<SetLogLevel> = OnceLock::new();
+
 pub fn log_init(level: Level, prefix: &str) -> WorkerGuard {
     if is_debug() {
         // for dev mode print to stdout
         let (writer, guard) = tracing_appender::non_blocking(std::io::stdout());
-        tracing_subscriber::fmt()
+        let builder = tracing_subscriber::fmt()
             .pretty()
             .with_writer(writer)
-            .with_max_level(level)
-            .init();
+            .with_env_filter(level_filter(level))
+            .with_filter_reloading();
+        let handle = builder.reload_handle();
+        let _ = SET_LOG_LEVEL.set(Box::new(move |level| {
+            handle
+                .reload(level_filter(level))
+                .map_err(|err| err.to_string())
+        }));
+        builder.init();
         guard
     } else {
         // for prod mode print to file
@@ -33,14 +58,33 @@ pub fn log_init(level: Level, prefix: &str) -> WorkerGuard {
             format!("{}.log", prefix),
         );
         let (file_writer, guard) = tracing_appender::non_blocking(file_appender);
-        tracing_subscriber::fmt()
+        let builder = tracing_subscriber::fmt()
             .with_writer(file_writer)
-            .with_max_level(level)
-            .init();
+            .with_env_filter(level_filter(level))
+            .with_filter_reloading();
+        let handle = builder.reload_handle();
+        let _ = SET_LOG_LEVEL.set(Box::new(move |level| {
+            handle
+                .reload(level_filter(level))
+                .map_err(|err| err.to_string())
+        }));
+        builder.init();
         guard
     }
 }
 
+fn level_filter(level: Level) -> EnvFilter {
+    EnvFilter::default().add_directive(LevelFilter::from_level(level).into())
+}
+
+/// Changes the level set by [`log_init`].
+pub fn set_log_level(level: Level) -> Result<(), String> {
+    match SET_LOG_LEVEL.get() {
+        Some(set) => set(level),
+        None => Err("logging is not initialized".to_string()),
+    }
+}
+
 #[instrument(skip(f))]
 pub async fn execute_catch_unwind<F: FnOnce() -> R + UnwindSafe, R>(f: F) {
     let res = panic::catch_unwind(f);
@@ -59,5 +103,5 @@ pub fn is_debug() -> bool {
     {
         return true;
     }
-    return false;
+    false
 }
@@ -1,6 +1,6 @@
 use diesel::prelude::*;
 
-#[derive(Queryable, Selectable, Debug)]
+#[derive(Queryable, Selectable, Debug, Clone)]
 #[diesel(table_name = crate::schema::vaults)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct Vault {
@@ -9,6 +9,12 @@ pub struct Vault {
     pub mount_point: String,
     pub data_dir: String,
     pub locked: i32,
+    pub restart_policy: String,
+    pub credential_provider: String,
+    pub credential_key_id: String,
+    pub ready: i32,
+    pub cipher: String,
+    pub auto_lock_secs: i32,
 }
 
 #[derive(Insertable, Debug)]
@@ -18,4 +24,73 @@ pub struct NewVault {
     pub name: String,
     pub mount_point: String,
     pub data_dir: String,
+    pub restart_policy: String,
+    pub credential_provider: String,
+    pub credential_key_id: String,
+    pub ready: i32,
+    pub cipher: String,
+    pub auto_lock_secs: i32,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = crate::schema::vault_actions)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct VaultAction {
+    pub id: i32,
+    pub vault_id: i32,
+    pub created_at: i64,
+    pub action: String,
+    pub message: String,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::vault_actions)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewVaultAction {
+    pub vault_id: i32,
+    pub created_at: i64,
+    pub action: String,
+    pub message: String,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = crate::schema::data_dir_moves)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct DataDirMove {
+    pub vault_id: i32,
+    pub old_data_dir: String,
+    pub new_data_dir: String,
+    pub remove_old: i32,
+    pub was_unlocked: i32,
+    pub phase: String,
+    pub created_at: i64,
+}
+
+#[derive(Queryable, Selectable, Debug, Clone, PartialEq)]
+#[diesel(table_name = crate::schema::jobs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Job {
+    pub id: i32,
+    pub vault_id: i32,
+    pub kind: String,
+    pub target: String,
+    pub state: String,
+    pub phase: String,
+    pub done_bytes: i64,
+    pub total_bytes: i64,
+    pub error: Option<String>,
+    pub created_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::jobs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewJob {
+    pub vault_id: i32,
+    pub kind: String,
+    pub target: String,
+    pub state: String,
+    pub phase: String,
+    pub created_at: i64,
 }
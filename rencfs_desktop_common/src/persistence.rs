@@ -9,18 +9,24 @@ use tracing::{info, instrument};
 use crate::directories::get_data_dir;
 use crate::{is_debug, MIGRATIONS};
 
-pub fn establish_connection() -> ConnectionResult<SqliteConnection> {
-    let database_url: String;
+/// Path of the SQLite database, `None` in dev mode if `DATABASE_URL` is not set.
+pub fn database_url() -> Option<String> {
     if is_debug() {
-        database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        env::var("DATABASE_URL").ok()
     } else {
-        database_url = get_data_dir()
-            .join("rencfs_desktop.db")
-            .to_str()
-            .unwrap()
-            .to_string()
+        Some(
+            get_data_dir()
+                .join("rencfs_desktop.db")
+                .to_str()
+                .unwrap()
+                .to_string(),
+        )
     }
+}
 
+pub fn establish_connection() -> ConnectionResult<SqliteConnection> {
+    let database_url = database_url()
+        .ok_or_else(|| ConnectionError::BadConnection("DATABASE_URL must be set".to_string()))?;
     let mut conn = SqliteConnection::establish(&database_url)?;
     conn.batch_execute("
             PRAGMA journal_mode = WAL;          -- better write-concurrency
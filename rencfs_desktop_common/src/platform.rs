@@ -0,0 +1,32 @@
+//! Linux process and mount management, without shelling out to `ps`, `kill` or `umount`.
+
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+pub mod memory;
+pub mod mounts;
+pub mod process;
+pub mod pty;
+
+#[derive(Debug, Error)]
+pub enum PlatformError {
+    #[error("no process with pid {0}")]
+    NoSuchProcess(u32),
+    #[error("not allowed to signal process {0}")]
+    PermissionDenied(u32),
+    #[error("cannot read the state of process {pid}: {reason}")]
+    ProcStat { pid: u32, reason: String },
+    #[error("{} is busy", .0.display())]
+    Busy(PathBuf),
+    #[error("{} is not mounted", .0.display())]
+    NotMounted(PathBuf),
+    #[error("cannot unmount {}: {reason}", .mount_point.display())]
+    Unmount {
+        mount_point: PathBuf,
+        reason: String,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
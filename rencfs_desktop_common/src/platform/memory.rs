@@ -0,0 +1,22 @@
+//! Keeping secrets in memory which is never swapped out.
+
+use std::io;
+
+/// Locks the pages of `buf` in RAM, see `mlock(2)`.
+///
+/// It fails if it's over `RLIMIT_MEMLOCK`, which is small for unprivileged users.
+pub fn lock(buf: &[u8]) -> io::Result<()> {
+    if buf.is_empty() {
+        return Ok(());
+    }
+    if unsafe { libc::mlock(buf.as_ptr().cast(), buf.len()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn unlock(buf: &[u8]) {
+    if !buf.is_empty() {
+        unsafe { libc::munlock(buf.as_ptr().cast(), buf.len()) };
+    }
+}
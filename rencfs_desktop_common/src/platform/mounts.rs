@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use tokio::process::Command;
+use tracing::warn;
+
+use super::PlatformError;
+
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+pub const FUSERMOUNT: &str = "fusermount3";
+
+/// An entry from `/proc/self/mountinfo`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MountInfo {
+    /// Unique while mounted, a new mount on the same mount point gets another one.
+    pub mount_id: u32,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    pub source: String,
+}
+
+impl MountInfo {
+    pub fn is_fuse(&self) -> bool {
+        self.fs_type == "fuse" || self.fs_type.starts_with("fuse.")
+    }
+
+    /// If the mount names rencfs as its type or source, other FUSE file systems are not ours.
+    pub fn is_rencfs(&self) -> bool {
+        self.is_fuse() && (self.fs_type == "fuse.rencfs" || self.source == "rencfs")
+    }
+}
+
+pub fn read_mounts() -> io::Result<Vec<MountInfo>> {
+    Ok(fs::read_to_string(MOUNTINFO)?
+        .lines()
+        .filter_map(parse_line)
+        .collect())
+}
+
+/// The FUSE mount at exactly `mount_point`, if any.
+pub fn find_fuse_mount(mount_point: &Path) -> io::Result<Option<MountInfo>> {
+    Ok(read_mounts()?
+        .into_iter()
+        .rev() // the last one is the one on top
+        .find(|m| m.mount_point == mount_point && m.is_fuse()))
+}
+
+pub fn is_fuse_mounted(mount_point: &Path) -> bool {
+    matches!(find_fuse_mount(mount_point), Ok(Some(_)))
+}
+
+/// The ids of the mounts there are now, to tell the ones made later, see [`is_newly_fuse_mounted`].
+pub fn mount_ids() -> io::Result<HashSet<u32>> {
+    Ok(read_mounts()?.into_iter().map(|m| m.mount_id).collect())
+}
+
+/// Like [`is_fuse_mounted`], only for a mount which is not in `before`.
+pub fn is_newly_fuse_mounted(mount_point: &Path, before: &HashSet<u32>) -> bool {
+    matches!(find_fuse_mount(mount_point), Ok(Some(m)) if !before.contains(&m.mount_id))
+}
+
+/// Unmounts with `fusermount3 -u`, fails with [`PlatformError::Busy`] if files are still open.
+pub async fn unmount(mount_point: &Path) -> Result<(), PlatformError> {
+    match fusermount(mount_point, &["-u"]).await {
+        Err(PlatformError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+            warn!("fusermount3 not found, using umount2");
+            umount2(mount_point, 0)
+        }
+        res => res,
+    }
+}
+
+/// Detaches the mount now, the kernel finishes it when its files are closed.
+pub async fn lazy_unmount(mount_point: &Path) -> Result<(), PlatformError> {
+    match fusermount(mount_point, &["-u", "-z"]).await {
+        Err(PlatformError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+            warn!("fusermount3 not found, using umount2");
+            umount2(mount_point, libc::MNT_DETACH)
+        }
+        res => res,
+    }
+}
+
+/// Unmounts, detaching the mount lazily if it's busy.
+pub async fn unmount_or_detach(mount_point: &Path) -> Result<(), PlatformError> {
+    match unmount(mount_point).await {
+        Err(PlatformError::Busy(_)) => {
+            warn!(mount_point = %mount_point.display(), "Mount point is busy, detaching it");
+            lazy_unmount(mount_point).await
+        }
+        res => res,
+    }
+}
+
+// unprivileged users can only unmount FUSE mounts with the setuid fusermount3
+async fn fusermount(mount_point: &Path, args: &[&str]) -> Result<(), PlatformError> {
+    let out = Command::new(FUSERMOUNT)
+        .args(args)
+        .arg(mount_point)
+        .output()
+        .await?;
+    if out.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&out.stderr).trim().to_string();
+    Err(if stderr.contains("busy") {
+        PlatformError::Busy(mount_point.to_path_buf())
+    } else if stderr.contains("not found in") || stderr.contains("Invalid argument") {
+        PlatformError::NotMounted(mount_point.to_path_buf())
+    } else {
+        PlatformError::Unmount {
+            mount_point: mount_point.to_path_buf(),
+            reason: stderr,
+        }
+    })
+}
+
+fn umount2(mount_point: &Path, flags: libc::c_int) -> Result<(), PlatformError> {
+    let path =
+        CString::new(mount_point.as_os_str().as_bytes()).map_err(|_| PlatformError::Unmount {
+            mount_point: mount_point.to_path_buf(),
+            reason: "path contains a nul byte".to_string(),
+        })?;
+    if unsafe { libc::umount2(path.as_ptr(), flags) } == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    Err(match err.raw_os_error() {
+        Some(libc::EBUSY) => PlatformError::Busy(mount_point.to_path_buf()),
+        Some(libc::EINVAL) => PlatformError::NotMounted(mount_point.to_path_buf()),
+        _ => PlatformError::Unmount {
+            mount_point: mount_point.to_path_buf(),
+            reason: err.to_string(),
+        },
+    })
+}
+
+// format is described in `man 5 proc`, section /proc/pid/mountinfo
+// 36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue
+fn parse_line(line: &str) -> Option<MountInfo> {
+    let mut fields = line.split(' ');
+    let mount_id = fields.next()?.parse().ok()?;
+    let mount_point = fields.nth(3)?;
+    // skip optional fields until the separator
+    let mut fields = fields.skip_while(|f| *f != "-").skip(1);
+    let fs_type = fields.next()?;
+    let source = fields.next()?;
+    Some(MountInfo {
+        mount_id,
+        mount_point: PathBuf::from(unescape(mount_point)),
+        fs_type: fs_type.to_string(),
+        source: unescape(source),
+    })
+}
+
+/// Spaces, tabs, newlines and backslashes are escaped as octal, like `\040`.
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let octal: String = chars.by_ref().take(3).collect();
+            match u8::from_str_radix(&octal, 8) {
+                Ok(b) => out.push(b as char),
+                Err(_) => {
+                    out.push(c);
+                    out.push_str(&octal);
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// The bytes an unprivileged user can still write on the filesystem of `path`, see `statvfs(3)`.
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
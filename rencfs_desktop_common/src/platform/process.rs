@@ -0,0 +1,193 @@
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use super::PlatformError;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a process gets to exit after SIGTERM before it's killed.
+pub const TERM_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Term,
+    Kill,
+}
+
+impl Signal {
+    fn as_raw(&self) -> libc::c_int {
+        match self {
+            Signal::Term => libc::SIGTERM,
+            Signal::Kill => libc::SIGKILL,
+        }
+    }
+}
+
+pub fn send_signal(pid: u32, signal: Signal) -> Result<(), PlatformError> {
+    // pid 0 and negative ones would signal whole process groups
+    let raw_pid = match libc::pid_t::try_from(pid) {
+        Ok(raw_pid) if raw_pid > 0 => raw_pid,
+        _ => return Err(PlatformError::NoSuchProcess(pid)),
+    };
+    if unsafe { libc::kill(raw_pid, signal.as_raw()) } == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::ESRCH) => Err(PlatformError::NoSuchProcess(pid)),
+        Some(libc::EPERM) => Err(PlatformError::PermissionDenied(pid)),
+        _ => Err(err.into()),
+    }
+}
+
+/// Process state, the third field of `/proc/<pid>/stat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    Sleeping,
+    DiskSleep,
+    Stopped,
+    TracingStop,
+    Zombie,
+    Dead,
+    Idle,
+    Other(char),
+}
+
+impl From<char> for ProcessState {
+    fn from(c: char) -> Self {
+        match c {
+            'R' => ProcessState::Running,
+            'S' => ProcessState::Sleeping,
+            'D' => ProcessState::DiskSleep,
+            'T' => ProcessState::Stopped,
+            't' => ProcessState::TracingStop,
+            'Z' => ProcessState::Zombie,
+            'X' | 'x' => ProcessState::Dead,
+            'I' => ProcessState::Idle,
+            c => ProcessState::Other(c),
+        }
+    }
+}
+
+/// The fields we need from `/proc/<pid>/stat`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcStat {
+    pub pid: u32,
+    pub comm: String,
+    pub state: ProcessState,
+    pub ppid: u32,
+    /// In clock ticks after boot, tells apart processes which reused a pid.
+    pub start_time: u64,
+}
+
+pub fn read_stat(pid: u32) -> Result<ProcStat, PlatformError> {
+    let content = match fs::read_to_string(format!("/proc/{pid}/stat")) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(PlatformError::NoSuchProcess(pid))
+        }
+        Err(err) => return Err(err.into()),
+    };
+    parse_stat(pid, &content).ok_or_else(|| PlatformError::ProcStat {
+        pid,
+        reason: format!("unexpected format: {content}"),
+    })
+}
+
+/// If the process exists and is not a zombie waiting to be reaped.
+pub fn is_alive(pid: u32) -> bool {
+    matches!(read_stat(pid), Ok(stat) if !matches!(stat.state, ProcessState::Zombie | ProcessState::Dead))
+}
+
+/// Like [`is_alive`], only if `pid` still belongs to the process which started at `start_time`.
+pub fn is_alive_since(pid: u32, start_time: u64) -> bool {
+    matches!(read_stat(pid), Ok(stat) if stat.start_time == start_time
+        && !matches!(stat.state, ProcessState::Zombie | ProcessState::Dead))
+}
+
+/// Bytes the process read and wrote so far, with any syscall, from `/proc/<pid>/io`.
+///
+/// A FUSE server reads every request from `/dev/fuse`, so this grows while its mount is used.
+pub fn io_chars(pid: u32) -> Result<u64, PlatformError> {
+    let content = match fs::read_to_string(format!("/proc/{pid}/io")) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(PlatformError::NoSuchProcess(pid))
+        }
+        Err(err) => return Err(err.into()),
+    };
+    parse_io_chars(&content).ok_or_else(|| PlatformError::ProcStat {
+        pid,
+        reason: format!("unexpected io format: {content}"),
+    })
+}
+
+/// Sends SIGTERM and, if the process is still alive after `grace`, SIGKILL.
+///
+/// Only for processes which are not our children, those stay zombies until they are waited for.
+/// Nothing is sent once `pid` belongs to another process than the one started at `start_time`.
+pub async fn terminate(pid: u32, start_time: u64, grace: Duration) -> Result<(), PlatformError> {
+    if !is_alive_since(pid, start_time) {
+        info!(pid, "Process already gone");
+        return Ok(());
+    }
+    match send_signal(pid, Signal::Term) {
+        Ok(_) => {}
+        Err(PlatformError::NoSuchProcess(_)) => return Ok(()),
+        Err(err) => return Err(err),
+    }
+    let started = Instant::now();
+    // checked once more after the grace, right before SIGKILL
+    loop {
+        if !is_alive_since(pid, start_time) {
+            info!(pid, "Process terminated");
+            return Ok(());
+        }
+        if started.elapsed() >= grace {
+            break;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    warn!(pid, ?grace, "Process didn't terminate in time, killing it");
+    match send_signal(pid, Signal::Kill) {
+        Err(PlatformError::NoSuchProcess(_)) => Ok(()),
+        res => res,
+    }
+}
+
+// format is described in `man 5 proc`, section /proc/pid/stat
+// 1234 (rencfs) S 1 1234 1234 0 -1 4194560 ...
+// comm can contain spaces and parentheses, so it ends at the last `)`
+fn parse_stat(pid: u32, content: &str) -> Option<ProcStat> {
+    let comm_start = content.find('(')?;
+    let comm_end = content.rfind(')')?;
+    let comm = content.get(comm_start + 1..comm_end)?;
+    let mut fields = content.get(comm_end + 1..)?.split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let ppid = fields.next()?.parse().ok()?;
+    // starttime is field 22, the 20th after comm
+    let start_time = fields.nth(17)?.parse().ok()?;
+    Some(ProcStat {
+        pid,
+        comm: comm.to_string(),
+        state: state.into(),
+        ppid,
+        start_time,
+    })
+}
+
+// rchar: 323934931
+// wchar: 323929600
+// syscr: 632687
+// ...
+fn parse_io_chars(content: &str) -> Option<u64> {
+    let field = |name: &str| {
+        content
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.trim().parse::<u64>().ok())
+    };
+    Some(field("rchar:")?.wrapping_add(field("wchar:")?))
+}
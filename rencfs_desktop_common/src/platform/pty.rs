@@ -0,0 +1,79 @@
+//! Pseudo-terminals, to type the vault password to rencfs like a user would.
+//!
+//! rencfs reads the password from its controlling terminal, and the daemon has none to give it.
+//! Writing it there instead of passing it in the environment or the arguments keeps it out of
+//! `/proc/<pid>/environ` and `/proc/<pid>/cmdline`.
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::Stdio;
+
+use tokio::process::Command;
+
+/// The master side is what the daemon writes to, the slave side is the terminal of the child.
+#[derive(Debug)]
+pub struct Pty {
+    master: File,
+    slave: OwnedFd,
+}
+
+impl Pty {
+    pub fn open() -> io::Result<Self> {
+        // not inherited by the other processes spawned meanwhile
+        let master = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+        if master < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = unsafe { File::from_raw_fd(master) };
+        if unsafe { libc::grantpt(master.as_raw_fd()) } != 0
+            || unsafe { libc::unlockpt(master.as_raw_fd()) } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        let mut name = [0 as libc::c_char; 64];
+        let res = unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+        if res != 0 {
+            return Err(io::Error::from_raw_os_error(res));
+        }
+        let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+        let slave = unsafe {
+            libc::open(
+                name.as_ptr(),
+                libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+            )
+        };
+        if slave < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+        Ok(Self { master, slave })
+    }
+
+    /// Makes the pty the stdin and the controlling terminal of `command`, in a session of its own.
+    ///
+    /// SIGHUP is ignored by the child, it would get one when the master side is closed.
+    pub fn attach(&self, command: &mut Command) -> io::Result<()> {
+        command.stdin(Stdio::from(self.slave.try_clone()?));
+        unsafe {
+            command.pre_exec(|| {
+                // stdin is already the slave side here
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                libc::signal(libc::SIGHUP, libc::SIG_IGN);
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Types `line` followed by enter.
+    pub fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        self.master.write_all(line)?;
+        self.master.write_all(b"\n")?;
+        self.master.flush()
+    }
+}
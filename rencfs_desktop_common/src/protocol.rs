@@ -0,0 +1,90 @@
+use thiserror::Error;
+
+/// Version of the daemon API, bump it on changes to `rencfs_desktop.proto` older peers can't handle.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest protocol version of the peer this build can still talk to.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional features the daemon advertises in `DaemonInfo.capabilities`.
+pub mod capabilities {
+    pub const CREATE_VAULT: &str = "create_vault";
+    pub const UPDATE_VAULT: &str = "update_vault";
+    pub const DELETE_VAULT: &str = "delete_vault";
+    pub const WATCH_VAULTS: &str = "watch_vaults";
+    pub const HEALTH: &str = "health";
+    pub const RESTART_POLICY: &str = "restart_policy";
+    pub const VAULT_ACTIONS: &str = "vault_actions";
+    pub const RENCFS_INFO: &str = "rencfs_info";
+    pub const RELOAD_CONFIG: &str = "reload_config";
+    pub const STOP: &str = "stop";
+    pub const CREDENTIAL_PROVIDERS: &str = "credential_providers";
+    pub const UNLOCK_WITH_PASSWORD: &str = "unlock_with_password";
+    pub const CHANGE_PASSWORD: &str = "change_password";
+    pub const INIT_VAULT: &str = "init_vault";
+    pub const CIPHER: &str = "cipher";
+    pub const DATA_DIR_MOVE: &str = "data_dir_move";
+    pub const JOBS: &str = "jobs";
+    pub const AUTO_LOCK: &str = "auto_lock";
+}
+
+/// Capabilities of this daemon build.
+pub const CAPABILITIES: &[&str] = &[
+    capabilities::CREATE_VAULT,
+    capabilities::UPDATE_VAULT,
+    capabilities::DELETE_VAULT,
+    capabilities::WATCH_VAULTS,
+    capabilities::HEALTH,
+    capabilities::RESTART_POLICY,
+    capabilities::VAULT_ACTIONS,
+    capabilities::RENCFS_INFO,
+    capabilities::RELOAD_CONFIG,
+    capabilities::STOP,
+    capabilities::CREDENTIAL_PROVIDERS,
+    capabilities::UNLOCK_WITH_PASSWORD,
+    capabilities::CHANGE_PASSWORD,
+    capabilities::INIT_VAULT,
+    capabilities::CIPHER,
+    capabilities::DATA_DIR_MOVE,
+    capabilities::JOBS,
+    capabilities::AUTO_LOCK,
+];
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum ProtocolError {
+    #[error("daemon {daemon_version} is too old, it speaks protocol {protocol_version} but at least {MIN_PROTOCOL_VERSION} is needed, please upgrade the daemon")]
+    DaemonTooOld {
+        daemon_version: String,
+        protocol_version: u32,
+    },
+    #[error("this app is too old for daemon {daemon_version}, it needs protocol {min_protocol_version} but the app speaks {PROTOCOL_VERSION}, please upgrade the app")]
+    ClientTooOld {
+        daemon_version: String,
+        min_protocol_version: u32,
+    },
+}
+
+/// Checks if a client of this build can talk to the daemon that replied with these versions.
+pub fn check_daemon(
+    daemon_version: &str,
+    protocol_version: u32,
+    min_protocol_version: u32,
+) -> Result<(), ProtocolError> {
+    if protocol_version < MIN_PROTOCOL_VERSION {
+        return Err(ProtocolError::DaemonTooOld {
+            daemon_version: daemon_version.to_string(),
+            protocol_version,
+        });
+    }
+    if min_protocol_version > PROTOCOL_VERSION {
+        return Err(ProtocolError::ClientTooOld {
+            daemon_version: daemon_version.to_string(),
+            min_protocol_version,
+        });
+    }
+    Ok(())
+}
+
+/// Checks if the daemon of this build can serve a client speaking `protocol_version`.
+pub fn is_client_supported(protocol_version: u32) -> bool {
+    protocol_version >= MIN_PROTOCOL_VERSION
+}
@@ -0,0 +1,292 @@
+//! Finding the rencfs binary and checking its version, and the key files it keeps in the data dir.
+
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use tokio::process::Command;
+use tracing::{info, instrument, warn};
+use zeroize::Zeroizing;
+
+/// Path of the rencfs binary, used if `rencfs_path` is not set in the config.
+pub const RENCFS_PATH_ENV: &str = "RENCFS_DESKTOP_RENCFS_PATH";
+const BIN_NAME: &str = "rencfs";
+/// `--version` should return right away, something else is at that path if it hangs.
+const VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Oldest rencfs version that can be used, inclusive.
+pub const MIN_VERSION: Version = Version(0, 13, 0);
+/// First rencfs version that can't be used anymore, exclusive.
+pub const MAX_VERSION: Version = Version(1, 0, 0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(pub u64, pub u64, pub u64);
+
+impl Version {
+    /// Parses `x.y.z`, anything after the patch number like `-rc.1` is ignored.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.trim().splitn(3, '.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let patch = parts.next()?;
+        let patch = patch
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(patch, |end| &patch[..end])
+            .parse()
+            .ok()?;
+        Some(Version(major, minor, patch))
+    }
+
+    pub fn is_supported(&self) -> bool {
+        (MIN_VERSION..MAX_VERSION).contains(self)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+/// The versions of rencfs that can be used, like `>=0.13.0, <1.0.0`.
+pub fn supported_versions() -> String {
+    format!(">={MIN_VERSION}, <{MAX_VERSION}")
+}
+
+/// If rencfs exited because the password can't decrypt the vault, from what it wrote to stderr.
+///
+/// It has no exit code of its own for it, it logs `FsError::InvalidPassword` and exits, or prints
+/// `Invalid old password` for `passwd`.
+pub fn is_wrong_password(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    ["invalid password", "invalid old password", "wrong password"]
+        .iter()
+        .any(|s| stderr.contains(s))
+}
+
+/// The files rencfs keeps the encrypted vault key in, under the data dir.
+const KEY_DIR: &str = "security";
+
+/// If rencfs created the key files of a vault in `data_dir`.
+pub fn has_key(data_dir: &Path) -> bool {
+    fs::read_dir(data_dir.join(KEY_DIR)).is_ok_and(|mut entries| entries.next().is_some())
+}
+
+/// A copy of the key files of a vault, to put them back if changing the password fails halfway.
+pub struct KeySnapshot {
+    dir: PathBuf,
+    files: Vec<(PathBuf, Zeroizing<Vec<u8>>)>,
+}
+
+impl KeySnapshot {
+    pub fn take(data_dir: &Path) -> io::Result<Self> {
+        let dir = data_dir.join(KEY_DIR);
+        let mut files = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push((entry.path(), Zeroizing::new(fs::read(entry.path())?)));
+            }
+        }
+        Ok(Self { dir, files })
+    }
+
+    /// Writes the files back as they were, each through a temp file so it's never half written.
+    pub fn restore(&self) -> io::Result<()> {
+        for (path, content) in &self.files {
+            let tmp = path.with_extension("restore");
+            let mut file = File::create(&tmp)?;
+            file.write_all(content)?;
+            file.sync_all()?;
+            fs::rename(&tmp, path)?;
+        }
+        File::open(&self.dir)?.sync_all()
+    }
+}
+
+/// The AEAD cipher a vault is encrypted with, it can't change without re-encrypting all of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Cipher {
+    #[default]
+    ChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+impl Cipher {
+    /// How it's stored in `vaults.cipher`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Cipher::ChaCha20Poly1305 => "chacha20_poly1305",
+            Cipher::Aes256Gcm => "aes256_gcm",
+        }
+    }
+
+    /// The value of `--cipher` for rencfs.
+    pub fn arg(&self) -> &'static str {
+        match self {
+            Cipher::ChaCha20Poly1305 => "ChaCha20Poly1305",
+            Cipher::Aes256Gcm => "Aes256Gcm",
+        }
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chacha20_poly1305" => Ok(Cipher::ChaCha20Poly1305),
+            "aes256_gcm" => Ok(Cipher::Aes256Gcm),
+            _ => Err(format!("unknown cipher {s}")),
+        }
+    }
+}
+
+/// Where the binary was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// `rencfs_path` from the config file.
+    Config,
+    /// [`RENCFS_PATH_ENV`].
+    Env,
+    Bundled,
+    Path,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Ok(Version),
+    Missing,
+    /// The binary exists but `--version` failed or printed something we don't understand.
+    UnknownVersion,
+    Unsupported(Version),
+}
+
+/// The rencfs binary the daemon runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RencfsBinary {
+    /// `None` if it was not found anywhere.
+    pub path: Option<PathBuf>,
+    pub source: Option<Source>,
+    pub status: Status,
+}
+
+impl RencfsBinary {
+    /// Looks for the binary, in order, at `configured`, [`RENCFS_PATH_ENV`], next to the current
+    /// executable and in `PATH`, then checks its version.
+    ///
+    /// An explicit path from the config or the env is used even if it doesn't exist, so a typo
+    /// shows up as a missing binary instead of silently running another one.
+    #[instrument]
+    pub async fn resolve(configured: Option<&Path>) -> Self {
+        let Some((path, source)) = find(configured) else {
+            warn!("rencfs not found");
+            return Self {
+                path: None,
+                source: None,
+                status: Status::Missing,
+            };
+        };
+        let status = if path.is_file() {
+            match version(&path).await {
+                Some(version) if version.is_supported() => Status::Ok(version),
+                Some(version) => Status::Unsupported(version),
+                None => Status::UnknownVersion,
+            }
+        } else {
+            Status::Missing
+        };
+        match &status {
+            Status::Ok(version) => info!(path = %path.display(), ?source, %version, "Using rencfs"),
+            status => warn!(path = %path.display(), ?source, ?status, "rencfs cannot be used"),
+        }
+        Self {
+            path: Some(path),
+            source: Some(source),
+            status,
+        }
+    }
+
+    pub fn version(&self) -> Option<Version> {
+        match self.status {
+            Status::Ok(version) | Status::Unsupported(version) => Some(version),
+            Status::Missing | Status::UnknownVersion => None,
+        }
+    }
+
+    /// What to show where the path is expected, when there is none.
+    pub fn display_path(&self) -> String {
+        self.path
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| "$PATH".to_string())
+    }
+}
+
+fn find(configured: Option<&Path>) -> Option<(PathBuf, Source)> {
+    if let Some(path) = configured {
+        return Some((path.to_path_buf(), Source::Config));
+    }
+    if let Some(path) = env::var_os(RENCFS_PATH_ENV).filter(|p| !p.is_empty()) {
+        return Some((PathBuf::from(path), Source::Env));
+    }
+    let bundled = env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join(BIN_NAME)))
+        .filter(|p| is_executable(p));
+    if let Some(path) = bundled {
+        return Some((path, Source::Bundled));
+    }
+    find_in_path(BIN_NAME).map(|path| (path, Source::Path))
+}
+
+/// First executable named `name` in the dirs from `PATH`.
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|p| is_executable(p))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Version reported by `rencfs --version`, `None` if it cannot be run.
+async fn version(path: &Path) -> Option<Version> {
+    let output = Command::new(path)
+        .arg("--version")
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(VERSION_TIMEOUT, output).await {
+        Ok(Ok(output)) if output.status.success() => output,
+        Ok(Ok(output)) => {
+            warn!(status = %output.status, "Cannot get rencfs version");
+            return None;
+        }
+        Ok(Err(err)) => {
+            warn!(err = %err, "Cannot run rencfs");
+            return None;
+        }
+        Err(_) => {
+            warn!(timeout = ?VERSION_TIMEOUT, "rencfs --version didn't finish in time");
+            return None;
+        }
+    };
+    let version = String::from_utf8_lossy(&output.stdout);
+    let version = version.trim();
+    // `rencfs 0.1.0`
+    Version::parse(version.strip_prefix(BIN_NAME).unwrap_or(version))
+}
@@ -1,5 +1,43 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    data_dir_moves (vault_id) {
+        vault_id -> Integer,
+        old_data_dir -> Text,
+        new_data_dir -> Text,
+        remove_old -> Integer,
+        was_unlocked -> Integer,
+        phase -> Text,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    jobs (id) {
+        id -> Integer,
+        vault_id -> Integer,
+        kind -> Text,
+        target -> Text,
+        state -> Text,
+        phase -> Text,
+        done_bytes -> BigInt,
+        total_bytes -> BigInt,
+        error -> Nullable<Text>,
+        created_at -> BigInt,
+        finished_at -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    vault_actions (id) {
+        id -> Integer,
+        vault_id -> Integer,
+        created_at -> BigInt,
+        action -> Text,
+        message -> Text,
+    }
+}
+
 diesel::table! {
     vaults (id) {
         id -> Integer,
@@ -7,5 +45,17 @@ diesel::table! {
         mount_point -> Text,
         data_dir -> Text,
         locked -> Integer,
+        restart_policy -> Text,
+        credential_provider -> Text,
+        credential_key_id -> Text,
+        ready -> Integer,
+        cipher -> Text,
+        auto_lock_secs -> Integer,
     }
 }
+
+diesel::joinable!(data_dir_moves -> vaults (vault_id));
+diesel::joinable!(jobs -> vaults (vault_id));
+diesel::joinable!(vault_actions -> vaults (vault_id));
+
+diesel::allow_tables_to_appear_in_same_query!(data_dir_moves, jobs, vault_actions, vaults,);
@@ -0,0 +1,363 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use diesel::{ExpressionMethods, SqliteConnection};
+use tokio::process::Child;
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::dao::{VaultActionDao, VaultDao};
+use crate::models::NewVaultAction;
+use crate::vault_events::{VaultEvent, VaultEventHub};
+
+/// How many lines from the end of the rencfs stderr are kept when it exits.
+pub(crate) const STDERR_TAIL_LINES: usize = 20;
+const STDERR_TAIL_BYTES: u64 = 8 * 1024;
+/// How often a process adopted from a previous daemon is checked, it can't be waited for.
+#[cfg(target_os = "linux")]
+const ADOPTED_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// What to do when the rencfs process of a vault exits on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
+impl RestartPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RestartPolicy::Never => "never",
+            RestartPolicy::OnFailure => "on_failure",
+            RestartPolicy::Always => "always",
+        }
+    }
+
+    /// `exit_code` is `None` if the process was killed by a signal, that counts as a failure.
+    pub fn should_restart(&self, exit_code: Option<i32>) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => exit_code != Some(0),
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+impl FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(RestartPolicy::Never),
+            "on_failure" => Ok(RestartPolicy::OnFailure),
+            "always" => Ok(RestartPolicy::Always),
+            _ => Err(format!("unknown restart policy {s}")),
+        }
+    }
+}
+
+/// Kinds of the actions recorded in `vault_actions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VaultActionKind {
+    Exited,
+    MountCleaned,
+    Restarting,
+    Restarted,
+    RestartFailed,
+    GaveUp,
+    AutoLocked,
+    MountPointBlocked,
+}
+
+impl VaultActionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VaultActionKind::Exited => "exited",
+            VaultActionKind::MountCleaned => "mount_cleaned",
+            VaultActionKind::Restarting => "restarting",
+            VaultActionKind::Restarted => "restarted",
+            VaultActionKind::RestartFailed => "restart_failed",
+            VaultActionKind::GaveUp => "gave_up",
+            VaultActionKind::AutoLocked => "auto_locked",
+            VaultActionKind::MountPointBlocked => "mount_point_blocked",
+        }
+    }
+}
+
+/// Saves what happened to the vault, errors are only logged.
+pub async fn record_action(
+    db_conn: &Arc<Mutex<SqliteConnection>>,
+    vault_id: u32,
+    kind: VaultActionKind,
+    message: String,
+) {
+    info!(
+        vault_id,
+        action = kind.as_str(),
+        message,
+        "Recording vault action"
+    );
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    let mut guard = db_conn.lock().await;
+    let res = VaultActionDao::new(&mut guard).insert(&NewVaultAction {
+        vault_id: vault_id as i32,
+        created_at,
+        action: kind.as_str().to_string(),
+        message,
+    });
+    if let Err(err) = res {
+        error!(vault_id, err = %err, "Cannot record vault action");
+    }
+}
+
+/// Watches the rencfs process of an unlocked vault.
+///
+/// If it exits on its own it records why, cleans the mount, marks the vault as locked and sends
+/// [`VaultEvent::ProcessDied`], the restart is up to whoever listens to that.
+///
+/// `stderr_start` is the size of [`stderr_path`] when the process started, the log is appended
+/// to by every run.
+pub struct Supervisor {
+    pid: u32,
+    stop: Option<oneshot::Sender<()>>,
+    task: JoinHandle<io::Result<()>>,
+}
+
+impl Supervisor {
+    pub fn spawn(
+        id: u32,
+        mut child: Child,
+        stderr_start: u64,
+        db_conn: Arc<Mutex<SqliteConnection>>,
+        events: VaultEventHub,
+    ) -> Self {
+        let pid = child.id().unwrap_or_default();
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let task = tokio::spawn(
+            async move {
+                tokio::select! {
+                    status = child.wait() => {
+                        let (exit_code, message) = exit_message(status);
+                        on_exit(id, exit_code, message, stderr_start, &db_conn, &events).await;
+                        Ok(())
+                    }
+                    _ = stop_rx => stop(&mut child).await,
+                }
+            }
+            .instrument(info_span!("supervisor", id, pid)),
+        );
+
+        Self {
+            pid,
+            stop: Some(stop_tx),
+            task,
+        }
+    }
+
+    /// Watches `pid`, a rencfs process left running by a previous daemon.
+    ///
+    /// It's not a child of this daemon, so it's polled until it's gone and its exit code is
+    /// unknown. `start_time` from `/proc/<pid>/stat` tells it apart from a process which got the
+    /// same pid after it exited, that one is never signaled.
+    #[cfg(target_os = "linux")]
+    pub fn adopt(
+        id: u32,
+        pid: u32,
+        start_time: u64,
+        db_conn: Arc<Mutex<SqliteConnection>>,
+        events: VaultEventHub,
+    ) -> Self {
+        use crate::platform::process::{is_alive_since, terminate, TERM_GRACE};
+
+        let stderr_start = std::fs::metadata(stderr_path(id))
+            .map(|m| m.len())
+            .unwrap_or_default();
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let task = tokio::spawn(
+            async move {
+                let gone = async {
+                    while is_alive_since(pid, start_time) {
+                        tokio::time::sleep(ADOPTED_POLL_INTERVAL).await;
+                    }
+                };
+                tokio::select! {
+                    _ = gone => {
+                        warn!("Adopted rencfs exited");
+                        let message = "rencfs exited, its exit code is unknown".to_string();
+                        on_exit(id, None, message, stderr_start, &db_conn, &events).await;
+                        Ok(())
+                    }
+                    _ = stop_rx => terminate(pid, start_time, TERM_GRACE).await.map_err(io::Error::other),
+                }
+            }
+            .instrument(info_span!("supervisor", id, pid)),
+        );
+
+        Self {
+            pid,
+            stop: Some(stop_tx),
+            task,
+        }
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// If the process exited and the cleanup after it is done.
+    pub fn has_exited(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Stops the process, without the cleanup done when it exits on its own.
+    pub async fn kill(&mut self) -> io::Result<()> {
+        self.start_kill();
+        (&mut self.task).await.map_err(io::Error::other)?
+    }
+
+    pub fn start_kill(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            // the task already ended if it's closed
+            let _ = stop.send(());
+        }
+    }
+}
+
+/// SIGTERM lets rencfs unmount the vault itself, it's killed if it doesn't exit in time.
+#[cfg(target_os = "linux")]
+async fn stop(child: &mut Child) -> io::Result<()> {
+    use crate::platform::process::{send_signal, Signal, TERM_GRACE};
+
+    let Some(pid) = child.id() else {
+        // already reaped
+        return Ok(());
+    };
+    if let Err(err) = send_signal(pid, Signal::Term) {
+        warn!(err = %err, "Cannot send SIGTERM");
+    } else if let Ok(status) = tokio::time::timeout(TERM_GRACE, child.wait()).await {
+        info!(status = ?status.ok(), "rencfs stopped");
+        return Ok(());
+    }
+    warn!("rencfs didn't stop in time, killing it");
+    child.kill().await
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn stop(child: &mut Child) -> io::Result<()> {
+    child.kill().await
+}
+
+/// The exit code, `None` if it was killed by a signal, and what to record about it.
+fn exit_message(status: io::Result<ExitStatus>) -> (Option<i32>, String) {
+    let exit_code = match status {
+        Ok(status) => {
+            warn!(%status, "rencfs exited");
+            status.code()
+        }
+        Err(err) => {
+            error!(err = %err, "Cannot wait for rencfs");
+            None
+        }
+    };
+    let message = match exit_code {
+        Some(code) => format!("rencfs exited with code {code}"),
+        None => "rencfs was killed".to_string(),
+    };
+    (exit_code, message)
+}
+
+async fn on_exit(
+    id: u32,
+    exit_code: Option<i32>,
+    message: String,
+    stderr_start: u64,
+    db_conn: &Arc<Mutex<SqliteConnection>>,
+    events: &VaultEventHub,
+) {
+    let stderr_tail = stderr_tail(id, stderr_start).unwrap_or_else(|err| {
+        warn!(err = %err, "Cannot read rencfs stderr");
+        String::new()
+    });
+    let message = if stderr_tail.is_empty() {
+        message
+    } else {
+        format!("{message}:\n{stderr_tail}")
+    };
+    record_action(db_conn, id, VaultActionKind::Exited, message).await;
+
+    let vault = {
+        let mut guard = db_conn.lock().await;
+        let mut dao = VaultDao::new(&mut guard);
+        let vault = dao.get(id as i32);
+        if vault.is_ok() {
+            use crate::schema::vaults::locked;
+            if let Err(err) = dao.update(id as i32, locked.eq(1)) {
+                error!(err = %err, "Cannot update vault state");
+            }
+        }
+        vault
+    };
+    match vault {
+        #[cfg(target_os = "linux")]
+        Ok(vault) => {
+            let mount_point = std::path::Path::new(&vault.mount_point);
+            // left as "Transport endpoint is not connected"
+            if crate::platform::mounts::is_fuse_mounted(mount_point) {
+                match crate::platform::mounts::lazy_unmount(mount_point).await {
+                    Ok(_) => {
+                        record_action(
+                            db_conn,
+                            id,
+                            VaultActionKind::MountCleaned,
+                            format!("unmounted {}", vault.mount_point),
+                        )
+                        .await
+                    }
+                    Err(err) => error!(err = %err, "Cannot clean mount"),
+                }
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        Ok(_) => {}
+        Err(err) => warn!(err = %err, "Cannot get vault"),
+    }
+
+    events.publish(VaultEvent::ProcessDied {
+        id,
+        exit_code,
+        stderr_tail,
+    });
+}
+
+/// Where rencfs writes its stderr.
+pub fn stderr_path(id: u32) -> PathBuf {
+    crate::config::current()
+        .rencfs_logs_dir
+        .join(format!("vault_{id}.err"))
+}
+
+/// The last lines rencfs wrote to its stderr log after `start`.
+pub fn stderr_tail(id: u32, start: u64) -> io::Result<String> {
+    let mut file = File::open(stderr_path(id))?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(
+        len.saturating_sub(STDERR_TAIL_BYTES).max(start),
+    ))?;
+    let mut buf = vec![];
+    file.read_to_end(&mut buf)?;
+    let text = String::from_utf8_lossy(&buf);
+    let lines: Vec<&str> = text.lines().collect();
+    Ok(lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n"))
+}
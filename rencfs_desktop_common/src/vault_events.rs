@@ -0,0 +1,100 @@
+use tokio::sync::broadcast;
+use tracing::debug;
+
+use crate::data_dir::MovePhase;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VaultEvent {
+    Locked {
+        id: u32,
+    },
+    Unlocked {
+        id: u32,
+    },
+    MountPointChanged {
+        id: u32,
+        mount_point: String,
+    },
+    DataDirChanged {
+        id: u32,
+        data_dir: String,
+    },
+    // progress of moving the files to the new data dir, DataDirChanged comes when it's done
+    DataDirMoving {
+        id: u32,
+        phase: MovePhase,
+        done_bytes: u64,
+        total_bytes: u64,
+    },
+    // with the last lines rencfs wrote to stderr
+    ProcessDied {
+        id: u32,
+        exit_code: Option<i32>,
+        stderr_tail: String,
+    },
+    Error {
+        id: u32,
+        message: String,
+    },
+    Created {
+        id: u32,
+    },
+    Renamed {
+        id: u32,
+        name: String,
+    },
+    Deleted {
+        id: u32,
+    },
+    // answered with `PromptProvider::answer`
+    PasswordRequested {
+        id: u32,
+        request_id: u64,
+    },
+    // the vault is idle and gets locked in `seconds_left`, unless it's kept unlocked
+    AutoLockWarning {
+        id: u32,
+        seconds_left: u32,
+    },
+}
+
+impl VaultEvent {
+    pub fn id(&self) -> u32 {
+        match self {
+            VaultEvent::Locked { id }
+            | VaultEvent::Unlocked { id }
+            | VaultEvent::MountPointChanged { id, .. }
+            | VaultEvent::DataDirChanged { id, .. }
+            | VaultEvent::DataDirMoving { id, .. }
+            | VaultEvent::ProcessDied { id, .. }
+            | VaultEvent::Error { id, .. }
+            | VaultEvent::Created { id }
+            | VaultEvent::Renamed { id, .. }
+            | VaultEvent::Deleted { id }
+            | VaultEvent::PasswordRequested { id, .. }
+            | VaultEvent::AutoLockWarning { id, .. } => *id,
+        }
+    }
+}
+
+/// Fans out vault state changes to all subscribers, like the `WatchVaults` streams.
+///
+/// Slow subscribers miss the oldest events once they fall more than `capacity` events behind.
+#[derive(Clone)]
+pub struct VaultEventHub(broadcast::Sender<VaultEvent>);
+
+impl VaultEventHub {
+    pub fn new(capacity: usize) -> Self {
+        Self(broadcast::channel(capacity).0)
+    }
+
+    pub fn publish(&self, event: VaultEvent) {
+        debug!(?event, "Publishing vault event");
+        // it's fine if nobody is listening
+        let _ = self.0.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<VaultEvent> {
+        self.0.subscribe()
+    }
+}
@@ -1,19 +1,32 @@
 use std::fs::OpenOptions;
-use std::process;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use diesel::{QueryResult, SqliteConnection};
 use serde::{Deserialize, Serialize};
-use sysinfo::{Pid, ProcessesToUpdate, ProcessStatus, System};
 use thiserror::Error;
-use tokio::process::{Child, Command};
-use tokio::sync::Mutex;
+use tokio::process::Command;
+use tokio::sync::{Mutex, RwLock};
 use tracing::{error, info, instrument, warn};
 
-use crate::dao::VaultDao;
-use crate::directories::get_logs_dir;
+use crate::config;
+use crate::credentials::{self, CredentialError, Credentials, Password};
+use crate::dao::{DataDirMoveDao, VaultDao};
+use crate::data_dir::{
+    check_empty_data_dir, clear_dir, copy_verified, is_inside, MovePhase, MoveState,
+};
+use crate::jobs::JobContext;
+use crate::models::{DataDirMove, Vault};
+#[cfg(target_os = "linux")]
+use crate::platform::PlatformError;
+use crate::rencfs::{Cipher, KeySnapshot, RencfsBinary, Status};
+use crate::supervisor::{stderr_path, stderr_tail, Supervisor, STDERR_TAIL_LINES};
+use crate::vault_events::{VaultEvent, VaultEventHub};
 
-#[derive(Debug, Error, Serialize, Deserialize, Clone)]
+#[derive(Debug, Error, Serialize, Deserialize, Clone, PartialEq)]
 pub enum VaultHandlerError {
     #[error("cannot lock vault")]
     CannotLockVault,
@@ -23,201 +36,415 @@ pub enum VaultHandlerError {
     CannotChangeMountPoint,
     #[error("cannot change data dir")]
     CannotChangeDataDir,
+    #[error("vault {0} not found")]
+    VaultNotFound(u32),
+    #[error("mount point {0} is busy")]
+    MountPointBusy(String),
+    #[error("something else is mounted on {0}")]
+    MountPointMounted(String),
+    #[error("rencfs binary not found at {0}")]
+    RencfsBinaryMissing(String),
+    #[error("rencfs {0} is not supported, {supported} is needed", supported = crate::rencfs::supported_versions())]
+    RencfsUnsupported(String),
+    #[error("wrong password")]
+    WrongPassword,
+    #[error("rencfs exited{}{}", .0.map(|c| format!(" with code {c}")).unwrap_or_default(),
+        if .1.is_empty() { String::new() } else { format!(": {}", .1) })]
+    ProcessExited(Option<i32>, String),
+    #[error("rencfs did not mount the vault in {0}s")]
+    MountTimeout(u64),
+    #[error("the password was not provided")]
+    PasswordNotProvided,
+    #[error("cannot get the password: {0}")]
+    CredentialUnavailable(String),
+    #[error("the vault is unlocked")]
+    VaultUnlocked,
+    #[error("cannot change password")]
+    CannotChangePassword,
+    #[error("data dir {0} is not empty")]
+    DataDirNotEmpty(String),
+    #[error("cannot write to data dir {0}")]
+    DataDirNotWritable(String),
+    #[error("cannot initialize vault")]
+    CannotInitVault,
+    #[error("not enough space in {0}")]
+    NoSpace(String),
+    #[error("{0} is inside the data dir of the vault")]
+    DataDirInsideCurrent(String),
+    #[error("cancelled")]
+    Cancelled,
+    #[error("cannot back up vault")]
+    CannotBackUpVault,
 }
 
+impl From<CredentialError> for VaultHandlerError {
+    fn from(err: CredentialError) -> Self {
+        match err {
+            CredentialError::NotProvided => VaultHandlerError::PasswordNotProvided,
+            err => VaultHandlerError::CredentialUnavailable(err.to_string()),
+        }
+    }
+}
+
+const MOUNT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long the vault has to lock when initializing it failed.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Deriving the keys from both passwords takes a few seconds, more on slow machines.
+const PASSWD_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often the progress of a data dir move is published.
+const MOVE_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
 pub struct VaultHandler {
     id: u32,
-    child: Option<Child>,
+    /// The rencfs process serving the vault while it's unlocked.
+    process: Option<Supervisor>,
     db_conn: Arc<Mutex<SqliteConnection>>,
+    events: VaultEventHub,
+    rencfs: Arc<RwLock<RencfsBinary>>,
+    credentials: Arc<Credentials>,
 }
 
 impl VaultHandler {
-    pub fn new(id: u32, db_conn: Arc<Mutex<SqliteConnection>>) -> Self {
+    pub fn new(
+        id: u32,
+        db_conn: Arc<Mutex<SqliteConnection>>,
+        events: VaultEventHub,
+        rencfs: Arc<RwLock<RencfsBinary>>,
+        credentials: Arc<Credentials>,
+    ) -> Self {
+        Self {
+            id,
+            process: None,
+            db_conn,
+            events,
+            rencfs,
+            credentials,
+        }
+    }
+
+    /// Handler for a vault already unlocked by the rencfs process `pid`, started by a previous
+    /// daemon run at `start_time`, see [`Supervisor::adopt`].
+    #[cfg(target_os = "linux")]
+    pub fn adopt(
+        id: u32,
+        pid: u32,
+        start_time: u64,
+        db_conn: Arc<Mutex<SqliteConnection>>,
+        events: VaultEventHub,
+        rencfs: Arc<RwLock<RencfsBinary>>,
+        credentials: Arc<Credentials>,
+    ) -> Self {
         Self {
             id,
-            child: None,
+            process: Some(Supervisor::adopt(
+                id,
+                pid,
+                start_time,
+                db_conn.clone(),
+                events.clone(),
+            )),
             db_conn,
+            events,
+            rencfs,
+            credentials,
+        }
+    }
+
+    /// The rencfs process serving the vault, if it's unlocked.
+    pub fn pid(&mut self) -> Option<u32> {
+        if !self.is_running() {
+            return None;
         }
+        self.process.as_ref().map(Supervisor::pid)
     }
 
     #[instrument(skip(self), fields(self.id), err)]
     pub async fn lock(&mut self, mount_point: Option<String>) -> Result<(), VaultHandlerError> {
         info!("");
 
+        // the row stays unlocked until rencfs is gone and so is its mount
+        #[cfg(target_os = "linux")]
         {
-            let mut guard = self.db_conn.lock().await;
-            let mut dao = VaultDao::new(&mut *guard);
-            match self.db_update_locked(true, &mut dao).await {
+            let was_running = self.is_running();
+            if was_running {
+                let mut child = self.process.take().unwrap();
+                info!("VaultHandler stopping child process to lock the vault");
+                if let Err(err) = child.kill().await {
+                    error!(err = %err, "Error stopping child process");
+                    self.process = Some(child);
+                    return Err(self.publish_error(VaultHandlerError::CannotLockVault));
+                }
+            } else {
+                info!("VaultHandler already locked");
+            }
+
+            let mount_point = if let Some(mount_point) = mount_point {
+                mount_point
+            } else {
+                self.db_get_vault()
+                    .await
+                    .map_err(|err| self.db_error(err, VaultHandlerError::CannotLockVault))?
+                    .mount_point
+            };
+            // rencfs unmounts on SIGTERM, this is for when it had to be killed, or when unmounting
+            // failed the last time
+            let path = std::path::Path::new(&mount_point);
+            let res = match crate::platform::mounts::find_fuse_mount(path) {
+                Ok(Some(mount)) if was_running || mount.is_rencfs() => {
+                    crate::platform::mounts::unmount(path).await
+                }
+                _ => Ok(()),
+            };
+            match res {
                 Ok(_) => {}
+                Err(PlatformError::NotMounted(_)) => {}
+                Err(PlatformError::Busy(_)) => {
+                    error!(mount_point, "Mount point is busy");
+                    return Err(self.publish_error(VaultHandlerError::MountPointBusy(mount_point)));
+                }
                 Err(err) => {
-                    error!(err = %err, "Cannot update vault state");
-                    return Err(VaultHandlerError::CannotLockVault.into());
+                    error!(mount_point, err = %err, "Cannot unmount");
+                    return Err(self.publish_error(VaultHandlerError::CannotLockVault));
                 }
             }
         }
 
-        #[cfg(target_os = "linux")]
         {
-            if self.child.is_none() {
-                info!("VaultHandler already locked");
-                return Ok(());
+            let mut guard = self.db_conn.lock().await;
+            let mut dao = VaultDao::new(&mut guard);
+            if let Err(err) = self.db_update_locked(true, &mut dao).await {
+                error!(err = %err, "Cannot update vault state");
+                return Err(self.publish_error(VaultHandlerError::CannotLockVault));
             }
-            info!("VaultHandler killing child process to lock the vault");
-            if let Err(err) = self.child.take().unwrap().kill().await {
-                error!(err = %err, "Error killing child process");
-                return Err(VaultHandlerError::CannotLockVault.into());
+        }
+        self.events.publish(VaultEvent::Locked { id: self.id });
+        Ok(())
+    }
+
+    /// Locks the vault, lazily unmounting it if the mount point is busy or locking takes longer
+    /// than `timeout`.
+    #[instrument(skip(self), fields(self.id), err)]
+    pub async fn force_lock(&mut self, timeout: Duration) -> Result<(), VaultHandlerError> {
+        let mount_point = match tokio::time::timeout(timeout, self.lock(None)).await {
+            Ok(Err(VaultHandlerError::MountPointBusy(mount_point))) => mount_point,
+            Ok(res) => return res,
+            Err(_) => {
+                warn!(?timeout, "Timed out locking the vault");
+                self.db_get_vault()
+                    .await
+                    .map_err(|err| self.db_error(err, VaultHandlerError::CannotLockVault))?
+                    .mount_point
             }
+        };
+        if let Some(mut child) = self.process.take() {
+            child.start_kill();
+        }
 
-            // for some reason of we use 'kill' method the child process doesn't receive the SIGKILL signal
-            // for that case we use `umount` command
-            // TODO: umount for windows
-            if cfg!(any(target_os = "linux", target_os = "macos")) {
-                let mount_point = if let Some(mount_point) = mount_point {
-                    mount_point
-                } else {
-                    let mut guard = self.db_conn.lock().await;
-                    let mut dao = VaultDao::new(&mut *guard);
-                    match dao.get(self.id as i32) {
-                        Ok(vault) => vault.mount_point,
-                        Err(err) => {
-                            error!(%err, "Cannot get vault");
-                            return Err(VaultHandlerError::CannotLockVault.into());
-                        }
-                    }
-                };
-                if let Err(_) = process::Command::new("umount").arg(&mount_point).output() {
-                    error!(mount_point, "Cannot umount");
-                    return Err(VaultHandlerError::CannotLockVault.into());
-                }
+        #[cfg(target_os = "linux")]
+        match crate::platform::mounts::lazy_unmount(std::path::Path::new(&mount_point)).await {
+            Ok(_) => warn!(mount_point, "Lazily unmounted busy mount point"),
+            Err(PlatformError::NotMounted(_)) => {}
+            Err(err) => {
+                error!(mount_point, err = %err, "Lazy umount failed");
+                return Err(self.publish_error(VaultHandlerError::MountPointBusy(mount_point)));
             }
         }
 
+        let mut guard = self.db_conn.lock().await;
+        let mut dao = VaultDao::new(&mut guard);
+        if let Err(err) = self.db_update_locked(true, &mut dao).await {
+            error!(err = %err, "Cannot update vault state");
+            return Err(self.publish_error(VaultHandlerError::CannotLockVault));
+        }
+        self.events.publish(VaultEvent::Locked { id: self.id });
         Ok(())
     }
 
-    #[instrument(skip(self), fields(self.id), err)]
-    pub async fn unlock(&mut self) -> Result<(), VaultHandlerError> {
+    /// Unlocks with `password` if set, or with the one from the credential provider of the vault.
+    ///
+    /// With `remember` the password is stored in the provider once it unlocked the vault.
+    #[instrument(skip(self, password), fields(self.id), err)]
+    pub async fn unlock(
+        &mut self,
+        password: Option<Password>,
+        remember: bool,
+    ) -> Result<(), VaultHandlerError> {
         info!("");
 
         #[cfg(target_os = "linux")]
         {
-            if self.child.is_some() {
+            if self.is_running() {
                 info!("VaultHandler already unlocked");
+                self.events.publish(VaultEvent::Unlocked { id: self.id });
                 return Ok(());
             }
 
             // create logs files
-            let logs_dir = get_logs_dir();
-            let stdout = OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(logs_dir.join(format!("vault_{}.out", self.id)))
-                .expect("Cannot create stdout file");
-            let stderr = OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(logs_dir.join(format!("vault_{}.err", self.id)))
-                .expect("Cannot create stderr file");
+            let logs_dir = config::current().rencfs_logs_dir.clone();
+            if let Err(err) = std::fs::create_dir_all(&logs_dir) {
+                error!(err = %err, "Cannot create logs dir");
+                return Err(self.publish_error(VaultHandlerError::CannotUnlockVault));
+            }
+            let open_log = |path| OpenOptions::new().append(true).create(true).open(path);
+            let (stdout, stderr) = match (
+                open_log(logs_dir.join(format!("vault_{}.out", self.id))),
+                open_log(stderr_path(self.id)),
+            ) {
+                (Ok(stdout), Ok(stderr)) => (stdout, stderr),
+                (Err(err), _) | (_, Err(err)) => {
+                    error!(err = %err, "Cannot create log files");
+                    return Err(self.publish_error(VaultHandlerError::CannotUnlockVault));
+                }
+            };
+            let stderr_start = stderr.metadata().map(|m| m.len()).unwrap_or_default();
 
             let vault = {
-                let mut guard = self.db_conn.lock().await;
-                let mut dao = VaultDao::new(&mut *guard);
-                match dao.get(self.id as i32) {
-                    Ok(vault) => vault,
+                self.db_get_vault()
+                    .await
+                    .map_err(|err| self.db_error(err, VaultHandlerError::CannotUnlockVault))?
+            };
+
+            let rencfs_bin = self.rencfs_bin().await?;
+            let cipher = self.cipher(&vault, VaultHandlerError::CannotUnlockVault)?;
+
+            // whatever is mounted there would look like rencfs mounted the vault
+            let mount_point = Path::new(&vault.mount_point);
+            if let Ok(Some(mount)) = crate::platform::mounts::find_fuse_mount(mount_point) {
+                error!(
+                    fs_type = mount.fs_type,
+                    source = mount.source,
+                    "Mount point is already mounted"
+                );
+                return Err(self.publish_error(VaultHandlerError::MountPointMounted(
+                    vault.mount_point.clone(),
+                )));
+            }
+            let mounts_before = match crate::platform::mounts::mount_ids() {
+                Ok(ids) => ids,
+                Err(err) => {
+                    error!(err = %err, "Cannot read the mounts");
+                    return Err(self.publish_error(VaultHandlerError::CannotUnlockVault));
+                }
+            };
+
+            let (password, store) = match password {
+                Some(password) => (password, remember),
+                None => match self.credentials.password(&vault).await {
+                    Ok(res) => res,
                     Err(err) => {
-                        error!(err = %err, "Cannot get vault");
-                        return Err(VaultHandlerError::CannotLockVault.into());
+                        error!(err = %err, "Cannot get the password");
+                        return Err(self.publish_error(err.into()));
                     }
+                },
+            };
+            let mut pty = match crate::platform::pty::Pty::open() {
+                Ok(pty) => pty,
+                Err(err) => {
+                    error!(err = %err, "Cannot open a pty for rencfs");
+                    return Err(self.publish_error(VaultHandlerError::CannotUnlockVault));
                 }
             };
 
             // spawn new process
-            let child =
-                Command::new("/home/gnome/dev/RustroverProjects/rencfs/rencfs/target/debug/rencfs")
-                    // TODO get pass from keystore
-                    .env("RENCFS_PASSWORD", "a")
-                    .stdout(stdout)
-                    .stderr(stderr)
-                    .arg("--mount-point")
-                    .arg(&vault.mount_point)
-                    .arg("--data-dir")
-                    .arg(&vault.data_dir)
-                    .arg("--umount-on-start")
-                    .arg("-u")
-                    .spawn();
-            let child = match child {
+            let mut command = Command::new(&rencfs_bin);
+            command
+                // the password is typed in the pty, it's not inherited from the daemon either
+                .env_remove("RENCFS_PASSWORD")
+                .stdout(stdout)
+                .stderr(stderr)
+                .arg("--mount-point")
+                .arg(&vault.mount_point)
+                .arg("--data-dir")
+                .arg(&vault.data_dir)
+                .arg("--cipher")
+                .arg(cipher.arg())
+                .arg("--umount-on-start")
+                .arg("-u");
+            if let Err(err) = pty.attach(&mut command) {
+                error!(err = %err, "Cannot attach the pty");
+                return Err(self.publish_error(VaultHandlerError::CannotUnlockVault));
+            }
+            let child = command.spawn();
+            let mut child = match child {
                 Ok(child) => child,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    error!(err = %err, "rencfs binary not found");
+                    return Err(self.publish_error(VaultHandlerError::RencfsBinaryMissing(
+                        rencfs_bin.display().to_string(),
+                    )));
+                }
                 Err(err) => {
                     error!(err = %err, "Cannot start process");
-                    return Err(VaultHandlerError::CannotUnlockVault.into());
+                    return Err(self.publish_error(VaultHandlerError::CannotUnlockVault));
                 }
             };
-
-            // wait few second and check if it started correctly
-            tokio::time::sleep(tokio::time::Duration::from_secs(8)).await;
-            if child.id().is_none() {
-                return Err(VaultHandlerError::CannotUnlockVault.into());
+            // it waits in the pty until rencfs reads it
+            if let Err(err) = pty.write_line(password.expose().as_bytes()) {
+                error!(err = %err, "Cannot pass the password to rencfs");
+                if let Err(err) = child.kill().await {
+                    error!(err = %err, "Cannot kill process");
+                }
+                return Err(self.publish_error(VaultHandlerError::CannotUnlockVault));
             }
-            let mut sys = System::new();
-            sys.refresh_processes(ProcessesToUpdate::All, false);
-            let mut is_defunct = false;
-            match sys.process(Pid::from_u32(child.id().unwrap())) {
-                Some(process) => {
-                    println!("{:?}", process.status());
-                    if process.status() == ProcessStatus::Dead
-                        || process.status() == ProcessStatus::Zombie
-                        || process.status() == ProcessStatus::Stop
-                    {
-                        warn!("Process is dead or zombie, killing it");
-                        is_defunct = true;
-                    } else {
-                        // try to check if it's defunct with ps command
-                        // TODO: ps for windows
-                        if cfg!(any(target_os = "linux", target_os = "macos")) {
-                            let out = Command::new("ps")
-                                .arg("-f")
-                                .arg(child.id().unwrap().to_string())
-                                .output()
-                                .await
-                                .expect("Cannot run ps command");
-                            String::from_utf8(out.stdout)
-                                .unwrap()
-                                .lines()
-                                .for_each(|line| {
-                                    if line.contains("defunct") {
-                                        warn!("Process is defunct, killing it");
-                                        is_defunct = true;
-                                    }
-                                });
-                        }
+
+            let timeout = config::current().mount_timeout();
+            let started = Instant::now();
+            loop {
+                if let Ok(Some(status)) = child.try_wait() {
+                    error!(%status, "Process exited before mounting");
+                    let stderr = stderr_tail(self.id, stderr_start).unwrap_or_default();
+                    if crate::rencfs::is_wrong_password(&stderr) {
+                        return Err(self.publish_error(VaultHandlerError::WrongPassword));
+                    }
+                    return Err(
+                        self.publish_error(VaultHandlerError::ProcessExited(status.code(), stderr))
+                    );
+                }
+                if crate::platform::mounts::is_newly_fuse_mounted(mount_point, &mounts_before) {
+                    info!(elapsed = ?started.elapsed(), "Vault mounted");
+                    break;
+                }
+                if started.elapsed() >= timeout {
+                    error!(?timeout, "Vault not mounted in time, killing rencfs");
+                    if let Err(err) = child.kill().await {
+                        error!(err = %err, "Cannot kill process");
                     }
+                    return Err(
+                        self.publish_error(VaultHandlerError::MountTimeout(timeout.as_secs()))
+                    );
                 }
-                None => return Err(VaultHandlerError::CannotUnlockVault.into()),
+                tokio::time::sleep(MOUNT_POLL_INTERVAL).await;
             }
-            if is_defunct {
-                // TODO: kill for windows
-                // if cfg!(any(linux, unix, macos)) {
-                process::Command::new("kill")
-                    .arg(child.id().unwrap().to_string())
-                    .output()
-                    .expect("Cannot kill process");
-                // }
-                return Err(VaultHandlerError::CannotUnlockVault.into());
+
+            // rencfs read the password if it mounted, and ignores the hangup of closing the pty
+            drop(pty);
+            if store {
+                match self.credentials.remember(&vault, &password).await {
+                    Ok(_) => info!(provider = vault.credential_provider, "Stored the password"),
+                    Err(err) => warn!(err = %err, "Cannot store the password"),
+                }
             }
 
-            self.child = Some(child);
+            self.process = Some(Supervisor::spawn(
+                self.id,
+                child,
+                stderr_start,
+                self.db_conn.clone(),
+                self.events.clone(),
+            ));
         }
 
         let mut guard = self.db_conn.lock().await;
-        let mut dao = VaultDao::new(&mut *guard);
+        let mut dao = VaultDao::new(&mut guard);
         match self.db_update_locked(false, &mut dao).await {
             Ok(_) => {}
             Err(err) => {
                 error!(err = %err, "Cannot update vault state");
-                return Err(VaultHandlerError::CannotUnlockVault.into());
+                return Err(self.publish_error(VaultHandlerError::CannotUnlockVault));
             }
         }
 
+        self.events.publish(VaultEvent::Unlocked { id: self.id });
         Ok(())
     }
 
@@ -228,40 +455,581 @@ impl VaultHandler {
     ) -> Result<(), VaultHandlerError> {
         info!("");
 
-        let unlocked = self.child.is_some();
+        let unlocked = self.is_running();
         if unlocked {
             self.lock(Some(old_mount_point)).await?;
-            self.unlock().await?;
+            self.unlock(None, false).await?;
         }
 
+        let vault = self
+            .db_get_vault()
+            .await
+            .map_err(|err| self.db_error(err, VaultHandlerError::CannotChangeMountPoint))?;
+        self.events.publish(VaultEvent::MountPointChanged {
+            id: self.id,
+            mount_point: vault.mount_point,
+        });
         Ok(())
     }
 
-    #[instrument(skip(self), fields(self.id), err)]
-    pub async fn change_data_dir(&mut self, old_data_dir: String) -> Result<(), VaultHandlerError> {
+    /// Moves the encrypted files to the empty `new_data_dir` and switches the vault to it.
+    ///
+    /// The vault is locked meanwhile and unlocked again after, if it was. The copy is checked
+    /// against the originals before the switch, until then the vault keeps using the old data dir
+    /// and on failure or when `job` is cancelled the copy is removed. The old data dir is removed
+    /// after with `remove_old`.
+    #[instrument(skip(self, job), fields(self.id, job = job.id()), err)]
+    pub async fn change_data_dir(
+        &mut self,
+        new_data_dir: String,
+        remove_old: bool,
+        job: &JobContext,
+    ) -> Result<(), VaultHandlerError> {
         info!("");
 
-        let unlocked = self.child.is_some();
-        if unlocked {
-            let mount_point = {
-                let mut guard = self.db_conn.lock().await;
-                let mut dao = VaultDao::new(&mut *guard);
-                match dao.get(self.id as i32) {
-                    Ok(vault) => vault.mount_point,
-                    Err(err) => {
-                        error!(err = %err, "Cannot get vault");
-                        return Err(VaultHandlerError::CannotChangeDataDir.into());
+        let vault = self
+            .db_get_vault()
+            .await
+            .map_err(|err| self.db_error(err, VaultHandlerError::CannotChangeDataDir))?;
+        if Path::new(&new_data_dir).starts_with(&vault.data_dir) {
+            return Err(self.publish_error(VaultHandlerError::DataDirInsideCurrent(new_data_dir)));
+        }
+        check_empty_data_dir(Path::new(&new_data_dir)).map_err(|err| self.publish_error(err))?;
+        // now that it exists, through a symlink or `..`
+        if is_inside(Path::new(&new_data_dir), Path::new(&vault.data_dir)) {
+            // it was checked to be empty, don't leave it in the vault
+            let _ = std::fs::remove_dir(&new_data_dir);
+            return Err(self.publish_error(VaultHandlerError::DataDirInsideCurrent(new_data_dir)));
+        }
+
+        let was_unlocked = self.is_running();
+        if was_unlocked {
+            self.lock(Some(vault.mount_point.clone())).await?;
+        }
+        let mv = DataDirMove {
+            vault_id: self.id as i32,
+            old_data_dir: vault.data_dir,
+            new_data_dir,
+            remove_old: remove_old as i32,
+            was_unlocked: was_unlocked as i32,
+            phase: MoveState::Copying.as_str().to_string(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or_default(),
+        };
+        let res = {
+            let mut guard = self.db_conn.lock().await;
+            DataDirMoveDao::new(&mut guard).insert(&mv)
+        };
+        if let Err(err) = res {
+            error!(err = %err, "Cannot record the data dir move");
+            self.unlock_after_move(&mv).await;
+            return Err(self.publish_error(VaultHandlerError::CannotChangeDataDir));
+        }
+        self.run_data_dir_move(mv, job).await
+    }
+
+    /// Finishes a move [`VaultHandler::change_data_dir`] didn't, because the daemon stopped.
+    ///
+    /// A partial copy is completed, or if the vault was already switched the old data dir is
+    /// removed if it should be.
+    #[instrument(skip(self, job), fields(self.id, job = job.id()), err)]
+    pub async fn resume_data_dir_move(
+        &mut self,
+        mv: DataDirMove,
+        job: &JobContext,
+    ) -> Result<(), VaultHandlerError> {
+        info!("");
+
+        self.run_data_dir_move(mv, job).await
+    }
+
+    async fn run_data_dir_move(
+        &mut self,
+        mv: DataDirMove,
+        job: &JobContext,
+    ) -> Result<(), VaultHandlerError> {
+        let state = mv.phase.parse().unwrap_or_else(|err: String| {
+            warn!(err, "Invalid data dir move phase in DB, copying again");
+            MoveState::Copying
+        });
+        if state == MoveState::Copying {
+            if let Err(err) = self.copy_and_switch(&mv, job).await {
+                if let Err(err) = clear_dir(Path::new(&mv.new_data_dir)) {
+                    error!(err = %err, "Cannot remove the copy from the new data dir");
+                }
+                self.db_delete_move().await;
+                self.unlock_after_move(&mv).await;
+                return Err(self.publish_error(err));
+            }
+            info!(data_dir = mv.new_data_dir, "Switched to the new data dir");
+        }
+
+        // too late to cancel, the vault uses the copy already
+        if mv.remove_old == 1 {
+            job.report(MovePhase::RemovingOld, 0, 0);
+            self.publish_move_progress(MovePhase::RemovingOld, 0, 0);
+            let old = PathBuf::from(&mv.old_data_dir);
+            match tokio::task::spawn_blocking(move || std::fs::remove_dir_all(old)).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) if err.kind() == io::ErrorKind::NotFound => {}
+                Ok(Err(err)) => warn!(err = %err, "Cannot remove the old data dir"),
+                Err(err) => warn!(err = %err, "Cannot remove the old data dir"),
+            }
+        }
+        self.db_delete_move().await;
+        self.events.publish(VaultEvent::DataDirChanged {
+            id: self.id,
+            data_dir: mv.new_data_dir.clone(),
+        });
+        self.unlock_after_move(&mv).await;
+        Ok(())
+    }
+
+    /// Copies and verifies the files, then points the vault to the copy.
+    async fn copy_and_switch(
+        &self,
+        mv: &DataDirMove,
+        job: &JobContext,
+    ) -> Result<(), VaultHandlerError> {
+        self.copy_data_dir(
+            &mv.old_data_dir,
+            &mv.new_data_dir,
+            job,
+            true,
+            VaultHandlerError::CannotChangeDataDir,
+        )
+        .await?;
+
+        let mut guard = self.db_conn.lock().await;
+        DataDirMoveDao::new(&mut guard)
+            .switch(mv, MoveState::Switched.as_str())
+            .map_err(|err| {
+                error!(err = %err, "Cannot switch to the new data dir");
+                VaultHandlerError::CannotChangeDataDir
+            })
+    }
+
+    /// Copies the encrypted files of the vault to the empty `dir`, checked like when moving the
+    /// data dir.
+    ///
+    /// The vault is locked meanwhile, so rencfs doesn't change them half way, and unlocked again
+    /// after if it was. On failure or when `job` is cancelled the copy is removed.
+    #[instrument(skip(self, job), fields(self.id, job = job.id()), err)]
+    pub async fn backup(&mut self, dir: String, job: &JobContext) -> Result<(), VaultHandlerError> {
+        info!("");
+
+        let vault = self
+            .db_get_vault()
+            .await
+            .map_err(|err| self.db_error(err, VaultHandlerError::CannotBackUpVault))?;
+        if Path::new(&dir).starts_with(&vault.data_dir) {
+            return Err(self.publish_error(VaultHandlerError::DataDirInsideCurrent(dir)));
+        }
+        check_empty_data_dir(Path::new(&dir)).map_err(|err| self.publish_error(err))?;
+        // now that it exists, through a symlink or `..`
+        if is_inside(Path::new(&dir), Path::new(&vault.data_dir)) {
+            // it was checked to be empty, don't leave it in the vault
+            let _ = std::fs::remove_dir(&dir);
+            return Err(self.publish_error(VaultHandlerError::DataDirInsideCurrent(dir)));
+        }
+
+        let was_unlocked = self.is_running();
+        if was_unlocked {
+            self.lock(Some(vault.mount_point.clone())).await?;
+        }
+        let res = self
+            .copy_data_dir(
+                &vault.data_dir,
+                &dir,
+                job,
+                false,
+                VaultHandlerError::CannotBackUpVault,
+            )
+            .await;
+        if res.is_err() {
+            if let Err(err) = clear_dir(Path::new(&dir)) {
+                error!(err = %err, "Cannot remove the partial backup");
+            }
+        } else {
+            info!(dir, "Vault backed up");
+        }
+        if was_unlocked {
+            if let Err(err) = self.unlock(None, false).await {
+                warn!(err = %err, "Cannot unlock the vault again after backing it up");
+            }
+        }
+        res.map_err(|err| self.publish_error(err))
+    }
+
+    /// [`copy_verified`] off the runtime, reporting its progress to `job`, and with `publish` to
+    /// the vault events as a data dir move.
+    async fn copy_data_dir(
+        &self,
+        from: &str,
+        to: &str,
+        job: &JobContext,
+        publish: bool,
+        other: VaultHandlerError,
+    ) -> Result<(), VaultHandlerError> {
+        let from = PathBuf::from(from);
+        let to = PathBuf::from(to);
+        let id = self.id;
+        let events = self.events.clone();
+        let job = job.clone();
+        let mut last: Option<(MovePhase, Instant)> = None;
+        let copied = tokio::task::spawn_blocking(move || {
+            copy_verified(
+                &from,
+                &to,
+                job.cancel_flag(),
+                &mut |phase, done_bytes, total_bytes| {
+                    job.report(phase, done_bytes, total_bytes);
+                    if !publish {
+                        return;
                     }
+                    let due = match last {
+                        Some((last_phase, at)) => {
+                            last_phase != phase
+                                || done_bytes == total_bytes
+                                || at.elapsed() >= MOVE_PROGRESS_INTERVAL
+                        }
+                        None => true,
+                    };
+                    if due {
+                        last = Some((phase, Instant::now()));
+                        events.publish(VaultEvent::DataDirMoving {
+                            id,
+                            phase,
+                            done_bytes,
+                            total_bytes,
+                        });
+                    }
+                },
+            )
+        })
+        .await;
+        match copied {
+            Ok(res) => res.map_err(|err| err.into_handler_error(other)),
+            Err(err) => {
+                error!(err = %err, "The copy task failed");
+                Err(other)
+            }
+        }
+    }
+
+    /// The unlock publishes its own errors, the move itself is done.
+    async fn unlock_after_move(&mut self, mv: &DataDirMove) {
+        if mv.was_unlocked == 1 {
+            if let Err(err) = self.unlock(None, false).await {
+                warn!(err = %err, "Cannot unlock the vault again after moving it");
+            }
+        }
+    }
+
+    fn publish_move_progress(&self, phase: MovePhase, done_bytes: u64, total_bytes: u64) {
+        self.events.publish(VaultEvent::DataDirMoving {
+            id: self.id,
+            phase,
+            done_bytes,
+            total_bytes,
+        });
+    }
+
+    /// Creates the encrypted store of a new vault in its empty data dir, with `password`.
+    ///
+    /// rencfs creates the key on its first start. It's then stopped and the vault unlocked once
+    /// more, to check the key it wrote opens with the password. On failure what rencfs created is
+    /// removed, so the data dir is empty again.
+    #[instrument(skip(self, password), fields(self.id), err)]
+    pub async fn init(&mut self, password: Password) -> Result<(), VaultHandlerError> {
+        info!("");
+
+        let vault = self
+            .db_get_vault()
+            .await
+            .map_err(|err| self.db_error(err, VaultHandlerError::CannotInitVault))?;
+        let data_dir = PathBuf::from(&vault.data_dir);
+        check_empty_data_dir(&data_dir).map_err(|err| self.publish_error(err))?;
+
+        if let Err(err) = self.init_and_verify(&data_dir, &password).await {
+            if self.is_running() {
+                if let Err(err) = self.force_lock(LOCK_TIMEOUT).await {
+                    error!(err = %err, "Cannot lock the vault");
                 }
-            };
-            self.lock(Some(mount_point)).await?;
-            // TODO: move content to new data dir
-            self.unlock().await?;
+            }
+            if let Err(err) = clear_dir(&data_dir) {
+                error!(err = %err, "Cannot clean the data dir");
+            }
+            return Err(err);
         }
+        info!("Vault initialized");
 
+        if let Err(err) = self.credentials.remember(&vault, &password).await {
+            warn!(err = %err, "Cannot store the password");
+        }
         Ok(())
     }
 
+    async fn init_and_verify(
+        &mut self,
+        data_dir: &Path,
+        password: &Password,
+    ) -> Result<(), VaultHandlerError> {
+        self.unlock(Some(password.clone()), false).await?;
+        self.lock(None).await?;
+        if !crate::rencfs::has_key(data_dir) {
+            error!("rencfs didn't create the vault key");
+            return Err(self.publish_error(VaultHandlerError::CannotInitVault));
+        }
+        // the trial unlock, with the key rencfs read back from the data dir
+        self.unlock(Some(password.clone()), false).await?;
+        self.lock(None).await
+    }
+
+    /// Changes the password with `rencfs passwd`, which checks `current` against the vault key.
+    ///
+    /// The new password is stored under a new key id and the vault switched to it only after
+    /// rencfs succeeded, so until then the old password and the stored one keep working. If
+    /// anything fails after rencfs started, its key files are put back as they were.
+    #[instrument(skip(self, current, new), fields(self.id), err)]
+    pub async fn change_password(
+        &mut self,
+        current: Password,
+        new: Password,
+    ) -> Result<(), VaultHandlerError> {
+        info!("");
+
+        // rencfs would rewrite the key under a running instance
+        if self.is_running() {
+            return Err(self.publish_error(VaultHandlerError::VaultUnlocked));
+        }
+        let vault = self
+            .db_get_vault()
+            .await
+            .map_err(|err| self.db_error(err, VaultHandlerError::CannotChangePassword))?;
+        let rencfs_bin = self.rencfs_bin().await?;
+        let cipher = self.cipher(&vault, VaultHandlerError::CannotChangePassword)?;
+
+        let new_vault = Vault {
+            credential_key_id: credentials::new_key_id(),
+            ..vault.clone()
+        };
+        if let Err(err) = self.credentials.remember(&new_vault, &new).await {
+            error!(err = %err, "Cannot store the new password");
+            return Err(self.publish_error(err.into()));
+        }
+        let snapshot = match KeySnapshot::take(Path::new(&vault.data_dir)) {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                error!(err = %err, "Cannot read the vault key");
+                self.forget_new_password(&new_vault).await;
+                return Err(self.publish_error(VaultHandlerError::CannotChangePassword));
+            }
+        };
+
+        let res = match self
+            .run_passwd(&rencfs_bin, &vault.data_dir, cipher, &current, &new)
+            .await
+        {
+            Ok(_) => {
+                use crate::schema::vaults::dsl::credential_key_id;
+                use diesel::ExpressionMethods;
+
+                let mut guard = self.db_conn.lock().await;
+                let mut dao = VaultDao::new(&mut guard);
+                dao.update(
+                    self.id as i32,
+                    credential_key_id.eq(&new_vault.credential_key_id),
+                )
+                .map_err(|err| {
+                    error!(err = %err, "Cannot switch to the new password");
+                    VaultHandlerError::CannotChangePassword
+                })
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = res {
+            if let Err(err) = snapshot.restore() {
+                error!(err = %err, "Cannot restore the vault key");
+            }
+            self.forget_new_password(&new_vault).await;
+            return Err(self.publish_error(err));
+        }
+
+        info!("Password changed");
+        let kind = credentials::kind_of(&vault).unwrap_or_default();
+        if let Err(err) = self.credentials.forget(&vault, kind).await {
+            warn!(err = %err, "Cannot delete the old password");
+        }
+        Ok(())
+    }
+
+    /// Types both passwords in the pty of `rencfs passwd`, the new one twice to confirm it.
+    async fn run_passwd(
+        &self,
+        rencfs_bin: &Path,
+        data_dir: &str,
+        cipher: Cipher,
+        current: &Password,
+        new: &Password,
+    ) -> Result<(), VaultHandlerError> {
+        #[cfg(target_os = "linux")]
+        {
+            let mut pty = crate::platform::pty::Pty::open().map_err(|err| {
+                error!(err = %err, "Cannot open a pty for rencfs");
+                VaultHandlerError::CannotChangePassword
+            })?;
+            let mut command = Command::new(rencfs_bin);
+            command
+                .env_remove("RENCFS_PASSWORD")
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .arg("passwd")
+                .arg("--data-dir")
+                .arg(data_dir)
+                .arg("--cipher")
+                .arg(cipher.arg());
+            pty.attach(&mut command).map_err(|err| {
+                error!(err = %err, "Cannot attach the pty");
+                VaultHandlerError::CannotChangePassword
+            })?;
+            let child = match command.spawn() {
+                Ok(child) => child,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    error!(err = %err, "rencfs binary not found");
+                    return Err(VaultHandlerError::RencfsBinaryMissing(
+                        rencfs_bin.display().to_string(),
+                    ));
+                }
+                Err(err) => {
+                    error!(err = %err, "Cannot start process");
+                    return Err(VaultHandlerError::CannotChangePassword);
+                }
+            };
+            for password in [current, new, new] {
+                if let Err(err) = pty.write_line(password.expose().as_bytes()) {
+                    error!(err = %err, "Cannot pass the passwords to rencfs");
+                    return Err(VaultHandlerError::CannotChangePassword);
+                }
+            }
+
+            let output = match tokio::time::timeout(PASSWD_TIMEOUT, child.wait_with_output()).await
+            {
+                Ok(Ok(output)) => output,
+                Ok(Err(err)) => {
+                    error!(err = %err, "Cannot wait for rencfs");
+                    return Err(VaultHandlerError::CannotChangePassword);
+                }
+                Err(_) => {
+                    error!(timeout = ?PASSWD_TIMEOUT, "rencfs passwd didn't finish in time");
+                    return Err(VaultHandlerError::CannotChangePassword);
+                }
+            };
+            let text = format!(
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            // it prints why it failed but might still exit with 0
+            if crate::rencfs::is_wrong_password(&text) {
+                return Err(VaultHandlerError::WrongPassword);
+            }
+            if !output.status.success() {
+                error!(status = %output.status, "rencfs passwd failed");
+                let lines: Vec<&str> = text.lines().collect();
+                return Err(VaultHandlerError::ProcessExited(
+                    output.status.code(),
+                    lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n"),
+                ));
+            }
+            Ok(())
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (rencfs_bin, data_dir, cipher, current, new);
+            Err(VaultHandlerError::CannotChangePassword)
+        }
+    }
+
+    async fn forget_new_password(&self, new_vault: &Vault) {
+        let kind = credentials::kind_of(new_vault).unwrap_or_default();
+        if let Err(err) = self.credentials.forget(new_vault, kind).await {
+            warn!(err = %err, "Cannot delete the new password");
+        }
+    }
+
+    /// `other` if the cipher in the DB is not one rencfs knows.
+    fn cipher(&self, vault: &Vault, other: VaultHandlerError) -> Result<Cipher, VaultHandlerError> {
+        vault.cipher.parse().map_err(|err: String| {
+            error!(err, "Invalid cipher in DB");
+            self.publish_error(other)
+        })
+    }
+
+    /// The rencfs binary to run, if it can be used.
+    async fn rencfs_bin(&self) -> Result<PathBuf, VaultHandlerError> {
+        let rencfs = self.rencfs.read().await.clone();
+        match (&rencfs.path, &rencfs.status) {
+            (Some(path), Status::Ok(_)) => Ok(path.clone()),
+            (Some(path), Status::UnknownVersion) => {
+                warn!(path = %path.display(), "Unknown rencfs version, trying it anyway");
+                Ok(path.clone())
+            }
+            (_, Status::Unsupported(version)) => {
+                Err(self.publish_error(VaultHandlerError::RencfsUnsupported(version.to_string())))
+            }
+            (None, _) | (_, Status::Missing) => Err(self.publish_error(
+                VaultHandlerError::RencfsBinaryMissing(rencfs.display_path()),
+            )),
+        }
+    }
+
+    /// Forgets the process if it exited, the supervisor already cleaned up after it.
+    fn is_running(&mut self) -> bool {
+        if self.process.as_ref().is_some_and(Supervisor::has_exited) {
+            self.process = None;
+        }
+        self.process.is_some()
+    }
+
+    fn publish_error(&self, err: VaultHandlerError) -> VaultHandlerError {
+        // the user asked for it, nothing went wrong
+        if err != VaultHandlerError::Cancelled {
+            self.events.publish(VaultEvent::Error {
+                id: self.id,
+                message: err.to_string(),
+            });
+        }
+        err
+    }
+
+    fn db_error(&self, err: diesel::result::Error, other: VaultHandlerError) -> VaultHandlerError {
+        error!(err = %err, "Cannot get vault");
+        match err {
+            diesel::result::Error::NotFound => {
+                self.publish_error(VaultHandlerError::VaultNotFound(self.id))
+            }
+            _ => self.publish_error(other),
+        }
+    }
+
+    #[instrument(skip(self), fields(self.id), err)]
+    async fn db_get_vault(&self) -> QueryResult<Vault> {
+        let mut guard = self.db_conn.lock().await;
+        let mut dao = VaultDao::new(&mut guard);
+        dao.get(self.id as i32)
+    }
+
+    async fn db_delete_move(&self) {
+        let mut guard = self.db_conn.lock().await;
+        if let Err(err) = DataDirMoveDao::new(&mut guard).delete(self.id as i32) {
+            error!(err = %err, "Cannot delete the data dir move");
+        }
+    }
+
     #[instrument(skip(self, dao), fields(self.id), err)]
     async fn db_update_locked(&self, state: bool, dao: &mut VaultDao<'_>) -> QueryResult<()> {
         use crate::schema::vaults::dsl::locked;
@@ -1,28 +1,334 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tonic::Status;
+use tonic::{Code, Status};
+use tonic_types::{ErrorDetails, StatusExt};
 
 use crate::vault_handler::VaultHandlerError;
 
-#[derive(Debug, Error, Serialize, Deserialize, Clone)]
+#[derive(Debug, Error, Serialize, Deserialize, Clone, PartialEq)]
 pub enum VaultServiceError {
     #[error("{0}")]
-    VaultHandlerError(#[from] VaultHandlerError),
+    VaultHandlerError(VaultHandlerError),
+    #[error("vault {0} not found")]
+    VaultNotFound(u32),
+    #[error("another vault named {0} exists")]
+    VaultNameExists(String),
+    #[error("invalid {0}")]
+    InvalidArgument(String),
+    #[error("database error: {0}")]
+    Database(String),
+    #[error("invalid config: {0}")]
+    InvalidConfig(String),
+    #[error("no password request {0}")]
+    PasswordRequestNotFound(u64),
+    #[error("the cipher of a vault can't be changed without re-encrypting it")]
+    CipherChangeNotSupported,
+    #[error("job {0} not found")]
+    JobNotFound(u32),
+    #[error("vault {0} has a job running")]
+    JobRunning(u32),
+    #[error("job {0} is not running")]
+    JobNotRunning(u32),
+}
+
+/// `ErrorInfo.domain` of the errors sent by the daemon.
+const DOMAIN: &str = "rencfs_desktop";
+const HINT_LOCALE: &str = "en-US";
+
+impl From<VaultHandlerError> for VaultServiceError {
+    fn from(err: VaultHandlerError) -> Self {
+        match err {
+            VaultHandlerError::VaultNotFound(id) => VaultServiceError::VaultNotFound(id),
+            err => VaultServiceError::VaultHandlerError(err),
+        }
+    }
 }
 
-static CUSTOM_ERROR: &str = "x-custom-tonic-error-vault_service_error";
+impl VaultServiceError {
+    pub fn code(&self) -> Code {
+        match self {
+            VaultServiceError::VaultHandlerError(err) => match err {
+                VaultHandlerError::VaultNotFound(_) => Code::NotFound,
+                VaultHandlerError::MountPointBusy(_)
+                | VaultHandlerError::MountPointMounted(_)
+                | VaultHandlerError::RencfsBinaryMissing(_)
+                | VaultHandlerError::RencfsUnsupported(_) => Code::FailedPrecondition,
+                VaultHandlerError::WrongPassword => Code::Unauthenticated,
+                VaultHandlerError::ProcessExited(..) => Code::Unavailable,
+                VaultHandlerError::MountTimeout(_) => Code::DeadlineExceeded,
+                VaultHandlerError::PasswordNotProvided => Code::Cancelled,
+                VaultHandlerError::CredentialUnavailable(_) => Code::FailedPrecondition,
+                VaultHandlerError::VaultUnlocked
+                | VaultHandlerError::DataDirNotEmpty(_)
+                | VaultHandlerError::DataDirNotWritable(_)
+                | VaultHandlerError::DataDirInsideCurrent(_) => Code::FailedPrecondition,
+                VaultHandlerError::NoSpace(_) => Code::ResourceExhausted,
+                VaultHandlerError::Cancelled => Code::Cancelled,
+                VaultHandlerError::CannotLockVault
+                | VaultHandlerError::CannotUnlockVault
+                | VaultHandlerError::CannotChangeMountPoint
+                | VaultHandlerError::CannotChangeDataDir
+                | VaultHandlerError::CannotChangePassword
+                | VaultHandlerError::CannotInitVault
+                | VaultHandlerError::CannotBackUpVault => Code::Internal,
+            },
+            VaultServiceError::VaultNotFound(_) => Code::NotFound,
+            VaultServiceError::VaultNameExists(_) => Code::AlreadyExists,
+            VaultServiceError::InvalidArgument(_) => Code::InvalidArgument,
+            VaultServiceError::Database(_) => Code::Unavailable,
+            VaultServiceError::InvalidConfig(_) => Code::FailedPrecondition,
+            VaultServiceError::PasswordRequestNotFound(_) => Code::NotFound,
+            VaultServiceError::CipherChangeNotSupported => Code::FailedPrecondition,
+            VaultServiceError::JobNotFound(_) => Code::NotFound,
+            VaultServiceError::JobRunning(_) | VaultServiceError::JobNotRunning(_) => {
+                Code::FailedPrecondition
+            }
+        }
+    }
+
+    /// What the user can do about it.
+    pub fn hint(&self) -> String {
+        match self {
+            VaultServiceError::VaultHandlerError(err) => match err {
+                VaultHandlerError::VaultNotFound(_) => {
+                    "the vault was probably deleted, refresh the list".to_string()
+                }
+                VaultHandlerError::MountPointBusy(mount_point) => {
+                    format!("close the files and terminals using {mount_point} and try again")
+                }
+                VaultHandlerError::MountPointMounted(mount_point) => {
+                    format!(
+                        "unmount what is mounted on {mount_point}, or choose another mount point"
+                    )
+                }
+                VaultHandlerError::RencfsBinaryMissing(path) => format!(
+                    "install rencfs, it was not found at {path}, or set rencfs_path in the daemon config or {}",
+                    crate::rencfs::RENCFS_PATH_ENV
+                ),
+                VaultHandlerError::RencfsUnsupported(_) => {
+                    format!("install rencfs {}", crate::rencfs::supported_versions())
+                }
+                VaultHandlerError::WrongPassword => "check the password and try again".to_string(),
+                VaultHandlerError::ProcessExited(..) => {
+                    "check the vault logs for the reason rencfs stopped".to_string()
+                }
+                VaultHandlerError::MountTimeout(_) => format!(
+                    "check the vault logs, or increase mount_timeout in {} if rencfs is just slow",
+                    crate::config::DaemonConfig::path().display()
+                ),
+                VaultHandlerError::PasswordNotProvided => {
+                    "unlock the vault again and enter the password".to_string()
+                }
+                VaultHandlerError::CredentialUnavailable(_) => {
+                    "check where the password is stored, or ask for it every time instead"
+                        .to_string()
+                }
+                VaultHandlerError::VaultUnlocked => "lock the vault first".to_string(),
+                VaultHandlerError::DataDirNotEmpty(_) => "choose an empty data dir".to_string(),
+                VaultHandlerError::DataDirNotWritable(path) => {
+                    format!("check the permissions of {path}, or choose another data dir")
+                }
+                VaultHandlerError::NoSpace(_) => {
+                    "free some space or choose another data dir".to_string()
+                }
+                VaultHandlerError::DataDirInsideCurrent(_) => {
+                    "choose a dir outside the data dir of the vault".to_string()
+                }
+                VaultHandlerError::Cancelled => "start it again if needed".to_string(),
+                VaultHandlerError::CannotLockVault
+                | VaultHandlerError::CannotUnlockVault
+                | VaultHandlerError::CannotChangeMountPoint
+                | VaultHandlerError::CannotChangeDataDir
+                | VaultHandlerError::CannotChangePassword
+                | VaultHandlerError::CannotInitVault
+                | VaultHandlerError::CannotBackUpVault => {
+                    "check the daemon logs for details".to_string()
+                }
+            },
+            VaultServiceError::VaultNotFound(_) => {
+                "the vault was probably deleted, refresh the list".to_string()
+            }
+            VaultServiceError::VaultNameExists(_) => "choose another name".to_string(),
+            VaultServiceError::InvalidArgument(field) => format!("set a valid {field}"),
+            VaultServiceError::Database(_) => {
+                "check the daemon logs, it might need a restart".to_string()
+            }
+            VaultServiceError::InvalidConfig(_) => format!(
+                "fix {} and reload, the previous config is still in use",
+                crate::config::DaemonConfig::path().display()
+            ),
+            VaultServiceError::PasswordRequestNotFound(_) => {
+                "it timed out or was answered already, unlock the vault again".to_string()
+            }
+            VaultServiceError::CipherChangeNotSupported => {
+                "create a new vault with that cipher and copy the files into it".to_string()
+            }
+            VaultServiceError::JobNotFound(_) => "refresh the list of jobs".to_string(),
+            VaultServiceError::JobRunning(_) => {
+                "wait for the running job to finish, or cancel it".to_string()
+            }
+            VaultServiceError::JobNotRunning(_) => "it already finished".to_string(),
+        }
+    }
+
+    /// The remediation hint sent by the daemon in the status details.
+    pub fn hint_from_status(status: &Status) -> Option<String> {
+        status.get_details_localized_message().map(|m| m.message)
+    }
+
+    /// `ErrorInfo.reason` and `ErrorInfo.metadata`, enough to build the error back on the client.
+    fn error_info(&self) -> (&'static str, HashMap<String, String>) {
+        let one = |k: &str, v: String| HashMap::from([(k.to_string(), v)]);
+        match self {
+            VaultServiceError::VaultHandlerError(err) => match err {
+                VaultHandlerError::VaultNotFound(id) => {
+                    ("VAULT_NOT_FOUND", one("id", id.to_string()))
+                }
+                VaultHandlerError::MountPointBusy(mount_point) => {
+                    ("MOUNT_POINT_BUSY", one("mount_point", mount_point.clone()))
+                }
+                VaultHandlerError::MountPointMounted(mount_point) => (
+                    "MOUNT_POINT_MOUNTED",
+                    one("mount_point", mount_point.clone()),
+                ),
+                VaultHandlerError::RencfsBinaryMissing(path) => {
+                    ("RENCFS_BINARY_MISSING", one("path", path.clone()))
+                }
+                VaultHandlerError::RencfsUnsupported(version) => {
+                    ("RENCFS_UNSUPPORTED", one("version", version.clone()))
+                }
+                VaultHandlerError::WrongPassword => ("WRONG_PASSWORD", HashMap::new()),
+                VaultHandlerError::ProcessExited(code, stderr) => {
+                    let mut metadata = one("stderr", stderr.clone());
+                    if let Some(code) = code {
+                        metadata.insert("exit_code".to_string(), code.to_string());
+                    }
+                    ("PROCESS_EXITED", metadata)
+                }
+                VaultHandlerError::MountTimeout(secs) => {
+                    ("MOUNT_TIMEOUT", one("timeout_secs", secs.to_string()))
+                }
+                VaultHandlerError::PasswordNotProvided => ("PASSWORD_NOT_PROVIDED", HashMap::new()),
+                VaultHandlerError::CredentialUnavailable(err) => {
+                    ("CREDENTIAL_UNAVAILABLE", one("error", err.clone()))
+                }
+                VaultHandlerError::CannotLockVault => ("CANNOT_LOCK_VAULT", HashMap::new()),
+                VaultHandlerError::CannotUnlockVault => ("CANNOT_UNLOCK_VAULT", HashMap::new()),
+                VaultHandlerError::CannotChangeMountPoint => {
+                    ("CANNOT_CHANGE_MOUNT_POINT", HashMap::new())
+                }
+                VaultHandlerError::CannotChangeDataDir => {
+                    ("CANNOT_CHANGE_DATA_DIR", HashMap::new())
+                }
+                VaultHandlerError::VaultUnlocked => ("VAULT_UNLOCKED", HashMap::new()),
+                VaultHandlerError::CannotChangePassword => {
+                    ("CANNOT_CHANGE_PASSWORD", HashMap::new())
+                }
+                VaultHandlerError::DataDirNotEmpty(path) => {
+                    ("DATA_DIR_NOT_EMPTY", one("path", path.clone()))
+                }
+                VaultHandlerError::DataDirNotWritable(path) => {
+                    ("DATA_DIR_NOT_WRITABLE", one("path", path.clone()))
+                }
+                VaultHandlerError::CannotInitVault => ("CANNOT_INIT_VAULT", HashMap::new()),
+                VaultHandlerError::NoSpace(path) => ("NO_SPACE", one("path", path.clone())),
+                VaultHandlerError::DataDirInsideCurrent(path) => {
+                    ("DATA_DIR_INSIDE_CURRENT", one("path", path.clone()))
+                }
+                VaultHandlerError::Cancelled => ("CANCELLED", HashMap::new()),
+                VaultHandlerError::CannotBackUpVault => ("CANNOT_BACK_UP_VAULT", HashMap::new()),
+            },
+            VaultServiceError::VaultNotFound(id) => ("VAULT_NOT_FOUND", one("id", id.to_string())),
+            VaultServiceError::VaultNameExists(name) => {
+                ("VAULT_NAME_EXISTS", one("name", name.clone()))
+            }
+            VaultServiceError::InvalidArgument(field) => {
+                ("INVALID_ARGUMENT", one("field", field.clone()))
+            }
+            VaultServiceError::Database(err) => ("DATABASE", one("error", err.clone())),
+            VaultServiceError::InvalidConfig(err) => ("INVALID_CONFIG", one("error", err.clone())),
+            VaultServiceError::PasswordRequestNotFound(request_id) => (
+                "PASSWORD_REQUEST_NOT_FOUND",
+                one("request_id", request_id.to_string()),
+            ),
+            VaultServiceError::CipherChangeNotSupported => {
+                ("CIPHER_CHANGE_NOT_SUPPORTED", HashMap::new())
+            }
+            VaultServiceError::JobNotFound(id) => ("JOB_NOT_FOUND", one("id", id.to_string())),
+            VaultServiceError::JobRunning(vault_id) => {
+                ("JOB_RUNNING", one("vault_id", vault_id.to_string()))
+            }
+            VaultServiceError::JobNotRunning(id) => ("JOB_NOT_RUNNING", one("id", id.to_string())),
+        }
+    }
+
+    fn from_error_info(reason: &str, metadata: &HashMap<String, String>) -> Option<Self> {
+        let get = |k: &str| metadata.get(k).cloned();
+        let handler = |err| Some(VaultServiceError::VaultHandlerError(err));
+        match reason {
+            "VAULT_NOT_FOUND" => Some(VaultServiceError::VaultNotFound(get("id")?.parse().ok()?)),
+            "MOUNT_POINT_BUSY" => handler(VaultHandlerError::MountPointBusy(get("mount_point")?)),
+            "MOUNT_POINT_MOUNTED" => {
+                handler(VaultHandlerError::MountPointMounted(get("mount_point")?))
+            }
+            "RENCFS_BINARY_MISSING" => {
+                handler(VaultHandlerError::RencfsBinaryMissing(get("path")?))
+            }
+            "RENCFS_UNSUPPORTED" => handler(VaultHandlerError::RencfsUnsupported(get("version")?)),
+            "WRONG_PASSWORD" => handler(VaultHandlerError::WrongPassword),
+            "PROCESS_EXITED" => handler(VaultHandlerError::ProcessExited(
+                get("exit_code").and_then(|c| c.parse().ok()),
+                get("stderr").unwrap_or_default(),
+            )),
+            "MOUNT_TIMEOUT" => handler(VaultHandlerError::MountTimeout(
+                get("timeout_secs")?.parse().ok()?,
+            )),
+            "PASSWORD_NOT_PROVIDED" => handler(VaultHandlerError::PasswordNotProvided),
+            "CREDENTIAL_UNAVAILABLE" => {
+                handler(VaultHandlerError::CredentialUnavailable(get("error")?))
+            }
+            "CANNOT_LOCK_VAULT" => handler(VaultHandlerError::CannotLockVault),
+            "CANNOT_UNLOCK_VAULT" => handler(VaultHandlerError::CannotUnlockVault),
+            "CANNOT_CHANGE_MOUNT_POINT" => handler(VaultHandlerError::CannotChangeMountPoint),
+            "CANNOT_CHANGE_DATA_DIR" => handler(VaultHandlerError::CannotChangeDataDir),
+            "VAULT_UNLOCKED" => handler(VaultHandlerError::VaultUnlocked),
+            "CANNOT_CHANGE_PASSWORD" => handler(VaultHandlerError::CannotChangePassword),
+            "DATA_DIR_NOT_EMPTY" => handler(VaultHandlerError::DataDirNotEmpty(get("path")?)),
+            "DATA_DIR_NOT_WRITABLE" => handler(VaultHandlerError::DataDirNotWritable(get("path")?)),
+            "CANNOT_INIT_VAULT" => handler(VaultHandlerError::CannotInitVault),
+            "NO_SPACE" => handler(VaultHandlerError::NoSpace(get("path")?)),
+            "DATA_DIR_INSIDE_CURRENT" => {
+                handler(VaultHandlerError::DataDirInsideCurrent(get("path")?))
+            }
+            "CANCELLED" => handler(VaultHandlerError::Cancelled),
+            "CANNOT_BACK_UP_VAULT" => handler(VaultHandlerError::CannotBackUpVault),
+            "VAULT_NAME_EXISTS" => Some(VaultServiceError::VaultNameExists(get("name")?)),
+            "INVALID_ARGUMENT" => Some(VaultServiceError::InvalidArgument(get("field")?)),
+            "DATABASE" => Some(VaultServiceError::Database(get("error")?)),
+            "INVALID_CONFIG" => Some(VaultServiceError::InvalidConfig(get("error")?)),
+            "PASSWORD_REQUEST_NOT_FOUND" => Some(VaultServiceError::PasswordRequestNotFound(
+                get("request_id")?.parse().ok()?,
+            )),
+            "CIPHER_CHANGE_NOT_SUPPORTED" => Some(VaultServiceError::CipherChangeNotSupported),
+            "JOB_NOT_FOUND" => Some(VaultServiceError::JobNotFound(get("id")?.parse().ok()?)),
+            "JOB_RUNNING" => Some(VaultServiceError::JobRunning(
+                get("vault_id")?.parse().ok()?,
+            )),
+            "JOB_NOT_RUNNING" => Some(VaultServiceError::JobNotRunning(get("id")?.parse().ok()?)),
+            _ => None,
+        }
+    }
+}
 
 impl TryFrom<Status> for VaultServiceError {
     type Error = ();
 
     fn try_from(status: Status) -> Result<Self, Self::Error> {
-        match status.code() {
-            tonic::Code::Internal => {
-                if let Some(err) = status.metadata().get(CUSTOM_ERROR) {
-                    Ok(serde_json::from_str(err.to_str().unwrap()).unwrap())
-                } else {
-                    Err(())
-                }
+        match status.get_details_error_info() {
+            Some(info) if info.domain == DOMAIN => {
+                Self::from_error_info(&info.reason, &info.metadata).ok_or(())
             }
             _ => Err(()),
         }
@@ -31,17 +337,10 @@ impl TryFrom<Status> for VaultServiceError {
 
 impl From<VaultServiceError> for Status {
     fn from(e: VaultServiceError) -> Self {
-        let mut status = Status::internal(format!("internal error: {}", e));
-
-        status.metadata_mut().insert(
-            CUSTOM_ERROR,
-            serde_json::to_string(&e)
-                .unwrap_or("could not serialize: {e}".to_string())
-                .parse()
-                .unwrap_or(tonic::metadata::MetadataValue::from_static(
-                    "unable to create metadata value",
-                )),
-        );
-        status
+        let (reason, metadata) = e.error_info();
+        let mut details = ErrorDetails::with_error_info(reason, DOMAIN, metadata);
+        details.set_localized_message(HINT_LOCALE, e.hint());
+
+        Status::with_error_details(e.code(), e.to_string(), details)
     }
 }
@@ -1,5 +1,11 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::compile_protos("../rencfs_desktop_common/proto/rencfs_desktop.proto")?;
     println!("cargo:rerun-if-changed=../rencfs_desktop_common/migrations");
+    // reported in `DaemonInfo.build_info`
+    println!("cargo:rustc-env=BUILD_TARGET={}", std::env::var("TARGET")?);
+    println!(
+        "cargo:rustc-env=BUILD_PROFILE={}",
+        std::env::var("PROFILE")?
+    );
     Ok(())
 }
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use diesel::SqliteConnection;
+use tokio::sync::Mutex;
+use tokio::time::interval;
+use tracing::{debug, error, info, instrument, warn};
+
+use rencfs_desktop_common::dao::VaultDao;
+use rencfs_desktop_common::supervisor::{record_action, VaultActionKind};
+use rencfs_desktop_common::vault_events::{VaultEvent, VaultEventHub};
+
+use crate::handlers::VaultHandlers;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How long before locking the app is warned, at most half the timeout.
+const WARN_BEFORE: Duration = Duration::from_secs(60);
+/// Shorter timeouts would lock vaults while their files are only being read slowly.
+pub const MIN_AUTO_LOCK_SECS: u32 = 60;
+
+/// What was last seen of an unlocked vault.
+struct Activity {
+    io_chars: Option<u64>,
+    last_active: Instant,
+    warned: bool,
+}
+
+/// Locks the vaults which have `auto_lock_secs` set once they are idle that long.
+///
+/// A vault is active while its rencfs process reads or writes anything, which it does for every
+/// request made to the mount, or while a request to the daemon uses it, like a job.
+pub struct AutoLock {
+    handlers: VaultHandlers,
+    db_conn: Arc<Mutex<SqliteConnection>>,
+    events: VaultEventHub,
+    activity: std::sync::Mutex<HashMap<u32, Activity>>,
+}
+
+impl AutoLock {
+    pub fn new(
+        handlers: VaultHandlers,
+        db_conn: Arc<Mutex<SqliteConnection>>,
+        events: VaultEventHub,
+    ) -> Self {
+        Self {
+            handlers,
+            db_conn,
+            events,
+            activity: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The vault is idle from now on, if it's unlocked.
+    pub fn keep_unlocked(&self, id: u32) {
+        if let Some(activity) = self.activity.lock().unwrap().get_mut(&id) {
+            info!(id, "Keeping vault unlocked");
+            activity.last_active = Instant::now();
+            activity.warned = false;
+        }
+    }
+
+    fn check(&self, id: u32, timeout: Duration) {
+        let io_chars = match self.handlers.try_acquire(id) {
+            Some(mut handler) => match handler.pid() {
+                Some(pid) => io_chars(pid),
+                None => {
+                    self.activity.lock().unwrap().remove(&id);
+                    return;
+                }
+            },
+            // busy with a request, like moving its data dir
+            None => None,
+        };
+
+        let mut all = self.activity.lock().unwrap();
+        let activity = all.entry(id).or_insert_with(|| Activity {
+            io_chars,
+            last_active: Instant::now(),
+            warned: false,
+        });
+        if io_chars.is_none() || io_chars != activity.io_chars {
+            activity.io_chars = io_chars;
+            activity.last_active = Instant::now();
+            activity.warned = false;
+            return;
+        }
+
+        let idle = activity.last_active.elapsed();
+        if idle >= timeout {
+            all.remove(&id);
+            drop(all);
+            info!(id, idle_secs = idle.as_secs(), "Vault is idle, locking it");
+            tokio::spawn(lock(id, idle, self.handlers.clone(), self.db_conn.clone()));
+            return;
+        }
+        let left = timeout - idle;
+        if !activity.warned && left <= WARN_BEFORE.min(timeout / 2) {
+            activity.warned = true;
+            debug!(id, seconds_left = left.as_secs(), "Warning about auto lock");
+            self.events.publish(VaultEvent::AutoLockWarning {
+                id,
+                seconds_left: left.as_secs() as u32,
+            });
+        }
+    }
+}
+
+/// Checks the unlocked vaults every few seconds, until the daemon stops.
+#[instrument(skip_all)]
+pub async fn run(auto_lock: Arc<AutoLock>) {
+    let mut ticker = interval(CHECK_INTERVAL);
+    loop {
+        ticker.tick().await;
+
+        let vaults = {
+            let mut guard = auto_lock.db_conn.lock().await;
+            match VaultDao::new(&mut guard).get_all(None) {
+                Ok(vaults) => vaults,
+                Err(err) => {
+                    warn!(err = %err, "Cannot get vaults");
+                    continue;
+                }
+            }
+        };
+        let mut checked = vec![];
+        for vault in vaults {
+            if vault.locked == 1 || vault.auto_lock_secs <= 0 {
+                continue;
+            }
+            let id = vault.id as u32;
+            auto_lock.check(id, Duration::from_secs(vault.auto_lock_secs as u64));
+            checked.push(id);
+        }
+        // locked, deleted, or not locking automatically anymore
+        auto_lock
+            .activity
+            .lock()
+            .unwrap()
+            .retain(|id, _| checked.contains(id));
+    }
+}
+
+/// Shared with a lock the user asked for meanwhile.
+async fn lock(
+    id: u32,
+    idle: Duration,
+    handlers: VaultHandlers,
+    db_conn: Arc<Mutex<SqliteConnection>>,
+) {
+    match handlers.lock(id).await {
+        Ok(_) => {
+            record_action(
+                &db_conn,
+                id,
+                VaultActionKind::AutoLocked,
+                format!("idle for {}s", idle.as_secs()),
+            )
+            .await
+        }
+        Err(err) => error!(id, err = %err, "Cannot lock idle vault"),
+    }
+}
+
+/// `None` when it can't be known, the vault is then taken as active.
+fn io_chars(pid: u32) -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    return match rencfs_desktop_common::platform::process::io_chars(pid) {
+        Ok(chars) => Some(chars),
+        Err(err) => {
+            warn!(pid, err = %err, "Cannot read rencfs I/O");
+            None
+        }
+    };
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = pid;
+        None
+    }
+}
@@ -0,0 +1,81 @@
+//! Command line arguments, and printing what the commands report.
+
+use std::fmt::Display;
+
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Runs rencfs for the vaults of the current user")]
+pub struct Cli {
+    /// Print JSON instead of text
+    #[arg(long, global = true)]
+    pub json: bool,
+    /// `run` if not set
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the daemon, this is the default
+    Run(RunArgs),
+    /// Show if the daemon is running, exits with 3 if it isn't
+    Status,
+    /// Lock all vaults and stop the running daemon
+    Stop(StopArgs),
+    /// Check that what the daemon needs is in place
+    Doctor,
+    /// Write the systemd user units to run the daemon as a service
+    #[cfg(target_os = "linux")]
+    InstallSystemd(InstallSystemdArgs),
+}
+
+#[derive(Debug, Default, Args)]
+pub struct RunArgs {
+    /// Stay attached to the terminal, this is the default
+    #[arg(long, conflicts_with = "background")]
+    pub foreground: bool,
+    /// Detach from the terminal, the output goes to `daemon.out` and `daemon.err` in the logs dir
+    #[arg(long)]
+    pub background: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct StopArgs {
+    /// Seconds to wait for the daemon to lock the vaults and exit
+    #[arg(long, default_value_t = 60)]
+    pub timeout: u64,
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Debug, Args)]
+pub struct InstallSystemdArgs {
+    /// Also write a socket unit, so systemd starts the daemon on the first connection
+    #[arg(long)]
+    pub socket: bool,
+    /// Overwrite the units if they exist
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Prints `report` as text or as one line of JSON.
+pub fn print<T: Serialize + Display>(json: bool, report: &T) {
+    if json {
+        match serde_json::to_string(report) {
+            Ok(report) => println!("{report}"),
+            Err(err) => eprintln!("Cannot serialize the report: {err}"),
+        }
+    } else {
+        println!("{report}");
+    }
+}
+
+/// Prints why a command failed, to stdout like the reports when it's JSON.
+pub fn print_error(json: bool, err: impl Display) {
+    if json {
+        println!("{}", serde_json::json!({ "error": err.to_string() }));
+    } else {
+        eprintln!("Error: {err}");
+    }
+}
@@ -0,0 +1,290 @@
+//! Commands talking to the daemon which is already running.
+
+// tonic::Status is large, but it's what the generated client returns
+#![allow(clippy::result_large_err)]
+
+use std::fmt;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tonic::transport::Channel;
+
+use rencfs_desktop_common::config;
+use rencfs_desktop_common::ipc;
+use rencfs_desktop_common::protocol;
+
+use crate::cli::{self, StopArgs};
+use crate::vault_service::vault_service_client::VaultServiceClient;
+use crate::vault_service::{
+    DaemonInfo, GetDaemonInfoRequest, ListVaultsRequest, RencfsStatus, StopRequest,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long `run --background` waits for the daemon to answer.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+/// LSB exit code of `status` when the daemon is not running.
+const EXIT_NOT_RUNNING: u8 = 3;
+
+type Client = VaultServiceClient<Channel>;
+
+/// `None` if nothing answers on `listen`.
+async fn connect() -> Option<Client> {
+    match tokio::time::timeout(CONNECT_TIMEOUT, ipc::connect()).await {
+        Ok(Ok(channel)) => Some(VaultServiceClient::new(channel)),
+        _ => None,
+    }
+}
+
+async fn daemon_info(client: &mut Client) -> Result<DaemonInfo, tonic::Status> {
+    let info = client
+        .get_daemon_info(GetDaemonInfoRequest {
+            protocol_version: protocol::PROTOCOL_VERSION,
+        })
+        .await?;
+    Ok(info.into_inner())
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    running: bool,
+    listen: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    daemon: Option<DaemonStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct DaemonStatus {
+    pid: u32,
+    version: String,
+    protocol_version: u32,
+    build_info: String,
+    uptime_secs: u64,
+    rencfs_path: Option<String>,
+    rencfs_version: Option<String>,
+    /// `ok`, `missing`, `unknown_version` or `unsupported`
+    rencfs_status: String,
+    vaults: usize,
+    unlocked_vaults: usize,
+}
+
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(daemon) = &self.daemon else {
+            return write!(f, "not running, nothing answers on {}", self.listen);
+        };
+        writeln!(f, "running, pid {}", daemon.pid)?;
+        writeln!(f, "  listen:  {}", self.listen)?;
+        writeln!(
+            f,
+            "  version: {}, protocol {}, {}",
+            daemon.version, daemon.protocol_version, daemon.build_info
+        )?;
+        writeln!(f, "  uptime:  {}", format_duration(daemon.uptime_secs))?;
+        writeln!(
+            f,
+            "  rencfs:  {} at {} ({})",
+            daemon
+                .rencfs_version
+                .as_deref()
+                .unwrap_or("unknown version"),
+            daemon.rencfs_path.as_deref().unwrap_or("unknown path"),
+            daemon.rencfs_status.replace('_', " ")
+        )?;
+        write!(
+            f,
+            "  vaults:  {} unlocked of {}",
+            daemon.unlocked_vaults, daemon.vaults
+        )
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    match (hours, minutes) {
+        (0, 0) => format!("{secs}s"),
+        (0, _) => format!("{minutes}m {secs}s"),
+        _ => format!("{hours}h {minutes}m {secs}s"),
+    }
+}
+
+pub async fn status(json: bool) -> ExitCode {
+    let listen = config::current().listen.clone();
+    let Some(mut client) = connect().await else {
+        cli::print(
+            json,
+            &StatusReport {
+                running: false,
+                listen,
+                daemon: None,
+            },
+        );
+        return ExitCode::from(EXIT_NOT_RUNNING);
+    };
+    let status = async {
+        let info = daemon_info(&mut client).await?;
+        let vaults = client
+            .list_vaults(ListVaultsRequest { limit: None })
+            .await?
+            .into_inner()
+            .vaults;
+        let rencfs_status = RencfsStatus::try_from(info.rencfs_status)
+            .map(|s| {
+                s.as_str_name()
+                    .trim_start_matches("RENCFS_STATUS_")
+                    .to_lowercase()
+            })
+            .unwrap_or_else(|_| "unknown".to_string());
+        Ok::<_, tonic::Status>(DaemonStatus {
+            pid: info.pid,
+            version: info.daemon_version,
+            protocol_version: info.protocol_version,
+            build_info: info.build_info,
+            uptime_secs: info.uptime_secs,
+            rencfs_path: info.rencfs_path,
+            rencfs_version: info.rencfs_version,
+            rencfs_status,
+            vaults: vaults.len(),
+            unlocked_vaults: vaults.iter().filter(|v| !v.locked).count(),
+        })
+    };
+    match status.await {
+        Ok(daemon) => {
+            cli::print(
+                json,
+                &StatusReport {
+                    running: true,
+                    listen,
+                    daemon: Some(daemon),
+                },
+            );
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            cli::print_error(json, format!("the daemon answered with {}", err.message()));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StopReport {
+    was_running: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+}
+
+impl fmt::Display for StopReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pid {
+            Some(pid) => write!(f, "stopped, pid {pid}"),
+            None => write!(f, "not running"),
+        }
+    }
+}
+
+pub async fn stop(args: StopArgs, json: bool) -> ExitCode {
+    let Some(mut client) = connect().await else {
+        cli::print(
+            json,
+            &StopReport {
+                was_running: false,
+                pid: None,
+            },
+        );
+        return ExitCode::SUCCESS;
+    };
+    let pid = match daemon_info(&mut client).await {
+        Ok(info) => info.pid,
+        Err(err) => {
+            cli::print_error(json, format!("the daemon answered with {}", err.message()));
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(err) = client.stop(StopRequest {}).await {
+        cli::print_error(json, format!("cannot stop the daemon: {}", err.message()));
+        return ExitCode::FAILURE;
+    }
+    drop(client);
+
+    let timeout = Duration::from_secs(args.timeout);
+    let started = Instant::now();
+    while is_running(pid).await {
+        if started.elapsed() >= timeout {
+            cli::print_error(
+                json,
+                format!("the daemon with pid {pid} is still running after {timeout:?}"),
+            );
+            return ExitCode::FAILURE;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    cli::print(
+        json,
+        &StopReport {
+            was_running: true,
+            pid: Some(pid),
+        },
+    );
+    ExitCode::SUCCESS
+}
+
+// the socket goes away before the vaults are locked, the process is what matters
+#[cfg(target_os = "linux")]
+async fn is_running(pid: u32) -> bool {
+    rencfs_desktop_common::platform::process::is_alive(pid)
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn is_running(_pid: u32) -> bool {
+    connect().await.is_some()
+}
+
+#[derive(Debug, Serialize)]
+pub struct StartReport {
+    pid: u32,
+    listen: String,
+    logs_dir: PathBuf,
+}
+
+impl fmt::Display for StartReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "started in the background, pid {}, output in {}",
+            self.pid,
+            self.logs_dir.display()
+        )
+    }
+}
+
+/// Waits for the daemon started by `run --background` to answer.
+pub async fn wait_started(json: bool, logs_dir: PathBuf) -> ExitCode {
+    let started = Instant::now();
+    while started.elapsed() < START_TIMEOUT {
+        if let Some(mut client) = connect().await {
+            if let Ok(info) = daemon_info(&mut client).await {
+                cli::print(
+                    json,
+                    &StartReport {
+                        pid: info.pid,
+                        listen: config::current().listen.clone(),
+                        logs_dir,
+                    },
+                );
+                return ExitCode::SUCCESS;
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    cli::print_error(
+        json,
+        format!(
+            "the daemon didn't answer in {START_TIMEOUT:?}, check the output in {}",
+            logs_dir.display()
+        ),
+    );
+    ExitCode::FAILURE
+}
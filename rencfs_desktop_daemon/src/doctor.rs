@@ -0,0 +1,301 @@
+//! `doctor`, checks what the daemon needs without starting it.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+
+use diesel::dsl::sql;
+use diesel::sql_types::Text;
+use diesel::{Connection, RunQueryDsl, SqliteConnection};
+use diesel_migrations::MigrationHarness;
+use serde::Serialize;
+
+use rencfs_desktop_common::config::{self, DaemonConfig};
+use rencfs_desktop_common::directories::{get_config_dir, get_data_dir, get_logs_dir};
+use rencfs_desktop_common::persistence;
+use rencfs_desktop_common::rencfs::{self, RencfsBinary};
+use rencfs_desktop_common::MIGRATIONS;
+
+use crate::cli;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    /// The daemon works, but not as well as it could.
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    name: String,
+    status: CheckStatus,
+    message: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: CheckStatus, message: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DoctorReport {
+    ok: bool,
+    checks: Vec<Check>,
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Ok => "ok",
+                CheckStatus::Warn => "warn",
+                CheckStatus::Fail => "FAIL",
+            };
+            writeln!(f, "{status:<4}  {:<width$}  {}", check.name, check.message)?;
+        }
+        if self.ok {
+            write!(f, "No problems found")
+        } else {
+            write!(f, "The daemon can't work until the failed checks are fixed")
+        }
+    }
+}
+
+/// Exits with failure if any check failed, warnings are fine.
+pub async fn run(json: bool) -> ExitCode {
+    let mut checks = vec![check_config()];
+    // falls back to the defaults if the file is invalid, so the other checks can still run
+    let config = config::current();
+    #[cfg(target_os = "linux")]
+    {
+        checks.push(check_dev_fuse());
+        checks.push(check_fusermount());
+    }
+    checks.push(check_rencfs(&config).await);
+    checks.push(check_database());
+    checks.extend(check_dirs(&config));
+
+    let ok = checks.iter().all(|c| c.status != CheckStatus::Fail);
+    cli::print(json, &DoctorReport { ok, checks });
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn check_config() -> Check {
+    let path = DaemonConfig::path();
+    match DaemonConfig::load() {
+        Ok(_) if path.exists() => Check::new("config", CheckStatus::Ok, path.display().to_string()),
+        Ok(_) => Check::new(
+            "config",
+            CheckStatus::Ok,
+            format!("{} doesn't exist, using the defaults", path.display()),
+        ),
+        Err(err) => Check::new("config", CheckStatus::Fail, err.to_string()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_dev_fuse() -> Check {
+    const DEV_FUSE: &str = "/dev/fuse";
+
+    // opening it doesn't mount anything, the connection is dropped with the file
+    match fs::OpenOptions::new().read(true).write(true).open(DEV_FUSE) {
+        Ok(_) => Check::new(DEV_FUSE, CheckStatus::Ok, "can be opened"),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Check::new(
+            DEV_FUSE,
+            CheckStatus::Fail,
+            "not found, install fuse3 and load the fuse kernel module",
+        ),
+        Err(err) => Check::new(
+            DEV_FUSE,
+            CheckStatus::Fail,
+            format!("cannot be opened: {err}"),
+        ),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_fusermount() -> Check {
+    use std::os::unix::fs::PermissionsExt;
+
+    use rencfs_desktop_common::platform::mounts::FUSERMOUNT;
+
+    let Some(path) = rencfs::find_in_path(FUSERMOUNT) else {
+        return Check::new(
+            FUSERMOUNT,
+            CheckStatus::Fail,
+            "not found in PATH, install fuse3",
+        );
+    };
+    // unprivileged users can mount and unmount only through the setuid binary
+    let setuid = path
+        .metadata()
+        .is_ok_and(|m| m.permissions().mode() & 0o4000 != 0);
+    if setuid {
+        Check::new(FUSERMOUNT, CheckStatus::Ok, path.display().to_string())
+    } else {
+        Check::new(
+            FUSERMOUNT,
+            CheckStatus::Fail,
+            format!("{} is not setuid root", path.display()),
+        )
+    }
+}
+
+async fn check_rencfs(config: &DaemonConfig) -> Check {
+    let binary = RencfsBinary::resolve(config.rencfs_path.as_deref()).await;
+    let path = binary.display_path();
+    match binary.status {
+        rencfs::Status::Ok(version) => {
+            Check::new("rencfs", CheckStatus::Ok, format!("{version} at {path}"))
+        }
+        rencfs::Status::Missing => {
+            Check::new("rencfs", CheckStatus::Fail, format!("not found at {path}"))
+        }
+        rencfs::Status::UnknownVersion => Check::new(
+            "rencfs",
+            CheckStatus::Warn,
+            format!("cannot get the version of {path}"),
+        ),
+        rencfs::Status::Unsupported(version) => Check::new(
+            "rencfs",
+            CheckStatus::Fail,
+            format!(
+                "{version} at {path} is not supported, use {}",
+                rencfs::supported_versions()
+            ),
+        ),
+    }
+}
+
+fn check_database() -> Check {
+    let Some(url) = persistence::database_url() else {
+        return Check::new("database", CheckStatus::Fail, "DATABASE_URL is not set");
+    };
+    // connecting would create it
+    if !Path::new(&url).exists() {
+        return Check::new(
+            "database",
+            CheckStatus::Ok,
+            format!("{url} doesn't exist, it's created on start"),
+        );
+    }
+    let mut conn = match SqliteConnection::establish(&url) {
+        Ok(conn) => conn,
+        Err(err) => {
+            return Check::new(
+                "database",
+                CheckStatus::Fail,
+                format!("cannot open {url}: {err}"),
+            )
+        }
+    };
+    let integrity_check = sql::<Text>("integrity_check FROM pragma_integrity_check");
+    let problems = match diesel::select(integrity_check).load::<String>(&mut conn) {
+        Ok(rows) => rows
+            .into_iter()
+            .filter(|row| row != "ok")
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            return Check::new(
+                "database",
+                CheckStatus::Fail,
+                format!("cannot check {url}: {err}"),
+            )
+        }
+    };
+    if !problems.is_empty() {
+        return Check::new(
+            "database",
+            CheckStatus::Fail,
+            format!("{url} is corrupted: {}", problems.join("; ")),
+        );
+    }
+    match conn.has_pending_migration(MIGRATIONS) {
+        Ok(false) => Check::new("database", CheckStatus::Ok, url),
+        Ok(true) => Check::new(
+            "database",
+            CheckStatus::Ok,
+            format!("{url}, it's migrated on the next start"),
+        ),
+        Err(err) => Check::new(
+            "database",
+            CheckStatus::Fail,
+            format!("cannot read the migrations of {url}: {err}"),
+        ),
+    }
+}
+
+/// The dirs the daemon writes to.
+fn check_dirs(config: &DaemonConfig) -> Vec<Check> {
+    let mut dirs = vec![
+        ("config dir", get_config_dir()),
+        ("data dir", get_data_dir()),
+        ("logs dir", get_logs_dir()),
+        ("rencfs logs dir", config.rencfs_logs_dir.clone()),
+    ];
+    #[cfg(target_os = "linux")]
+    if let Some(parent) = Path::new(&config.listen).parent() {
+        dirs.push(("socket dir", parent.to_path_buf()));
+    }
+    dirs.iter()
+        .map(|(name, path)| check_dir(name, path))
+        .collect()
+}
+
+fn check_dir(name: &str, path: &Path) -> Check {
+    if !path.exists() {
+        return Check::new(
+            name,
+            CheckStatus::Ok,
+            format!("{} doesn't exist, it's created when needed", path.display()),
+        );
+    }
+    if !path.is_dir() {
+        return Check::new(
+            name,
+            CheckStatus::Fail,
+            format!("{} is not a directory", path.display()),
+        );
+    }
+    let probe = path.join(".rencfs_desktop_doctor");
+    if let Err(err) = fs::write(&probe, b"") {
+        return Check::new(
+            name,
+            CheckStatus::Fail,
+            format!("cannot write to {}: {err}", path.display()),
+        );
+    }
+    let _ = fs::remove_file(&probe);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        // others could replace the DB, the logs or the socket
+        let mode = path.metadata().map(|m| m.permissions().mode()).unwrap_or(0);
+        if mode & 0o022 != 0 {
+            return Check::new(
+                name,
+                CheckStatus::Warn,
+                format!(
+                    "{} can be written by other users, mode {:o}",
+                    path.display(),
+                    mode & 0o777
+                ),
+            );
+        }
+    }
+    Check::new(name, CheckStatus::Ok, path.display().to_string())
+}
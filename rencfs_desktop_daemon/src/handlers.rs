@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use diesel::SqliteConnection;
+use tokio::sync::{watch, Mutex, OwnedMutexGuard, RwLock};
+use tracing::{info, instrument};
+
+use rencfs_desktop_common::credentials::Credentials;
+use rencfs_desktop_common::rencfs::RencfsBinary;
+use rencfs_desktop_common::vault_events::VaultEventHub;
+use rencfs_desktop_common::vault_handler::{VaultHandler, VaultHandlerError};
+
+/// A request which is the same every time it's made, so one still pending can answer the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Lock,
+    /// With the password from the credential provider, a typed one is never shared.
+    Unlock {
+        remember: bool,
+    },
+}
+
+/// The last request queued for a vault, if it can be joined.
+struct Pending {
+    op: Op,
+    result: watch::Receiver<Option<Result<(), VaultHandlerError>>>,
+}
+
+/// The handlers of the vaults used so far, each behind its own lock.
+///
+/// Requests for different vaults run concurrently, the ones for the same vault one at a time in the
+/// order they came, as the lock is fair. A lock or unlock made while the same one is the last queued
+/// for the vault waits for it and gets its result, instead of running again.
+#[derive(Clone)]
+pub struct VaultHandlers {
+    handlers: Arc<std::sync::Mutex<HashMap<u32, Arc<Mutex<VaultHandler>>>>>,
+    pending: Arc<std::sync::Mutex<HashMap<u32, Pending>>>,
+    db_conn: Arc<Mutex<SqliteConnection>>,
+    events: VaultEventHub,
+    rencfs: Arc<RwLock<RencfsBinary>>,
+    credentials: Arc<Credentials>,
+}
+
+impl VaultHandlers {
+    pub fn new(
+        db_conn: Arc<Mutex<SqliteConnection>>,
+        events: VaultEventHub,
+        rencfs: Arc<RwLock<RencfsBinary>>,
+        credentials: Arc<Credentials>,
+    ) -> Self {
+        Self {
+            handlers: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            pending: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            db_conn,
+            events,
+            rencfs,
+            credentials,
+        }
+    }
+
+    /// Replaces the handler of `id`, like with one adopting a running rencfs.
+    pub fn insert(&self, id: u32, handler: VaultHandler) {
+        self.handlers
+            .lock()
+            .unwrap()
+            .insert(id, Arc::new(Mutex::new(handler)));
+    }
+
+    /// Requests already waiting for the handler still get it, they fail as the vault is gone.
+    pub fn remove(&self, id: u32) {
+        self.handlers.lock().unwrap().remove(&id);
+        self.pending.lock().unwrap().remove(&id);
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.handlers.lock().unwrap().contains_key(&id)
+    }
+
+    /// The handlers so far, to be locked one by one.
+    pub fn all(&self) -> Vec<(u32, Arc<Mutex<VaultHandler>>)> {
+        self.handlers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, handler)| (*id, handler.clone()))
+            .collect()
+    }
+
+    /// Waits for the requests of the vault before this one, the handler is created the first time.
+    pub async fn acquire(&self, id: u32) -> OwnedMutexGuard<VaultHandler> {
+        self.pending.lock().unwrap().remove(&id);
+        self.handler(id).lock_owned().await
+    }
+
+    /// Like [`VaultHandlers::acquire`], if the vault has a handler already.
+    pub async fn acquire_existing(&self, id: u32) -> Option<OwnedMutexGuard<VaultHandler>> {
+        self.pending.lock().unwrap().remove(&id);
+        let handler = self.handlers.lock().unwrap().get(&id).cloned()?;
+        Some(handler.lock_owned().await)
+    }
+
+    /// The handler of `id` if it has one and nothing is using or waiting for it.
+    pub fn try_acquire(&self, id: u32) -> Option<OwnedMutexGuard<VaultHandler>> {
+        let handler = self.handlers.lock().unwrap().get(&id).cloned()?;
+        handler.try_lock_owned().ok()
+    }
+
+    pub async fn lock(&self, id: u32) -> Result<(), VaultHandlerError> {
+        self.run_once(id, Op::Lock, |mut handler| async move {
+            handler.lock(None).await
+        })
+        .await
+    }
+
+    /// Unlocks with the password from the credential provider of the vault.
+    pub async fn unlock(&self, id: u32, remember: bool) -> Result<(), VaultHandlerError> {
+        self.run_once(id, Op::Unlock { remember }, move |mut handler| async move {
+            handler.unlock(None, remember).await
+        })
+        .await
+    }
+
+    fn handler(&self, id: u32) -> Arc<Mutex<VaultHandler>> {
+        self.handlers
+            .lock()
+            .unwrap()
+            .entry(id)
+            .or_insert_with(|| {
+                Arc::new(Mutex::new(VaultHandler::new(
+                    id,
+                    self.db_conn.clone(),
+                    self.events.clone(),
+                    self.rencfs.clone(),
+                    self.credentials.clone(),
+                )))
+            })
+            .clone()
+    }
+
+    /// Runs `f` in turn, or gets the result of the same `op` if it's the last one queued and
+    /// still pending.
+    #[instrument(skip(self, f))]
+    async fn run_once<F, Fut>(&self, id: u32, op: Op, f: F) -> Result<(), VaultHandlerError>
+    where
+        F: FnOnce(OwnedMutexGuard<VaultHandler>) -> Fut,
+        Fut: Future<Output = Result<(), VaultHandlerError>>,
+    {
+        let (tx, rx) = loop {
+            let joined = {
+                let mut pending = self.pending.lock().unwrap();
+                match pending.get(&id) {
+                    Some(p) if p.op == op => p.result.clone(),
+                    _ => {
+                        let (tx, rx) = watch::channel(None);
+                        let result = rx.clone();
+                        pending.insert(id, Pending { op, result });
+                        break (tx, rx);
+                    }
+                }
+            };
+            info!("Joining the same pending request");
+            let mut result = joined.clone();
+            if let Ok(res) = result.wait_for(Option::is_some).await {
+                return res.clone().expect("waited for it");
+            }
+            // it was dropped before it ended, like when its client went away
+            self.remove_pending(id, &joined);
+        };
+
+        let res = f(self.handler(id).lock_owned().await).await;
+        self.remove_pending(id, &rx);
+        tx.send_replace(Some(res.clone()));
+        res
+    }
+
+    /// Later requests queue after `result`, unless another one replaced it already.
+    fn remove_pending(
+        &self,
+        id: u32,
+        result: &watch::Receiver<Option<Result<(), VaultHandlerError>>>,
+    ) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending
+            .get(&id)
+            .is_some_and(|p| p.result.same_channel(result))
+        {
+            pending.remove(&id);
+        }
+    }
+}
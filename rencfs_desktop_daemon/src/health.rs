@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use diesel::{sql_query, RunQueryDsl, SqliteConnection};
+use diesel_migrations::MigrationHarness;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+use tokio::time::{interval, Duration};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tracing::{info, instrument, warn};
+
+use rencfs_desktop_common::dao::VaultDao;
+use rencfs_desktop_common::vault_events::{VaultEvent, VaultEventHub};
+use rencfs_desktop_common::MIGRATIONS;
+
+use crate::handlers::VaultHandlers;
+use crate::vault_service::vault_service_server::VaultServiceServer;
+use crate::vault_service::MyVaultService;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Health service name for a vault, `SERVING` while its rencfs process is alive and mounted.
+pub fn vault_service_name(id: u32) -> String {
+    format!("rencfs_desktop.vault.{id}")
+}
+
+/// Keeps the health statuses up to date, the overall one (`""`) and the `VaultService` one
+/// reflect if the DB is reachable and migrated, and there is one per vault.
+#[instrument(skip_all)]
+pub async fn run(
+    mut reporter: HealthReporter,
+    db_conn: Arc<Mutex<SqliteConnection>>,
+    events: VaultEventHub,
+    handlers: VaultHandlers,
+) {
+    let mut rx = events.subscribe();
+    let mut ticker = interval(CHECK_INTERVAL);
+    let mut known = HashSet::new();
+
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Ok(VaultEvent::Deleted { id }) => {
+                    known.remove(&id);
+                    reporter.clear_service_status(&vault_service_name(id)).await;
+                    continue;
+                }
+                Ok(VaultEvent::Created { .. })
+                | Ok(VaultEvent::Unlocked { .. })
+                | Ok(VaultEvent::Locked { .. })
+                | Ok(VaultEvent::ProcessDied { .. }) => {}
+                Ok(_) => continue,
+                Err(RecvError::Lagged(n)) => warn!(skipped = n, "Health checker skipped events"),
+                Err(RecvError::Closed) => return,
+            },
+            _ = ticker.tick() => {}
+        }
+
+        let mut guard = db_conn.lock().await;
+        let db_status = match check_db(&mut guard) {
+            Ok(_) => ServingStatus::Serving,
+            Err(err) => {
+                warn!(err, "Daemon is not healthy");
+                ServingStatus::NotServing
+            }
+        };
+        reporter.set_service_status("", db_status).await;
+        if db_status == ServingStatus::Serving {
+            reporter
+                .set_serving::<VaultServiceServer<MyVaultService>>()
+                .await;
+        } else {
+            reporter
+                .set_not_serving::<VaultServiceServer<MyVaultService>>()
+                .await;
+            continue;
+        }
+
+        let vaults = match VaultDao::new(&mut guard).get_all(None) {
+            Ok(vaults) => vaults,
+            Err(err) => {
+                warn!(err = %err, "Cannot get vaults");
+                continue;
+            }
+        };
+        drop(guard);
+        for vault in vaults {
+            let id = vault.id as u32;
+            let process_alive = match handlers.try_acquire(id) {
+                Some(mut handler) => handler.pid().is_some_and(is_alive),
+                // busy locking or unlocking it, the DB and the mount tell how far it got
+                None if handlers.contains(id) => true,
+                None => false,
+            };
+            // the DB is updated before the events are sent
+            let serving = vault.locked == 0 && process_alive && is_mounted(&vault.mount_point);
+            let status = if serving {
+                ServingStatus::Serving
+            } else {
+                ServingStatus::NotServing
+            };
+            if known.insert(id) {
+                info!(id, "Reporting vault health");
+            }
+            reporter
+                .set_service_status(vault_service_name(id), status)
+                .await;
+        }
+    }
+}
+
+fn check_db(conn: &mut SqliteConnection) -> Result<(), String> {
+    sql_query("SELECT 1")
+        .execute(conn)
+        .map_err(|err| format!("database is not reachable: {err}"))?;
+    match conn.has_pending_migration(MIGRATIONS) {
+        Ok(false) => Ok(()),
+        Ok(true) => Err("database has pending migrations".to_string()),
+        Err(err) => Err(format!("cannot check migrations: {err}")),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_mounted(mount_point: &str) -> bool {
+    rencfs_desktop_common::platform::mounts::is_fuse_mounted(std::path::Path::new(mount_point))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_mounted(_mount_point: &str) -> bool {
+    true
+}
+
+#[cfg(target_os = "linux")]
+fn is_alive(pid: u32) -> bool {
+    rencfs_desktop_common::platform::process::is_alive(pid)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_alive(_pid: u32) -> bool {
+    true
+}
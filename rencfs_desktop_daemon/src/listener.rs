@@ -0,0 +1,63 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+
+use tokio::net::{UnixListener, UnixStream};
+use tokio_stream::wrappers::UnixListenerStream;
+use tokio_stream::{Stream, StreamExt};
+use tracing::{info, instrument, warn};
+
+/// Binds the daemon socket, readable and writable only by the current user.
+#[instrument(err)]
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) => {
+            // only a socket left by a previous run is ours to remove
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ));
+            }
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("another daemon is listening on {}", path.display()),
+                ));
+            }
+            info!("Removing stale socket");
+            fs::remove_file(path)?;
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    Ok(listener)
+}
+
+/// Incoming connections, dropping the ones not coming from the user running the daemon.
+pub fn incoming(listener: UnixListener) -> impl Stream<Item = io::Result<UnixStream>> {
+    UnixListenerStream::new(listener).filter(|conn| match conn {
+        Ok(stream) => is_peer_owner(stream),
+        Err(_) => true,
+    })
+}
+
+fn is_peer_owner(stream: &UnixStream) -> bool {
+    let uid = unsafe { libc::getuid() };
+    match stream.peer_cred() {
+        Ok(cred) if cred.uid() == uid => true,
+        Ok(cred) => {
+            warn!(peer_uid = cred.uid(), pid = ?cred.pid(), "Rejecting connection from other user");
+            false
+        }
+        Err(err) => {
+            warn!(err = %err, "Cannot get peer credentials, rejecting connection");
+            false
+        }
+    }
+}
@@ -5,131 +5,167 @@ extern crate directories;
 use std::backtrace::Backtrace;
 use std::fs::OpenOptions;
 use std::panic::catch_unwind;
-use std::str::FromStr;
+use std::process::ExitCode;
 use std::sync::Arc;
-use std::thread;
 
+use clap::Parser;
 #[cfg(target_os = "linux")]
-use daemonize::Daemonize;
+use daemonize::{Daemonize, Outcome};
 use dotenvy::dotenv;
-use rencfs_desktop_common::is_debug;
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex, Notify, RwLock};
 use tokio::task;
 use tonic::transport::Server;
-use tracing::{error, info, instrument, Level};
+use tracing::{error, info, instrument, warn};
 
+use rencfs_desktop_common::config;
+use rencfs_desktop_common::credentials::Credentials;
 use rencfs_desktop_common::directories::{get_data_dir, get_logs_dir};
+use rencfs_desktop_common::jobs::JobManager;
 use rencfs_desktop_common::persistence::run_migrations;
+use rencfs_desktop_common::rencfs::RencfsBinary;
+use rencfs_desktop_common::vault_events::VaultEventHub;
 
+use crate::cli::{Cli, Command, RunArgs};
 use crate::vault_service::vault_service_server::VaultServiceServer;
 use crate::vault_service::MyVaultService;
 
+mod auto_lock;
+mod cli;
+mod client;
+mod doctor;
+mod handlers;
+mod health;
+#[cfg(target_os = "linux")]
+mod listener;
+#[cfg(target_os = "linux")]
+mod pidfile;
+#[cfg(target_os = "linux")]
+mod reconcile;
+mod restarter;
+mod shutdown;
+#[cfg(target_os = "linux")]
+mod systemd;
 mod vault_service;
 
-#[tokio::main]
-async fn main() {
-    let path = dotenv();
-    match path {
-        Ok(path) => println!("Loaded env file from {:?}", path),
-        Err(err) => eprintln!("Error loading env file: {:?}", err),
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let env_file = dotenv();
+
+    // doctor reports an invalid config itself
+    if let Err(err) = config::reload() {
+        if !matches!(cli.command, Some(Command::Doctor)) {
+            cli::print_error(cli.json, format!("cannot load config: {err}"));
+            return ExitCode::FAILURE;
+        }
+    }
+
+    match cli.command.unwrap_or(Command::Run(RunArgs::default())) {
+        Command::Run(args) => {
+            if !cli.json {
+                match env_file {
+                    Ok(path) => println!("Loaded env file from {:?}", path),
+                    Err(err) => eprintln!("Error loading env file: {:?}", err),
+                }
+            }
+            run(args, cli.json)
+        }
+        Command::Status => runtime().block_on(client::status(cli.json)),
+        Command::Stop(args) => runtime().block_on(client::stop(args, cli.json)),
+        Command::Doctor => runtime().block_on(doctor::run(cli.json)),
+        #[cfg(target_os = "linux")]
+        Command::InstallSystemd(args) => systemd::install(args, cli.json),
     }
+}
 
-    if is_debug() {
-        // TODO: take level from configs
-        let log_level = Level::from_str("DEBUG").unwrap();
-        let _log_guard = rencfs_desktop_common::log_init(log_level, "daemon");
-
-        // in dev mode, we don't want to daemonize, so we can see logs in the console and have debug
-        run_in_daemon().await;
-    } else {
-        // todo: daemonize after we can do this on windows also
-        // #[cfg(target_os = "linux")]
-        // daemonize();
-        run_in_daemon().await;
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Runtime::new().expect("Cannot create tokio runtime")
+}
+
+fn run(args: RunArgs, json: bool) -> ExitCode {
+    if args.background {
+        #[cfg(target_os = "linux")]
+        return daemonize(json);
+        #[cfg(not(target_os = "linux"))]
+        {
+            cli::print_error(json, "running in the background is supported only on Linux");
+            return ExitCode::FAILURE;
+        }
     }
+    let _log_guard = rencfs_desktop_common::log_init(config::current().log_level(), "daemon");
+    #[cfg(target_os = "linux")]
+    let _pidfile = match pidfile::PidFile::acquire() {
+        Ok(pidfile) => pidfile,
+        Err(err) => {
+            error!(err = %err, "Cannot start");
+            cli::print_error(json, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    runtime().block_on(run_in_daemon())
 }
 
+/// Forks before any thread is started, the parent waits for the daemon to answer.
 #[cfg(target_os = "linux")]
-#[instrument]
-fn daemonize() {
+fn daemonize(json: bool) -> ExitCode {
+    // the child takes the lock, this is to fail here instead of in the background
+    if let Err(err) = pidfile::check() {
+        cli::print_error(json, err);
+        return ExitCode::FAILURE;
+    }
+
     let logs_dir = get_logs_dir();
-    let uid = unsafe { libc::getuid() };
-    let gid = unsafe { libc::getgid() };
-    let username = whoami::username();
-
-    OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(logs_dir.join("daemon.out"))
-        .unwrap();
-    OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(logs_dir.join("daemon.err"))
-        .unwrap();
-
-    let stdout = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(logs_dir.join("daemon.out"))
-        .unwrap();
-    let stderr = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(logs_dir.join("daemon.err"))
-        .unwrap();
-
-    let daemonize = Daemonize::new()
-        // .pid_file("/tmp/test.pid") // Every method except `new` and `start`
-        // .chown_pid_file(true)      // is optional, see `Daemonize` documentation
-        .working_directory(get_data_dir()) // for default behaviour.
-        .user(username.as_str())
-        // .group("gnome") // Group name
-        .group(gid) // or group id.
-        // .umask(0o600)    // Set umask, `0o027` by default.
+    let open = |name: &str| {
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(logs_dir.join(name))
+    };
+    let (stdout, stderr) = match (open("daemon.out"), open("daemon.err")) {
+        (Ok(stdout), Ok(stderr)) => (stdout, stderr),
+        (Err(err), _) | (_, Err(err)) => {
+            cli::print_error(json, format!("cannot open the daemon output: {err}"));
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match Daemonize::new()
+        .pid_file(pidfile::path())
+        .working_directory(get_data_dir())
         .stdout(stdout)
         .stderr(stderr)
-        .privileged_action(move || {
-            println!("Privileged action, my uid is: {}, my gid is: {}", uid, gid);
-
-            // TODO: take level from configs
-            let log_level = Level::from_str("DEBUG").unwrap();
-            let _log_guard = rencfs_desktop_common::log_init(log_level, "daemon");
-
-            let handle = thread::spawn(|| {
-                let rt = tokio::runtime::Runtime::new().unwrap();
-                rt.block_on(async {
-                    run_in_daemon().await;
-                });
-            });
-            handle.join().unwrap();
-
-            "Executed before drop privileges"
-        });
-
-    match daemonize.start() {
-        Ok(_) => {}
-        Err(e) => {
-            error!(err = %e)
+        .execute()
+    {
+        Outcome::Parent(Ok(_)) => runtime().block_on(client::wait_started(json, logs_dir)),
+        Outcome::Parent(Err(err)) => {
+            cli::print_error(json, format!("cannot start in the background: {err}"));
+            ExitCode::FAILURE
+        }
+        Outcome::Child(Ok(_)) => {
+            let _log_guard =
+                rencfs_desktop_common::log_init(config::current().log_level(), "daemon");
+            runtime().block_on(run_in_daemon())
+        }
+        Outcome::Child(Err(err)) => {
+            // stderr is already the daemon.err file, if it got that far
+            eprintln!("Cannot daemonize: {err}");
+            ExitCode::FAILURE
         }
     }
 }
 
 #[instrument]
-pub async fn run_in_daemon() {
+pub async fn run_in_daemon() -> ExitCode {
     info!("Starting daemon");
 
     let res = task::spawn_blocking(|| {
         catch_unwind(|| {
             let handle = tokio::runtime::Handle::current();
-            handle.block_on(async {
-                daemon_run_async().await.expect("Error running daemon");
-            });
+            handle.block_on(async { daemon_run_async().await.expect("Error running daemon") })
         })
     })
     .await;
     match res {
-        Ok(Ok(_)) => {}
+        Ok(Ok(exit_code)) => exit_code,
         Ok(Err(err)) => {
             error!("panic {err:#?}");
             error!(backtrace = %Backtrace::force_capture());
@@ -144,7 +180,7 @@ pub async fn run_in_daemon() {
 }
 
 #[instrument]
-async fn daemon_run_async() -> Result<(), Box<dyn std::error::Error>> {
+async fn daemon_run_async() -> Result<ExitCode, Box<dyn std::error::Error>> {
     let mut conn =
         rencfs_desktop_common::persistence::establish_connection().unwrap_or_else(|_| {
             error!("Error connecting to database");
@@ -158,15 +194,113 @@ async fn daemon_run_async() -> Result<(), Box<dyn std::error::Error>> {
     let db_conn = Arc::new(Mutex::new(conn));
 
     info!("Starting server");
-    let addr = "[::1]:50051".parse()?;
-    let service = MyVaultService::new(db_conn);
+    let events = VaultEventHub::new(128);
+    let credentials = Arc::new(Credentials::new(events.clone()));
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    let config = config::current();
+    let rencfs = Arc::new(RwLock::new(
+        RencfsBinary::resolve(config.rencfs_path.as_deref()).await,
+    ));
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let stop = Arc::new(Notify::new());
+    let jobs = Arc::new(JobManager::new(db_conn.clone()));
+    let mut service = MyVaultService::new(
+        db_conn.clone(),
+        events.clone(),
+        rencfs.clone(),
+        credentials.clone(),
+        jobs.clone(),
+        shutdown_rx,
+        stop.clone(),
+    );
+    let handlers = service.handlers();
+    tokio::spawn(health::run(
+        health_reporter,
+        db_conn.clone(),
+        events.clone(),
+        handlers.clone(),
+    ));
+    #[cfg(target_os = "linux")]
+    let startup_warnings =
+        reconcile::run(&db_conn, &events, &handlers, &rencfs, &credentials).await;
+    #[cfg(not(target_os = "linux"))]
+    let startup_warnings = vec![];
+    service.set_startup_warnings(startup_warnings);
+    #[cfg(target_os = "linux")]
+    reconcile::resume_data_dir_moves(&db_conn, &handlers, &jobs).await;
+    jobs.fail_interrupted().await;
+    tokio::spawn(restarter::run(
+        handlers.clone(),
+        db_conn.clone(),
+        events.clone(),
+    ));
+    tokio::spawn(auto_lock::run(service.auto_lock()));
     let service = VaultServiceServer::new(service);
+    // stop accepting requests, and end the streams so the server can finish
+    let signal = async move {
+        tokio::select! {
+            _ = shutdown::signal() => {}
+            _ = stop.notified() => info!("Stop requested"),
+        }
+        info!("Stopping server");
+        #[cfg(target_os = "linux")]
+        systemd::notify("STOPPING=1");
+        let _ = shutdown_tx.send(true);
+    };
 
-    info!("Listening on {}", addr);
-    Server::builder()
-        .add_service(service)
-        .serve(addr)
-        .await?;
+    #[cfg(target_os = "linux")]
+    let served = {
+        let path = std::path::PathBuf::from(&config.listen);
+        let activated = systemd::listener()?;
+        let socket_activated = activated.is_some();
+        let listener = match activated {
+            // systemd owns the socket, `listen` is what it was installed with
+            Some(listener) => {
+                info!("Using the socket passed by systemd");
+                tokio::net::UnixListener::from_std(listener)?
+            }
+            None => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let listener = listener::bind(&path)?;
+                info!("Listening on {}", path.display());
+                listener
+            }
+        };
+        systemd::notify("READY=1");
+        systemd::spawn_watchdog();
+        let served = Server::builder()
+            .add_service(health_service)
+            .add_service(service)
+            .serve_with_incoming_shutdown(listener::incoming(listener), signal)
+            .await;
+        if !socket_activated {
+            if let Err(err) = std::fs::remove_file(&path) {
+                warn!(err = %err, "Cannot remove socket");
+            }
+        }
+        served
+    };
+    #[cfg(not(target_os = "linux"))]
+    let served = {
+        let addr = config.listen.parse()?;
+        info!("Listening on {}", addr);
+        Server::builder()
+            .add_service(health_service)
+            .add_service(service)
+            .serve_with_shutdown(addr, signal)
+            .await
+    };
+    if let Err(err) = &served {
+        error!(err = %err, "Server failed");
+    }
 
-    Ok(())
+    let exit_code =
+        shutdown::lock_all(handlers, &db_conn, config::current().shutdown_timeout()).await;
+    info!("Daemon stopped");
+    if served.is_err() {
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(exit_code)
 }
@@ -0,0 +1,99 @@
+//! `daemon.pid` in the runtime dir, locked for as long as the daemon runs so only one can.
+//!
+//! The lock is released by the kernel when the process exits, also when it crashes, so a pidfile
+//! which is not locked is stale and can be reclaimed.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+use thiserror::Error;
+use tracing::info;
+
+use rencfs_desktop_common::directories::get_runtime_dir;
+
+const FILE_NAME: &str = "daemon.pid";
+
+#[derive(Debug, Error)]
+pub enum PidFileError {
+    #[error("another daemon is already running with pid {}, stop it with `rencfs_desktop_daemon stop`", .0.map_or("unknown".to_string(), |pid| pid.to_string()))]
+    AlreadyRunning(Option<u32>),
+    #[error("cannot use pidfile {}: {source}", .path.display())]
+    Io { path: PathBuf, source: io::Error },
+}
+
+pub fn path() -> PathBuf {
+    get_runtime_dir().join(FILE_NAME)
+}
+
+/// Held for the lifetime of the daemon.
+#[derive(Debug)]
+pub struct PidFile {
+    file: File,
+}
+
+impl PidFile {
+    /// Locks the pidfile and writes the pid of this process in it.
+    pub fn acquire() -> Result<Self, PidFileError> {
+        let path = path();
+        let io_err = |source| PidFileError::Io {
+            path: path.clone(),
+            source,
+        };
+        let mut file = lock()?;
+        if let Some(stale) = read_pid(&mut file) {
+            info!(pid = stale, "Reclaiming stale pidfile");
+        }
+        file.set_len(0).map_err(io_err)?;
+        file.rewind().map_err(io_err)?;
+        writeln!(file, "{}", std::process::id()).map_err(io_err)?;
+        file.sync_all().map_err(io_err)?;
+        Ok(Self { file })
+    }
+}
+
+impl Drop for PidFile {
+    // not removed, another instance could have it open already and would lock a deleted file
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+    }
+}
+
+/// Fails with [`PidFileError::AlreadyRunning`] if a daemon holds the pidfile, without taking it.
+pub fn check() -> Result<(), PidFileError> {
+    lock().map(drop)
+}
+
+fn lock() -> Result<File, PidFileError> {
+    let path = path();
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(&path)
+        .map_err(|source| PidFileError::Io {
+            path: path.clone(),
+            source,
+        })?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(file);
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        Err(PidFileError::AlreadyRunning(read_pid(&mut file)))
+    } else {
+        Err(PidFileError::Io { path, source: err })
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut content = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
@@ -0,0 +1,345 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use diesel::{ExpressionMethods, SqliteConnection};
+use sysinfo::{Process, ProcessRefreshKind, ProcessStatus, ProcessesToUpdate, System, UpdateKind};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{error, info, instrument, warn};
+
+use rencfs_desktop_common::credentials::Credentials;
+use rencfs_desktop_common::dao::{DataDirMoveDao, VaultDao};
+use rencfs_desktop_common::data_dir;
+use rencfs_desktop_common::jobs::{JobKind, JobManager};
+use rencfs_desktop_common::platform::mounts;
+use rencfs_desktop_common::platform::mounts::MountInfo;
+use rencfs_desktop_common::platform::process::{read_stat, terminate, TERM_GRACE};
+use rencfs_desktop_common::rencfs::RencfsBinary;
+use rencfs_desktop_common::schema::vaults::locked;
+use rencfs_desktop_common::supervisor::{record_action, VaultActionKind};
+use rencfs_desktop_common::vault_events::VaultEventHub;
+use rencfs_desktop_common::vault_handler::VaultHandler;
+
+use crate::handlers::VaultHandlers;
+
+/// A rencfs process of the current user.
+#[derive(Debug)]
+struct RencfsProcess {
+    pid: u32,
+    /// From `/proc/<pid>/stat`, so a pid reused meanwhile is not signaled.
+    start_time: u64,
+    mount_point: PathBuf,
+    data_dir: PathBuf,
+}
+
+/// Brings the DB in line with what is really running, as the state could be left wrong by a
+/// previous daemon that didn't stop cleanly.
+///
+/// Vaults still served by a rencfs process are adopted into `handlers`, stale FUSE mounts and
+/// processes which are not mounted anymore are cleaned, and `locked` is corrected. Vaults left half
+/// created by InitVault are deleted, with their data dir if it only holds what rencfs created.
+///
+/// Returns what the user should check, like the data dirs which were left alone.
+#[instrument(skip_all)]
+pub async fn run(
+    db_conn: &Arc<Mutex<SqliteConnection>>,
+    events: &VaultEventHub,
+    handlers: &VaultHandlers,
+    rencfs: &Arc<RwLock<RencfsBinary>>,
+    credentials: &Arc<Credentials>,
+) -> Vec<String> {
+    let rencfs_path = rencfs.read().await.path.clone();
+    let mut guard = db_conn.lock().await;
+    let unready = VaultDao::new(&mut guard)
+        .delete_unready()
+        .unwrap_or_else(|err| {
+            error!(err = %err, "Cannot delete half created vaults");
+            vec![]
+        });
+    let vaults = match VaultDao::new(&mut guard).get_all(None) {
+        Ok(vaults) => vaults,
+        Err(err) => {
+            error!(err = %err, "Cannot get vaults");
+            return vec![];
+        }
+    };
+    let fuse_mounts: Vec<MountInfo> = match mounts::read_mounts() {
+        Ok(mounts) => mounts.into_iter().filter(MountInfo::is_fuse).collect(),
+        Err(err) => {
+            error!(err = %err, "Cannot read mounts");
+            return vec![];
+        }
+    };
+    let processes = rencfs_processes(rencfs_path.as_deref());
+    info!(
+        vaults = vaults.len(),
+        fuse_mounts = fuse_mounts.len(),
+        processes = processes.len(),
+        "Reconciling vaults"
+    );
+
+    let mut used = vec![];
+    let mut warnings = vec![];
+    for vault in unready {
+        info!(id = vault.id, "Deleted vault left half created");
+        let mount_point = Path::new(&vault.mount_point);
+        let data_dir = Path::new(&vault.data_dir);
+        for process in processes
+            .iter()
+            .filter(|p| p.mount_point == mount_point && p.data_dir == data_dir)
+        {
+            used.push(process.pid);
+            if let Err(err) = terminate(process.pid, process.start_time, TERM_GRACE).await {
+                error!(pid = process.pid, err = %err, "Cannot stop rencfs");
+            }
+        }
+        if fuse_mounts
+            .iter()
+            .any(|m| m.mount_point == mount_point && m.is_rencfs())
+        {
+            if let Err(err) = mounts::lazy_unmount(mount_point).await {
+                error!(err = %err, "Cannot remove stale mount");
+            }
+        }
+
+        // like InitVault does when it fails
+        let cleared = if vaults.iter().any(|v| Path::new(&v.data_dir) == data_dir) {
+            Ok(false)
+        } else {
+            data_dir::clear_rencfs_dir(data_dir)
+        };
+        match cleared {
+            Ok(true) => info!(data_dir = vault.data_dir, "Emptied the data dir"),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            res => {
+                warn!(data_dir = vault.data_dir, res = ?res, "Leaving the data dir alone");
+                warnings.push(format!(
+                    "vault {} was not fully created, its data dir {} was left as it was, remove \
+                     what is in it if you don't need it",
+                    vault.name, vault.data_dir
+                ));
+            }
+        }
+    }
+
+    // recorded once the DB is released
+    let mut blocked = vec![];
+
+    for vault in vaults {
+        let id = vault.id as u32;
+        let mount_point = Path::new(&vault.mount_point);
+        let data_dir = Path::new(&vault.data_dir);
+        let process = processes
+            .iter()
+            .find(|p| p.mount_point == mount_point && p.data_dir == data_dir);
+        used.extend(process.map(|p| p.pid));
+        // the last one is the one on top
+        let mount = fuse_mounts
+            .iter()
+            .rev()
+            .find(|m| m.mount_point == mount_point);
+
+        let unlocked = match (process, mount) {
+            (Some(process), Some(_)) => {
+                info!(id, pid = process.pid, "Adopting running vault");
+                handlers.insert(
+                    id,
+                    VaultHandler::adopt(
+                        id,
+                        process.pid,
+                        process.start_time,
+                        db_conn.clone(),
+                        events.clone(),
+                        rencfs.clone(),
+                        credentials.clone(),
+                    ),
+                );
+                true
+            }
+            (Some(process), None) => {
+                warn!(
+                    id,
+                    pid = process.pid,
+                    "rencfs is running but not mounted, stopping it"
+                );
+                if let Err(err) = terminate(process.pid, process.start_time, TERM_GRACE).await {
+                    error!(id, pid = process.pid, err = %err, "Cannot stop rencfs");
+                }
+                false
+            }
+            (None, Some(mount)) => {
+                if processes.iter().any(|p| p.mount_point == mount_point) {
+                    // mounted by rencfs with another data dir, not ours to touch
+                    warn!(
+                        id,
+                        mount_point = vault.mount_point,
+                        "Mount point is used by another rencfs"
+                    );
+                    blocked.push((id, "another rencfs is mounted there".to_string()));
+                } else if mount.is_rencfs() {
+                    warn!(id, mount_point = vault.mount_point, "Removing stale mount");
+                    if let Err(err) = mounts::lazy_unmount(mount_point).await {
+                        error!(id, err = %err, "Cannot remove stale mount");
+                    }
+                } else {
+                    // like sshfs, mounted there by the user
+                    warn!(
+                        id,
+                        mount_point = vault.mount_point,
+                        fs_type = mount.fs_type,
+                        source = mount.source,
+                        "Mount point is used by another file system"
+                    );
+                    blocked.push((
+                        id,
+                        format!("{} from {} is mounted there", mount.fs_type, mount.source),
+                    ));
+                }
+                false
+            }
+            (None, None) => false,
+        };
+
+        if unlocked != (vault.locked == 0) {
+            info!(id, unlocked, "Correcting vault state");
+            let mut dao = VaultDao::new(&mut guard);
+            if let Err(err) = dao.update(vault.id, locked.eq(if unlocked { 0 } else { 1 })) {
+                error!(id, err = %err, "Cannot update vault state");
+            }
+        }
+    }
+
+    for process in processes.iter().filter(|p| !used.contains(&p.pid)) {
+        info!(
+            ?process,
+            "Leaving alone rencfs process not started for a vault"
+        );
+    }
+
+    drop(guard);
+    for (id, message) in blocked {
+        record_action(db_conn, id, VaultActionKind::MountPointBlocked, message).await;
+    }
+    warnings
+}
+
+/// Finishes in the background the data dir moves a previous daemon didn't, see
+/// [`VaultHandler::resume_data_dir_move`].
+///
+/// The handlers of the vaults are locked before returning, so no request touches them until the
+/// moves are done, like during the UpdateVault that started them. Each move goes on in the job it
+/// was started with.
+#[instrument(skip_all)]
+pub async fn resume_data_dir_moves(
+    db_conn: &Arc<Mutex<SqliteConnection>>,
+    handlers: &VaultHandlers,
+    jobs: &Arc<JobManager>,
+) {
+    let moves = {
+        let mut guard = db_conn.lock().await;
+        match DataDirMoveDao::new(&mut guard).get_all() {
+            Ok(moves) => moves,
+            Err(err) => {
+                error!(err = %err, "Cannot get data dir moves");
+                return;
+            }
+        }
+    };
+    if moves.is_empty() {
+        return;
+    }
+    let mut resumed = vec![];
+    for mv in moves {
+        let job = jobs
+            .resume(
+                mv.vault_id as u32,
+                JobKind::DataDirMove,
+                mv.new_data_dir.clone(),
+            )
+            .await;
+        match job {
+            Ok(job) => {
+                let handler = handlers.acquire(mv.vault_id as u32).await;
+                resumed.push((mv, job, handler));
+            }
+            Err(err) => error!(id = mv.vault_id, err = %err, "Cannot resume the job of the move"),
+        }
+    }
+
+    for (mv, job, mut handler) in resumed {
+        let jobs = jobs.clone();
+        tokio::spawn(async move {
+            let id = mv.vault_id as u32;
+            info!(id, phase = mv.phase, "Resuming data dir move");
+            let res = handler.resume_data_dir_move(mv, &job).await;
+            if let Err(err) = &res {
+                error!(id, err = %err, "Cannot finish data dir move");
+            }
+            drop(handler);
+            jobs.finish(&job, &res).await;
+        });
+    }
+}
+
+/// The rencfs processes of the user, named `rencfs` or running `rencfs_path`, the binary the daemon
+/// resolved, which can be named otherwise.
+fn rencfs_processes(rencfs_path: Option<&Path>) -> Vec<RencfsProcess> {
+    let uid = unsafe { libc::getuid() };
+    let mut sys = System::new();
+    sys.refresh_processes_specifics(
+        ProcessesToUpdate::All,
+        true,
+        ProcessRefreshKind::new()
+            .with_cmd(UpdateKind::Always)
+            .with_exe(UpdateKind::Always)
+            .with_user(UpdateKind::Always),
+    );
+    sys.processes()
+        .iter()
+        .filter(|(_, p)| is_rencfs(p, rencfs_path))
+        .filter(|(_, p)| p.user_id().is_some_and(|u| **u == uid))
+        .filter(|(_, p)| !matches!(p.status(), ProcessStatus::Zombie | ProcessStatus::Dead))
+        .filter_map(|(pid, p)| {
+            Some(RencfsProcess {
+                pid: pid.as_u32(),
+                start_time: read_stat(pid.as_u32()).ok()?.start_time,
+                mount_point: arg_value(p.cmd(), "--mount-point", "-m")?,
+                data_dir: arg_value(p.cmd(), "--data-dir", "-d")?,
+            })
+        })
+        .collect()
+}
+
+fn is_rencfs(process: &Process, rencfs_path: Option<&Path>) -> bool {
+    if process.name() == "rencfs" {
+        return true;
+    }
+    let Some(rencfs_path) = rencfs_path else {
+        return false;
+    };
+    // the name is cut to 15 bytes by the kernel, the exe is complete if it can be read
+    process.exe().is_some_and(|exe| exe == rencfs_path)
+        || rencfs_path.file_name().is_some_and(|name| {
+            process.name() == name
+                || process
+                    .exe()
+                    .and_then(Path::file_name)
+                    .is_some_and(|exe_name| exe_name == name)
+        })
+}
+
+/// Value of the arg given as `--long value`, `--long=value` or `-s value`.
+fn arg_value(cmd: &[OsString], long: &str, short: &str) -> Option<PathBuf> {
+    let mut args = cmd.iter();
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+        if arg == long || arg == short {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(value) = arg.strip_prefix(&format!("{long}=")) {
+            return Some(PathBuf::from(value));
+        }
+    }
+    None
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use diesel::SqliteConnection;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::AbortHandle;
+use tracing::{error, info, instrument, warn};
+
+use rencfs_desktop_common::dao::VaultDao;
+use rencfs_desktop_common::supervisor::{record_action, RestartPolicy, VaultActionKind};
+use rencfs_desktop_common::vault_events::{VaultEvent, VaultEventHub};
+
+use crate::handlers::VaultHandlers;
+
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Running this long after a restart resets the attempts.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Consecutive restarts of a vault.
+struct Attempts {
+    count: u32,
+    last_restart: Instant,
+}
+
+/// Restarts the rencfs processes that exited on their own, according to the vault restart policy.
+///
+/// A pending restart is canceled if the vault is locked or deleted meanwhile.
+#[instrument(skip_all)]
+pub async fn run(
+    handlers: VaultHandlers,
+    db_conn: Arc<Mutex<SqliteConnection>>,
+    events: VaultEventHub,
+) {
+    let mut rx = events.subscribe();
+    // restart tasks send the attempts they used when they finish
+    let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+    let mut pending: HashMap<u32, AbortHandle> = HashMap::new();
+    let mut attempts: HashMap<u32, Attempts> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Ok(VaultEvent::ProcessDied { id, exit_code, .. }) => {
+                    let policy = match restart_policy(&db_conn, id).await {
+                        Some(policy) => policy,
+                        None => continue,
+                    };
+                    if !policy.should_restart(exit_code) {
+                        continue;
+                    }
+                    let count = match attempts.get(&id) {
+                        Some(a) if a.last_restart.elapsed() < STABLE_AFTER => a.count,
+                        _ => 0,
+                    };
+                    let task = tokio::spawn(restart(
+                        id,
+                        count,
+                        handlers.clone(),
+                        db_conn.clone(),
+                        done_tx.clone(),
+                    ));
+                    if let Some(old) = pending.insert(id, task.abort_handle()) {
+                        old.abort();
+                    }
+                }
+                Ok(VaultEvent::Locked { id }) | Ok(VaultEvent::Deleted { id }) => {
+                    attempts.remove(&id);
+                    if let Some(task) = pending.remove(&id) {
+                        if !task.is_finished() {
+                            info!(id, "Canceling restart, the vault was locked meanwhile");
+                            task.abort();
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(n)) => warn!(skipped = n, "Restarter skipped events"),
+                Err(RecvError::Closed) => return,
+            },
+            Some((id, count)) = done_rx.recv() => {
+                pending.remove(&id);
+                attempts.insert(id, Attempts { count, last_restart: Instant::now() });
+            }
+        }
+    }
+}
+
+async fn restart_policy(db_conn: &Arc<Mutex<SqliteConnection>>, id: u32) -> Option<RestartPolicy> {
+    let mut guard = db_conn.lock().await;
+    match VaultDao::new(&mut guard).get(id as i32) {
+        Ok(vault) => vault.restart_policy.parse().ok(),
+        Err(err) => {
+            warn!(id, err = %err, "Cannot get vault");
+            None
+        }
+    }
+}
+
+/// Tries to unlock the vault until it works or runs out of attempts, starting from attempt `count`.
+#[instrument(skip(handlers, db_conn, done))]
+async fn restart(
+    id: u32,
+    mut count: u32,
+    handlers: VaultHandlers,
+    db_conn: Arc<Mutex<SqliteConnection>>,
+    done: mpsc::UnboundedSender<(u32, u32)>,
+) {
+    loop {
+        if count >= MAX_ATTEMPTS {
+            record_action(
+                &db_conn,
+                id,
+                VaultActionKind::GaveUp,
+                format!("not restarting after {count} attempts"),
+            )
+            .await;
+            break;
+        }
+        let delay = INITIAL_BACKOFF
+            .saturating_mul(2u32.saturating_pow(count))
+            .min(MAX_BACKOFF);
+        count += 1;
+        record_action(
+            &db_conn,
+            id,
+            VaultActionKind::Restarting,
+            format!("attempt {count} in {}s", delay.as_secs()),
+        )
+        .await;
+        tokio::time::sleep(delay).await;
+
+        if !handlers.contains(id) {
+            warn!("Vault has no handler anymore");
+            break;
+        }
+        // shared with an unlock the user asked for meanwhile
+        let res = handlers.unlock(id, false).await;
+        match res {
+            Ok(_) => {
+                record_action(&db_conn, id, VaultActionKind::Restarted, String::new()).await;
+                break;
+            }
+            Err(err) => {
+                error!(err = %err, "Restart failed");
+                record_action(
+                    &db_conn,
+                    id,
+                    VaultActionKind::RestartFailed,
+                    err.to_string(),
+                )
+                .await;
+            }
+        }
+    }
+    let _ = done.send((id, count));
+}
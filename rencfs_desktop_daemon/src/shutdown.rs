@@ -0,0 +1,97 @@
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use diesel::SqliteConnection;
+use tokio::sync::Mutex;
+use tracing::{error, info, instrument, warn};
+
+use rencfs_desktop_common::dao::VaultDao;
+
+use crate::handlers::VaultHandlers;
+
+/// Resolves when the daemon is asked to stop, with SIGTERM or SIGINT.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigterm = signal(SignalKind::terminate()).expect("cannot listen for SIGTERM");
+        tokio::select! {
+            _ = sigterm.recv() => info!("Received SIGTERM"),
+            _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Received Ctrl-C");
+    }
+}
+
+/// Locks all vaults, the state is saved in the DB by each handler.
+///
+/// A vault whose handler is still busy after `timeout` is lazily unmounted instead, and counted
+/// as failed as its state in the DB is left to the next start.
+///
+/// Returns failure if any of them could not be locked.
+#[instrument(skip(handlers, db_conn))]
+pub async fn lock_all(
+    handlers: VaultHandlers,
+    db_conn: &Arc<Mutex<SqliteConnection>>,
+    timeout: Duration,
+) -> ExitCode {
+    let handlers = handlers.all();
+    info!(count = handlers.len(), "Locking vaults");
+
+    let mut failed = vec![];
+    for (id, handler) in handlers {
+        // after what's already running on it, if that ends in time
+        let Ok(mut handler) = tokio::time::timeout(timeout, handler.lock()).await else {
+            warn!(id, ?timeout, "Timed out waiting for the vault handler");
+            detach(id, db_conn).await;
+            failed.push(id);
+            continue;
+        };
+        if let Err(err) = handler.force_lock(timeout).await {
+            error!(id, err = %err, "Cannot lock vault");
+            failed.push(id);
+        }
+    }
+
+    if failed.is_empty() {
+        info!("All vaults locked");
+        ExitCode::SUCCESS
+    } else {
+        error!(?failed, "Some vaults could not be locked");
+        ExitCode::FAILURE
+    }
+}
+
+/// Lazily unmounts the vault `id` without its handler, its rencfs exits once the mount is gone.
+async fn detach(id: u32, db_conn: &Arc<Mutex<SqliteConnection>>) {
+    let vault = {
+        let mut guard = db_conn.lock().await;
+        VaultDao::new(&mut guard).get(id as i32)
+    };
+    match vault {
+        Ok(vault) => lazy_unmount(id, &vault.mount_point).await,
+        Err(err) => error!(id, err = %err, "Cannot get vault"),
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn lazy_unmount(id: u32, mount_point: &str) {
+    use rencfs_desktop_common::platform::{mounts, PlatformError};
+
+    match mounts::lazy_unmount(std::path::Path::new(mount_point)).await {
+        Ok(_) => warn!(id, mount_point, "Lazily unmounted mount point"),
+        Err(PlatformError::NotMounted(_)) => {}
+        Err(err) => error!(id, mount_point, err = %err, "Lazy umount failed"),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn lazy_unmount(id: u32, mount_point: &str) {
+    error!(id, mount_point, "Cannot unmount the vault");
+}
@@ -0,0 +1,219 @@
+//! Running as a `systemd --user` service: socket activation, readiness and the watchdog, see
+//! `sd_listen_fds(3)` and `sd_notify(3)`, and installing the units.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::path::PathBuf;
+use std::process::{self, ExitCode};
+use std::time::Duration;
+
+use directories::BaseDirs;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use rencfs_desktop_common::config;
+
+use crate::cli::{self, InstallSystemdArgs};
+
+const SD_LISTEN_FDS_START: RawFd = 3;
+const UNIT_NAME: &str = "rencfs_desktop_daemon";
+/// Keepalives are sent twice as often.
+const WATCHDOG_SEC: u64 = 30;
+
+/// The socket passed by systemd when the daemon is started by its `.socket` unit.
+///
+/// Only the first one is used, the unit has a single `ListenStream`.
+pub fn listener() -> io::Result<Option<UnixListener>> {
+    // the variables are inherited by rencfs, the pid tells they are not meant for it
+    let for_us = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(process::id());
+    let fds = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<i32>().ok())
+        .unwrap_or(0);
+    if !for_us || fds < 1 {
+        return Ok(None);
+    }
+    if fds > 1 {
+        warn!(fds, "Using only the first socket passed by systemd");
+    }
+    let fd = SD_LISTEN_FDS_START;
+    // so rencfs doesn't inherit it
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let listener = unsafe { UnixListener::from_raw_fd(fd) };
+    // fails if it's not a Unix socket
+    listener.local_addr()?;
+    listener.set_nonblocking(true)?;
+    Ok(Some(listener))
+}
+
+/// Sends `state` to systemd, if it started the daemon with `Type=notify`.
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let sent =
+        UnixDatagram::unbound().and_then(|socket| match path.as_bytes().strip_prefix(b"@") {
+            Some(name) => {
+                socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)
+            }
+            None => socket.send_to(state.as_bytes(), &path),
+        });
+    if let Err(err) = sent {
+        warn!(err = %err, state, "Cannot notify systemd");
+    }
+}
+
+/// Sends keepalives if the unit has `WatchdogSec`, at half the interval systemd expects them.
+pub fn spawn_watchdog() {
+    let Some(timeout) = watchdog_timeout() else {
+        return;
+    };
+    info!(?timeout, "Sending watchdog keepalives");
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(timeout / 2);
+        loop {
+            interval.tick().await;
+            notify("WATCHDOG=1");
+        }
+    });
+}
+
+fn watchdog_timeout() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+#[derive(Debug, Serialize)]
+pub struct InstallReport {
+    units: Vec<PathBuf>,
+    /// What to run to start the daemon now and on login.
+    enable: String,
+}
+
+impl fmt::Display for InstallReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for unit in &self.units {
+            writeln!(f, "wrote {}", unit.display())?;
+        }
+        write!(f, "enable it with: {}", self.enable)
+    }
+}
+
+/// Writes the units to the systemd user dir in the XDG config dir, without enabling them.
+pub fn install(args: InstallSystemdArgs, json: bool) -> ExitCode {
+    match write_units(&args) {
+        Ok(report) => {
+            cli::print(json, &report);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            cli::print_error(json, err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn write_units(args: &InstallSystemdArgs) -> Result<InstallReport, String> {
+    let dir = BaseDirs::new()
+        .ok_or("cannot find the home dir")?
+        .config_dir()
+        .join("systemd")
+        .join("user");
+    let exe = env::current_exe().map_err(|err| format!("cannot find this executable: {err}"))?;
+
+    let mut units = vec![(
+        dir.join(format!("{UNIT_NAME}.service")),
+        service_unit(&exe.display().to_string(), args.socket),
+    )];
+    if args.socket {
+        units.push((
+            dir.join(format!("{UNIT_NAME}.socket")),
+            socket_unit(&config::current().listen),
+        ));
+    }
+    if !args.force {
+        if let Some((path, _)) = units.iter().find(|(path, _)| path.exists()) {
+            return Err(format!(
+                "{} already exists, use --force to overwrite it",
+                path.display()
+            ));
+        }
+    }
+    fs::create_dir_all(&dir).map_err(|err| format!("cannot create {}: {err}", dir.display()))?;
+    for (path, content) in &units {
+        fs::write(path, content)
+            .map_err(|err| format!("cannot write {}: {err}", path.display()))?;
+    }
+
+    let enabled = if args.socket { "socket" } else { "service" };
+    Ok(InstallReport {
+        units: units.into_iter().map(|(path, _)| path).collect(),
+        enable: format!(
+            "systemctl --user daemon-reload && systemctl --user enable --now {UNIT_NAME}.{enabled}"
+        ),
+    })
+}
+
+fn service_unit(exe: &str, socket: bool) -> String {
+    // systemd splits ExecStart on whitespace unless quoted
+    let exe = if exe.contains(char::is_whitespace) {
+        format!("\"{exe}\"")
+    } else {
+        exe.to_string()
+    };
+    let socket = if socket {
+        format!("Requires={UNIT_NAME}.socket\nAfter={UNIT_NAME}.socket\n")
+    } else {
+        String::new()
+    };
+    format!(
+        "\
+[Unit]
+Description=rencfs desktop daemon, mounts the encrypted vaults
+Documentation=https://github.com/radumarias/rencfs-desktop
+{socket}
+[Service]
+Type=notify
+NotifyAccess=main
+ExecStart={exe} run --foreground
+WatchdogSec={WATCHDOG_SEC}
+Restart=on-failure
+
+[Install]
+WantedBy=default.target
+"
+    )
+}
+
+fn socket_unit(listen: &str) -> String {
+    format!(
+        "\
+[Unit]
+Description=rencfs desktop daemon socket
+
+[Socket]
+ListenStream={listen}
+SocketMode=0600
+DirectoryMode=0700
+
+[Install]
+WantedBy=sockets.target
+"
+    )
+}
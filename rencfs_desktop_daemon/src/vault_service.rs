@@ -1,28 +1,154 @@
-use std::collections::HashMap;
+// tonic::Status is large, but it's what the generated service traits return
+#![allow(clippy::result_large_err)]
+
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use diesel::SqliteConnection;
-use tokio::sync::Mutex;
+use diesel::query_builder::QueryFragment;
+use diesel::result::DatabaseErrorKind;
+use diesel::sqlite::Sqlite;
+use diesel::{AsChangeset, ExpressionMethods, SqliteConnection};
+use tokio::sync::{mpsc, watch, Mutex, Notify, RwLock};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream, WatchStream};
+use tokio_stream::{Stream, StreamExt};
 use tonic::{Request, Response, Status};
-use tracing::{info, instrument};
+use tracing::{error, info, instrument, warn};
 
-use rencfs_desktop_common::vault_handler::{VaultHandler, VaultHandlerError};
+use rencfs_desktop_common::config;
+use rencfs_desktop_common::credentials::{self, Credentials, Password, ProviderKind};
+use rencfs_desktop_common::dao::{VaultActionDao, VaultDao};
+use rencfs_desktop_common::data_dir::MovePhase;
+use rencfs_desktop_common::jobs::{self, JobManager};
+use rencfs_desktop_common::models;
+use rencfs_desktop_common::models::NewVault;
+use rencfs_desktop_common::protocol;
+use rencfs_desktop_common::rencfs;
+use rencfs_desktop_common::rencfs::RencfsBinary;
+use rencfs_desktop_common::schema::vaults;
+use rencfs_desktop_common::supervisor;
+use rencfs_desktop_common::vault_events;
+use rencfs_desktop_common::vault_events::VaultEventHub;
+use rencfs_desktop_common::vault_handler::VaultHandlerError;
 use rencfs_desktop_common::vault_service_error::VaultServiceError;
 
+use crate::auto_lock::{AutoLock, MIN_AUTO_LOCK_SECS};
+use crate::handlers::VaultHandlers;
 use crate::vault_service::vault_service_server::VaultService;
 
 tonic::include_proto!("rencfs_desktop");
 
+const DEFAULT_ACTIONS_LIMIT: u32 = 50;
+const DEFAULT_JOBS_LIMIT: u32 = 50;
+// how often WatchJob sends the progress of a running job
+const JOB_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
 pub struct MyVaultService {
-    handlers: Arc<Mutex<HashMap<u32, VaultHandler>>>,
+    handlers: VaultHandlers,
+    auto_lock: Arc<AutoLock>,
     db_conn: Arc<Mutex<SqliteConnection>>,
+    events: VaultEventHub,
+    started_at: Instant,
+    rencfs: Arc<RwLock<RencfsBinary>>,
+    credentials: Arc<Credentials>,
+    jobs: Arc<JobManager>,
+    // set to true when the daemon is stopping, to end the streams
+    shutdown: watch::Receiver<bool>,
+    // notified by the Stop RPC, the server then stops like on SIGTERM
+    stop: Arc<Notify>,
+    startup_warnings: Vec<String>,
 }
 
 impl MyVaultService {
-    pub fn new(db_conn: Arc<Mutex<SqliteConnection>>) -> Self {
+    pub fn new(
+        db_conn: Arc<Mutex<SqliteConnection>>,
+        events: VaultEventHub,
+        rencfs: Arc<RwLock<RencfsBinary>>,
+        credentials: Arc<Credentials>,
+        jobs: Arc<JobManager>,
+        shutdown: watch::Receiver<bool>,
+        stop: Arc<Notify>,
+    ) -> Self {
+        let handlers = VaultHandlers::new(
+            db_conn.clone(),
+            events.clone(),
+            rencfs.clone(),
+            credentials.clone(),
+        );
         Self {
-            handlers: Arc::new(Mutex::new(HashMap::new())),
+            auto_lock: Arc::new(AutoLock::new(
+                handlers.clone(),
+                db_conn.clone(),
+                events.clone(),
+            )),
+            handlers,
             db_conn,
+            events,
+            started_at: Instant::now(),
+            rencfs,
+            credentials,
+            jobs,
+            shutdown,
+            stop,
+            startup_warnings: vec![],
+        }
+    }
+
+    /// Sent in `DaemonInfo`, for what reconciling left to the user.
+    pub fn set_startup_warnings(&mut self, warnings: Vec<String>) {
+        self.startup_warnings = warnings;
+    }
+
+    /// The handlers of the vaults used so far, so they can be locked on shutdown.
+    pub fn handlers(&self) -> VaultHandlers {
+        self.handlers.clone()
+    }
+
+    /// Tracks how long the vaults are idle, to be run with [`crate::auto_lock::run`].
+    pub fn auto_lock(&self) -> Arc<AutoLock> {
+        self.auto_lock.clone()
+    }
+
+    async fn db_get_vault(&self, id: u32) -> Result<models::Vault, VaultServiceError> {
+        let mut guard = self.db_conn.lock().await;
+        let mut dao = VaultDao::new(&mut guard);
+        dao.get(id as i32)
+            .map_err(|err| Self::map_db_error(err, id, ""))
+    }
+
+    async fn db_insert_vault(
+        &self,
+        new_vault: &NewVault,
+    ) -> Result<models::Vault, VaultServiceError> {
+        let mut guard = self.db_conn.lock().await;
+        let mut dao = VaultDao::new(&mut guard);
+        dao.insert(new_vault)
+            .and_then(|_| dao.get_by_name(&new_vault.name))
+            .map_err(|err| Self::map_db_error(err, 0, &new_vault.name))
+    }
+
+    async fn db_update<V>(&self, id: u32, name_v: &str, value: V) -> Result<(), VaultServiceError>
+    where
+        V: AsChangeset<Target = vaults::table>,
+        <V as AsChangeset>::Changeset: QueryFragment<Sqlite>,
+    {
+        let mut guard = self.db_conn.lock().await;
+        let mut dao = VaultDao::new(&mut guard);
+        dao.update(id as i32, value)
+            .map_err(|err| Self::map_db_error(err, id, name_v))
+    }
+
+    fn map_db_error(err: diesel::result::Error, id: u32, name_v: &str) -> VaultServiceError {
+        match err {
+            diesel::result::Error::NotFound => VaultServiceError::VaultNotFound(id),
+            diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                VaultServiceError::VaultNameExists(name_v.to_string())
+            }
+            err => {
+                error!(err = %err, "Database error");
+                VaultServiceError::Database(err.to_string())
+            }
         }
     }
 
@@ -36,11 +162,321 @@ impl MyVaultService {
     }
 }
 
+impl From<models::Vault> for Vault {
+    fn from(vault: models::Vault) -> Self {
+        let restart_policy = vault.restart_policy.parse().unwrap_or_else(|err| {
+            warn!(err, "Invalid restart policy in DB");
+            supervisor::RestartPolicy::Never
+        });
+        let credential_provider = vault.credential_provider.parse().unwrap_or_else(|err| {
+            warn!(err, "Invalid credential provider in DB");
+            ProviderKind::Prompt
+        });
+        let cipher = vault.cipher.parse().unwrap_or_else(|err| {
+            warn!(err, "Invalid cipher in DB");
+            rencfs::Cipher::default()
+        });
+        Vault {
+            id: vault.id as u32,
+            name: vault.name,
+            mount_point: vault.mount_point,
+            data_dir: vault.data_dir,
+            locked: vault.locked == 1,
+            restart_policy: RestartPolicy::from(restart_policy).into(),
+            credential_provider: CredentialProvider::from(credential_provider).into(),
+            cipher: Cipher::from(cipher).into(),
+            auto_lock_secs: vault.auto_lock_secs.max(0) as u32,
+        }
+    }
+}
+
+impl From<supervisor::RestartPolicy> for RestartPolicy {
+    fn from(policy: supervisor::RestartPolicy) -> Self {
+        match policy {
+            supervisor::RestartPolicy::Never => RestartPolicy::Never,
+            supervisor::RestartPolicy::OnFailure => RestartPolicy::OnFailure,
+            supervisor::RestartPolicy::Always => RestartPolicy::Always,
+        }
+    }
+}
+
+impl From<RestartPolicy> for supervisor::RestartPolicy {
+    fn from(policy: RestartPolicy) -> Self {
+        match policy {
+            RestartPolicy::Never => supervisor::RestartPolicy::Never,
+            RestartPolicy::OnFailure => supervisor::RestartPolicy::OnFailure,
+            RestartPolicy::Always => supervisor::RestartPolicy::Always,
+        }
+    }
+}
+
+impl From<ProviderKind> for CredentialProvider {
+    fn from(kind: ProviderKind) -> Self {
+        match kind {
+            ProviderKind::Prompt => CredentialProvider::Prompt,
+            ProviderKind::SecretService => CredentialProvider::SecretService,
+            ProviderKind::KeyFile => CredentialProvider::KeyFile,
+        }
+    }
+}
+
+impl From<CredentialProvider> for ProviderKind {
+    fn from(provider: CredentialProvider) -> Self {
+        match provider {
+            CredentialProvider::Prompt => ProviderKind::Prompt,
+            CredentialProvider::SecretService => ProviderKind::SecretService,
+            CredentialProvider::KeyFile => ProviderKind::KeyFile,
+        }
+    }
+}
+
+impl From<rencfs::Cipher> for Cipher {
+    fn from(cipher: rencfs::Cipher) -> Self {
+        match cipher {
+            rencfs::Cipher::ChaCha20Poly1305 => Cipher::Chacha20Poly1305,
+            rencfs::Cipher::Aes256Gcm => Cipher::Aes256Gcm,
+        }
+    }
+}
+
+impl From<Cipher> for rencfs::Cipher {
+    fn from(cipher: Cipher) -> Self {
+        match cipher {
+            Cipher::Chacha20Poly1305 => rencfs::Cipher::ChaCha20Poly1305,
+            Cipher::Aes256Gcm => rencfs::Cipher::Aes256Gcm,
+        }
+    }
+}
+
+impl From<MovePhase> for DataDirMovePhase {
+    fn from(phase: MovePhase) -> Self {
+        match phase {
+            MovePhase::Copying => DataDirMovePhase::Copying,
+            MovePhase::Verifying => DataDirMovePhase::Verifying,
+            MovePhase::RemovingOld => DataDirMovePhase::RemovingOld,
+        }
+    }
+}
+
+impl From<jobs::JobKind> for JobKind {
+    fn from(kind: jobs::JobKind) -> Self {
+        match kind {
+            jobs::JobKind::DataDirMove => JobKind::DataDirMove,
+            jobs::JobKind::Backup => JobKind::Backup,
+        }
+    }
+}
+
+impl From<jobs::JobState> for JobState {
+    fn from(state: jobs::JobState) -> Self {
+        match state {
+            jobs::JobState::Running => JobState::Running,
+            jobs::JobState::Succeeded => JobState::Succeeded,
+            jobs::JobState::Failed => JobState::Failed,
+            jobs::JobState::Cancelled => JobState::Cancelled,
+        }
+    }
+}
+
+impl From<models::Job> for Job {
+    fn from(job: models::Job) -> Self {
+        let kind = job.kind.parse().unwrap_or_else(|err| {
+            warn!(err, "Invalid job kind in DB");
+            jobs::JobKind::DataDirMove
+        });
+        let state = job.state.parse().unwrap_or_else(|err| {
+            warn!(err, "Invalid job state in DB");
+            jobs::JobState::Failed
+        });
+        let phase = job.phase.parse().unwrap_or_else(|err| {
+            warn!(err, "Invalid job phase in DB");
+            MovePhase::Copying
+        });
+        let done_bytes = job.done_bytes.max(0) as u64;
+        let total_bytes = job.total_bytes.max(0) as u64;
+        let percent = match state {
+            jobs::JobState::Succeeded => 100,
+            _ if total_bytes == 0 => 0,
+            _ => (done_bytes.min(total_bytes) * 100 / total_bytes) as u32,
+        };
+        Job {
+            id: job.id as u32,
+            vault_id: job.vault_id as u32,
+            kind: JobKind::from(kind).into(),
+            target: job.target,
+            state: JobState::from(state).into(),
+            phase: DataDirMovePhase::from(phase).into(),
+            percent,
+            done_bytes,
+            total_bytes,
+            error: job.error,
+            created_at: job.created_at,
+            finished_at: job.finished_at,
+        }
+    }
+}
+
+impl From<&rencfs::Status> for RencfsStatus {
+    fn from(status: &rencfs::Status) -> Self {
+        match status {
+            rencfs::Status::Ok(_) => RencfsStatus::Ok,
+            rencfs::Status::Missing => RencfsStatus::Missing,
+            rencfs::Status::UnknownVersion => RencfsStatus::UnknownVersion,
+            rencfs::Status::Unsupported(_) => RencfsStatus::Unsupported,
+        }
+    }
+}
+
+fn parse_restart_policy(value: i32) -> Result<supervisor::RestartPolicy, VaultServiceError> {
+    RestartPolicy::try_from(value)
+        .map(supervisor::RestartPolicy::from)
+        .map_err(|_| VaultServiceError::InvalidArgument("restart policy".to_string()))
+}
+
+fn parse_credential_provider(value: i32) -> Result<ProviderKind, VaultServiceError> {
+    CredentialProvider::try_from(value)
+        .map(ProviderKind::from)
+        .map_err(|_| VaultServiceError::InvalidArgument("credential provider".to_string()))
+}
+
+fn parse_cipher(value: i32) -> Result<rencfs::Cipher, VaultServiceError> {
+    Cipher::try_from(value)
+        .map(rencfs::Cipher::from)
+        .map_err(|_| VaultServiceError::InvalidArgument("cipher".to_string()))
+}
+
+fn parse_auto_lock_secs(value: u32) -> Result<i32, VaultServiceError> {
+    match i32::try_from(value) {
+        Ok(secs) if value == 0 || value >= MIN_AUTO_LOCK_SECS => Ok(secs),
+        _ => Err(VaultServiceError::InvalidArgument("auto lock".to_string())),
+    }
+}
+
+/// The row for a new vault, `ready` unless InitVault still has to create its encrypted store.
+fn new_vault(request: CreateVaultRequest, ready: bool) -> Result<NewVault, VaultServiceError> {
+    let new_vault = NewVault {
+        name: request.name.trim().to_string(),
+        mount_point: request.mount_point,
+        data_dir: request.data_dir,
+        restart_policy: parse_restart_policy(request.restart_policy)?
+            .as_str()
+            .to_string(),
+        credential_provider: parse_credential_provider(request.credential_provider)?
+            .as_str()
+            .to_string(),
+        credential_key_id: credentials::new_key_id(),
+        ready: if ready { 1 } else { 0 },
+        cipher: parse_cipher(request.cipher)?.as_str().to_string(),
+        auto_lock_secs: parse_auto_lock_secs(request.auto_lock_secs)?,
+    };
+    if new_vault.name.is_empty() {
+        return Err(VaultServiceError::InvalidArgument("name".to_string()));
+    }
+    if new_vault.mount_point.is_empty() {
+        return Err(VaultServiceError::InvalidArgument(
+            "mount point".to_string(),
+        ));
+    }
+    if new_vault.data_dir.is_empty() {
+        return Err(VaultServiceError::InvalidArgument("data dir".to_string()));
+    }
+    Ok(new_vault)
+}
+
+impl From<models::VaultAction> for VaultAction {
+    fn from(action: models::VaultAction) -> Self {
+        VaultAction {
+            created_at: action.created_at,
+            action: action.action,
+            message: action.message,
+        }
+    }
+}
+
+impl From<vault_events::VaultEvent> for VaultEvent {
+    fn from(event: vault_events::VaultEvent) -> Self {
+        use vault_events::VaultEvent as E;
+
+        let id = event.id();
+        let event = match event {
+            E::Locked { .. } => vault_event::Event::Locked(LockedEvent {}),
+            E::Unlocked { .. } => vault_event::Event::Unlocked(UnlockedEvent {}),
+            E::MountPointChanged { mount_point, .. } => {
+                vault_event::Event::MountPointChanged(MountPointChangedEvent { mount_point })
+            }
+            E::DataDirChanged { data_dir, .. } => {
+                vault_event::Event::DataDirChanged(DataDirChangedEvent { data_dir })
+            }
+            E::DataDirMoving {
+                phase,
+                done_bytes,
+                total_bytes,
+                ..
+            } => vault_event::Event::DataDirMoving(DataDirMovingEvent {
+                phase: DataDirMovePhase::from(phase).into(),
+                done_bytes,
+                total_bytes,
+            }),
+            E::ProcessDied {
+                exit_code,
+                stderr_tail,
+                ..
+            } => vault_event::Event::ProcessDied(ProcessDiedEvent {
+                exit_code,
+                stderr_tail,
+            }),
+            E::Error { message, .. } => vault_event::Event::Error(ErrorEvent { message }),
+            E::Created { .. } => vault_event::Event::Created(CreatedEvent {}),
+            E::Renamed { name, .. } => vault_event::Event::Renamed(RenamedEvent { name }),
+            E::Deleted { .. } => vault_event::Event::Deleted(DeletedEvent {}),
+            E::PasswordRequested { request_id, .. } => {
+                vault_event::Event::PasswordRequested(PasswordRequestedEvent { request_id })
+            }
+            E::AutoLockWarning { seconds_left, .. } => {
+                vault_event::Event::AutoLockWarning(AutoLockWarningEvent { seconds_left })
+            }
+        };
+        VaultEvent {
+            id,
+            event: Some(event),
+        }
+    }
+}
+
 #[tonic::async_trait]
 impl VaultService for MyVaultService {
-    async fn hello(&self, request: Request<HelloRequest>) -> Result<Response<HelloReply>, Status> {
-        Ok(Response::new(HelloReply {
-            message: format!("Hello, {}!", request.into_inner().name),
+    type WatchVaultsStream = Pin<Box<dyn Stream<Item = Result<VaultEvent, Status>> + Send>>;
+    type WatchJobStream = Pin<Box<dyn Stream<Item = Result<Job, Status>> + Send>>;
+
+    #[instrument(skip(self), err)]
+    async fn get_daemon_info(
+        &self,
+        request: Request<GetDaemonInfoRequest>,
+    ) -> Result<Response<DaemonInfo>, Status> {
+        let client_version = request.into_inner().protocol_version;
+        info!(client_version, "Daemon info request received");
+        if !protocol::is_client_supported(client_version) {
+            // the client decides what to do, it knows better what to tell the user
+            warn!(client_version, "Client protocol is not supported");
+        }
+
+        let rencfs = self.rencfs.read().await;
+        Ok(Response::new(DaemonInfo {
+            daemon_version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol_version: protocol::PROTOCOL_VERSION,
+            min_protocol_version: protocol::MIN_PROTOCOL_VERSION,
+            build_info: format!("{} {}", env!("BUILD_TARGET"), env!("BUILD_PROFILE")),
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            rencfs_version: rencfs.version().map(|v| v.to_string()),
+            capabilities: protocol::CAPABILITIES
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+            rencfs_path: rencfs.path.as_ref().map(|p| p.display().to_string()),
+            rencfs_status: RencfsStatus::from(&rencfs.status).into(),
+            supported_rencfs_versions: rencfs::supported_versions(),
+            pid: std::process::id(),
+            startup_warnings: self.startup_warnings.clone(),
         }))
     }
 
@@ -49,64 +485,581 @@ impl VaultService for MyVaultService {
         let id = request.into_inner().id;
         info!(id, "Vault lock request received");
 
-        let mut handlers = self.handlers.lock().await;
-        let handler = handlers
-            .entry(id)
-            .or_insert_with(|| VaultHandler::new(id, self.db_conn.clone()));
+        return MyVaultService::handle_handler_empty_response(self.handlers.lock(id).await).await;
+    }
 
-        return MyVaultService::handle_handler_empty_response(handler.lock(None).await).await;
+    #[instrument(skip(self, request), err)]
+    async fn unlock(
+        &self,
+        request: Request<UnlockRequest>,
+    ) -> Result<Response<EmptyReply>, Status> {
+        let mut request = request.into_inner();
+        let id = request.id;
+        info!(
+            id,
+            typed = request.password.is_some(),
+            request.remember,
+            "Vault unlock request received"
+        );
+        let res = match request.password.take().map(Password::new) {
+            Some(password) => {
+                let mut handler = self.handlers.acquire(id).await;
+                handler.unlock(Some(password), request.remember).await
+            }
+            None => self.handlers.unlock(id, request.remember).await,
+        };
+
+        return MyVaultService::handle_handler_empty_response(res).await;
     }
 
     #[instrument(skip(self), err)]
-    async fn unlock(&self, request: Request<IdRequest>) -> Result<Response<EmptyReply>, Status> {
+    async fn create_vault(
+        &self,
+        request: Request<CreateVaultRequest>,
+    ) -> Result<Response<VaultReply>, Status> {
+        let request = request.into_inner();
+        info!(request.name, "Vault create request received");
+
+        let new_vault = new_vault(request, true)?;
+        let vault = self.db_insert_vault(&new_vault).await?;
+        self.events.publish(vault_events::VaultEvent::Created {
+            id: vault.id as u32,
+        });
+
+        Ok(Response::new(VaultReply {
+            vault: Some(vault.into()),
+        }))
+    }
+
+    #[instrument(skip(self, request), err)]
+    async fn init_vault(
+        &self,
+        request: Request<InitVaultRequest>,
+    ) -> Result<Response<VaultReply>, Status> {
+        let request = request.into_inner();
+        let password = Password::new(request.password);
+        let Some(request) = request.vault else {
+            return Err(VaultServiceError::InvalidArgument("vault".to_string()).into());
+        };
+        info!(request.name, "Vault init request received");
+        if password.expose().is_empty() {
+            return Err(VaultServiceError::InvalidArgument("password".to_string()).into());
+        }
+
+        // not listed until it's ready
+        let new_vault = new_vault(request, false)?;
+        let vault = self.db_insert_vault(&new_vault).await?;
+        let id = vault.id as u32;
+
+        let mut handler = self.handlers.acquire(id).await;
+        if let Err(err) = handler.init(password).await {
+            drop(handler);
+            self.handlers.remove(id);
+            let mut guard = self.db_conn.lock().await;
+            if let Err(err) = VaultDao::new(&mut guard).delete(vault.id) {
+                error!(err = %err, "Cannot delete the vault which failed to initialize");
+            }
+            return Err(VaultServiceError::from(err).into());
+        }
+        drop(handler);
+
+        self.db_update(id, &vault.name, vaults::ready.eq(1)).await?;
+        let vault = self.db_get_vault(id).await?;
+        self.events
+            .publish(vault_events::VaultEvent::Created { id });
+
+        Ok(Response::new(VaultReply {
+            vault: Some(vault.into()),
+        }))
+    }
+
+    #[instrument(skip(self), err)]
+    async fn get_vault(&self, request: Request<IdRequest>) -> Result<Response<VaultReply>, Status> {
         let id = request.into_inner().id;
-        info!(id, "Vault unlock request received");
+        info!(id, "Vault get request received");
 
-        let mut handlers = self.handlers.lock().await;
-        let handler = handlers
-            .entry(id)
-            .or_insert_with(|| VaultHandler::new(id, self.db_conn.clone()));
+        let vault = self.db_get_vault(id).await?;
 
-        return MyVaultService::handle_handler_empty_response(handler.unlock().await).await;
+        Ok(Response::new(VaultReply {
+            vault: Some(vault.into()),
+        }))
     }
 
     #[instrument(skip(self), err)]
-    async fn change_mount_point(
+    async fn list_vaults(
         &self,
-        request: Request<StringIdRequest>,
+        request: Request<ListVaultsRequest>,
+    ) -> Result<Response<ListVaultsReply>, Status> {
+        let limit = request.into_inner().limit;
+        info!(limit, "Vault list request received");
+
+        let vaults = {
+            let mut guard = self.db_conn.lock().await;
+            let mut dao = VaultDao::new(&mut guard);
+            dao.get_all(limit.map(i64::from))
+                .map_err(|err| Self::map_db_error(err, 0, ""))?
+        };
+
+        Ok(Response::new(ListVaultsReply {
+            vaults: vaults.into_iter().map(Vault::from).collect(),
+        }))
+    }
+
+    #[instrument(skip(self), err)]
+    async fn update_vault(
+        &self,
+        request: Request<UpdateVaultRequest>,
+    ) -> Result<Response<VaultReply>, Status> {
+        let request = request.into_inner();
+        let id = request.id;
+        info!(id, "Vault update request received");
+
+        let old = self.db_get_vault(id).await?;
+
+        // all checked first, so nothing is changed if one of them is wrong
+        if let Some(cipher) = request.cipher {
+            if parse_cipher(cipher)?.as_str() != old.cipher {
+                return Err(VaultServiceError::CipherChangeNotSupported.into());
+            }
+        }
+        let new_name = request.name.map(|n| n.trim().to_string());
+        if new_name.as_ref().is_some_and(|n| n.is_empty()) {
+            return Err(VaultServiceError::InvalidArgument("name".to_string()).into());
+        }
+        let restart_policy = request
+            .restart_policy
+            .map(parse_restart_policy)
+            .transpose()?;
+        let credential_provider = request
+            .credential_provider
+            .map(parse_credential_provider)
+            .transpose()?;
+        let auto_lock_secs = request
+            .auto_lock_secs
+            .map(parse_auto_lock_secs)
+            .transpose()?;
+        if request.mount_point.as_ref().is_some_and(|m| m.is_empty()) {
+            return Err(VaultServiceError::InvalidArgument("mount point".to_string()).into());
+        }
+        if request.data_dir.as_ref().is_some_and(|d| d.is_empty()) {
+            return Err(VaultServiceError::InvalidArgument("data dir".to_string()).into());
+        }
+
+        if let Some(new_name) = new_name {
+            if new_name != old.name {
+                self.db_update(id, &new_name, vaults::name.eq(&new_name))
+                    .await?;
+                self.events
+                    .publish(vault_events::VaultEvent::Renamed { id, name: new_name });
+            }
+        }
+
+        if let Some(restart_policy) = restart_policy {
+            self.db_update(
+                id,
+                &old.name,
+                vaults::restart_policy.eq(restart_policy.as_str()),
+            )
+            .await?;
+        }
+
+        if let Some(new_kind) = credential_provider {
+            if new_kind.as_str() != old.credential_provider {
+                self.db_update(
+                    id,
+                    &old.name,
+                    vaults::credential_provider.eq(new_kind.as_str()),
+                )
+                .await?;
+                // it's asked for again and stored in the new one, not left behind in the old one
+                if let Ok(old_kind) = old.credential_provider.parse() {
+                    if let Err(err) = self.credentials.forget(&old, old_kind).await {
+                        warn!(err = %err, "Cannot delete the password from the old provider");
+                    }
+                }
+            }
+        }
+
+        if let Some(auto_lock_secs) = auto_lock_secs {
+            if auto_lock_secs != old.auto_lock_secs {
+                self.db_update(id, &old.name, vaults::auto_lock_secs.eq(auto_lock_secs))
+                    .await?;
+                // a shorter timeout doesn't lock it right away
+                self.auto_lock.keep_unlocked(id);
+            }
+        }
+
+        // released before replying, unless a data dir move took it
+        {
+            let mut handler = self.handlers.acquire(id).await;
+            if let Some(new_mount_point) = request.mount_point {
+                if new_mount_point != old.mount_point {
+                    // the handler unlocks it again at the one in the db
+                    self.db_update(id, &old.name, vaults::mount_point.eq(&new_mount_point))
+                        .await?;
+                    if let Err(err) = handler.change_mount_point(old.mount_point.clone()).await {
+                        self.db_update(id, &old.name, vaults::mount_point.eq(&old.mount_point))
+                            .await?;
+                        // it was locked to be moved
+                        if old.locked == 0 && handler.pid().is_none() {
+                            if let Err(err) = handler.unlock(None, false).await {
+                                warn!(err = %err, "Cannot unlock the vault at its old mount point");
+                            }
+                        }
+                        return Err(VaultServiceError::from(err).into());
+                    }
+                }
+            }
+            if let Some(new_data_dir) = request.data_dir {
+                if new_data_dir != old.data_dir {
+                    // like StartJob, but the reply waits for it
+                    let job = self
+                        .jobs
+                        .start(id, jobs::JobKind::DataDirMove, new_data_dir.clone())
+                        .await?;
+                    // the handler switches the vault to it once the files are there, in a task of its
+                    // own so the job is finished even if the client goes away meanwhile
+                    let jobs = self.jobs.clone();
+                    let remove_old = request.remove_old_data_dir;
+                    let task = tokio::spawn(async move {
+                        let res = handler
+                            .change_data_dir(new_data_dir, remove_old, &job)
+                            .await;
+                        drop(handler);
+                        jobs.finish(&job, &res).await;
+                        res
+                    });
+                    task.await
+                        .unwrap_or(Err(VaultHandlerError::CannotChangeDataDir))
+                        .map_err(VaultServiceError::from)?;
+                }
+            }
+        }
+
+        let vault = self.db_get_vault(id).await?;
+        Ok(Response::new(VaultReply {
+            vault: Some(vault.into()),
+        }))
+    }
+
+    #[instrument(skip(self), err)]
+    async fn delete_vault(
+        &self,
+        request: Request<IdRequest>,
     ) -> Result<Response<EmptyReply>, Status> {
+        let id = request.into_inner().id;
+        info!(id, "Vault delete request received");
+
+        let vault = self.db_get_vault(id).await?;
+
+        let mut handler = self.handlers.acquire_existing(id).await;
+        if let Some(handler) = handler.as_mut() {
+            handler
+                .lock(Some(vault.mount_point.clone()))
+                .await
+                .map_err(VaultServiceError::from)?;
+            self.handlers.remove(id);
+        }
+        {
+            let mut guard = self.db_conn.lock().await;
+            let mut dao = VaultDao::new(&mut guard);
+            dao.delete(id as i32)
+                .map_err(|err| Self::map_db_error(err, id, &vault.name))?;
+        }
+        drop(handler);
+        if let Ok(kind) = vault.credential_provider.parse() {
+            if let Err(err) = self.credentials.forget(&vault, kind).await {
+                warn!(err = %err, "Cannot delete the password of the vault");
+            }
+        }
+        self.events
+            .publish(vault_events::VaultEvent::Deleted { id });
+
+        Ok(Response::new(EmptyReply {}))
+    }
+
+    #[instrument(skip(self))]
+    async fn watch_vaults(
+        &self,
+        request: Request<WatchVaultsRequest>,
+    ) -> Result<Response<Self::WatchVaultsStream>, Status> {
+        let ids = request.into_inner().ids;
+        info!(?ids, "Watch vaults request received");
+
+        let app_watching = self.credentials.prompt().app_watching();
+        let stream =
+            BroadcastStream::new(self.events.subscribe()).filter_map(move |event| match event {
+                Ok(event) if ids.is_empty() || ids.contains(&event.id()) => Some(Ok(event.into())),
+                Ok(_) => None,
+                Err(BroadcastStreamRecvError::Lagged(n)) => {
+                    warn!(skipped = n, "Watcher is too slow, skipped events");
+                    Some(Ok(VaultEvent {
+                        id: 0,
+                        event: Some(vault_event::Event::Resync(ResyncEvent {})),
+                    }))
+                }
+            });
+        // end the stream when stopping, otherwise the server waits for it forever
+        let shutdown = WatchStream::new(self.shutdown.clone())
+            .filter(|stopping| *stopping)
+            .map(|_| None);
+        let stream = stream
+            .map(Some)
+            .merge(shutdown)
+            .take_while(Option::is_some)
+            .filter_map(|event| event)
+            // the closure owns the guard, so the app counts as watching until the stream is dropped
+            .map(move |event| {
+                let _app_watching = &app_watching;
+                event
+            });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    #[instrument(skip(self), err)]
+    async fn list_vault_actions(
+        &self,
+        request: Request<ListVaultActionsRequest>,
+    ) -> Result<Response<ListVaultActionsReply>, Status> {
         let request = request.into_inner();
         let id = request.id;
-        info!(id, "Vault change mount point request received");
+        info!(id, "Vault actions list request received");
 
-        let mut handlers = self.handlers.lock().await;
-        let handler = handlers
-            .entry(id)
-            .or_insert_with(|| VaultHandler::new(id, self.db_conn.clone()));
+        self.db_get_vault(id).await?;
+        let actions = {
+            let mut guard = self.db_conn.lock().await;
+            let mut dao = VaultActionDao::new(&mut guard);
+            dao.get_by_vault(
+                id as i32,
+                request.limit.unwrap_or(DEFAULT_ACTIONS_LIMIT).into(),
+            )
+            .map_err(|err| Self::map_db_error(err, id, ""))?
+        };
 
-        return MyVaultService::handle_handler_empty_response(
-            handler.change_mount_point(request.value).await,
-        )
-        .await;
+        Ok(Response::new(ListVaultActionsReply {
+            actions: actions.into_iter().map(VaultAction::from).collect(),
+        }))
+    }
+
+    #[instrument(skip(self), err)]
+    async fn reload_config(
+        &self,
+        _request: Request<ReloadConfigRequest>,
+    ) -> Result<Response<ReloadConfigReply>, Status> {
+        info!("Reload config request received");
+
+        let changes = config::reload().map_err(|err| {
+            error!(err = %err, "Cannot reload config");
+            VaultServiceError::InvalidConfig(err.to_string())
+        })?;
+        let config = config::current();
+        if changes.applied.contains(&"log_level") {
+            if let Err(err) = rencfs_desktop_common::set_log_level(config.log_level()) {
+                warn!(err, "Cannot change log level");
+            }
+        }
+        // even with the same path, rencfs might have been installed or upgraded meanwhile
+        let rencfs = RencfsBinary::resolve(config.rencfs_path.as_deref()).await;
+        *self.rencfs.write().await = rencfs;
+        info!(?changes, "Config reloaded");
+
+        Ok(Response::new(ReloadConfigReply {
+            applied: changes.applied.iter().map(|s| s.to_string()).collect(),
+            restart_required: changes
+                .restart_required
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        }))
     }
 
     #[instrument(skip(self), err)]
-    async fn change_data_dir(
+    async fn stop(&self, _request: Request<StopRequest>) -> Result<Response<EmptyReply>, Status> {
+        info!("Stop request received");
+
+        // the reply is sent before the server stops accepting requests
+        self.stop.notify_one();
+        Ok(Response::new(EmptyReply {}))
+    }
+
+    // the request has the password
+    #[instrument(skip(self, request), err)]
+    async fn provide_password(
         &self,
-        request: Request<StringIdRequest>,
+        request: Request<ProvidePasswordRequest>,
+    ) -> Result<Response<EmptyReply>, Status> {
+        let request = request.into_inner();
+        let request_id = request.request_id;
+        info!(
+            request_id,
+            cancelled = request.password.is_none(),
+            "Provide password request received"
+        );
+
+        let password = request.password.map(Password::new);
+        if !self.credentials.prompt().answer(request_id, password) {
+            return Err(VaultServiceError::PasswordRequestNotFound(request_id).into());
+        }
+        Ok(Response::new(EmptyReply {}))
+    }
+
+    #[instrument(skip(self, request), err)]
+    async fn change_password(
+        &self,
+        request: Request<ChangePasswordRequest>,
     ) -> Result<Response<EmptyReply>, Status> {
         let request = request.into_inner();
         let id = request.id;
-        info!(id, "Vault change data dir request received");
+        info!(id, "Change password request received");
+
+        let current = Password::new(request.current_password);
+        let new = Password::new(request.new_password);
+        if new.expose().is_empty() {
+            return Err(VaultServiceError::InvalidArgument("new password".to_string()).into());
+        }
 
-        let mut handlers = self.handlers.lock().await;
-        let handler = handlers
-            .entry(id)
-            .or_insert_with(|| VaultHandler::new(id, self.db_conn.clone()));
+        let mut handler = self.handlers.acquire(id).await;
 
         return MyVaultService::handle_handler_empty_response(
-            handler.change_data_dir(request.value).await,
+            handler.change_password(current, new).await,
         )
         .await;
     }
+
+    #[instrument(skip(self), err)]
+    async fn start_job(&self, request: Request<StartJobRequest>) -> Result<Response<Job>, Status> {
+        let request = request.into_inner();
+        let id = request.vault_id;
+        info!(id, "Job start request received");
+
+        let (kind, target) = match &request.job {
+            Some(start_job_request::Job::DataDirMove(job)) => {
+                if job.data_dir.is_empty() {
+                    return Err(VaultServiceError::InvalidArgument("data dir".to_string()).into());
+                }
+                (jobs::JobKind::DataDirMove, job.data_dir.clone())
+            }
+            Some(start_job_request::Job::Backup(job)) => {
+                if job.dir.is_empty() {
+                    return Err(VaultServiceError::InvalidArgument("dir".to_string()).into());
+                }
+                (jobs::JobKind::Backup, job.dir.clone())
+            }
+            None => return Err(VaultServiceError::InvalidArgument("job".to_string()).into()),
+        };
+        self.db_get_vault(id).await?;
+        let job = self.jobs.start(id, kind, target).await?;
+        let reply = job.snapshot();
+
+        // runs after the requests already waiting for the vault, the reply doesn't wait for it
+        let handlers = self.handlers.clone();
+        let jobs = self.jobs.clone();
+        tokio::spawn(async move {
+            let mut handler = handlers.acquire(id).await;
+            let res = match request.job {
+                Some(start_job_request::Job::DataDirMove(move_job)) => {
+                    handler
+                        .change_data_dir(move_job.data_dir, move_job.remove_old_data_dir, &job)
+                        .await
+                }
+                Some(start_job_request::Job::Backup(backup)) => {
+                    handler.backup(backup.dir, &job).await
+                }
+                None => unreachable!("checked above"),
+            };
+            drop(handler);
+            jobs.finish(&job, &res).await;
+        });
+
+        Ok(Response::new(reply.into()))
+    }
+
+    #[instrument(skip(self), err)]
+    async fn get_job(&self, request: Request<JobIdRequest>) -> Result<Response<Job>, Status> {
+        let id = request.into_inner().id;
+        info!(id, "Job get request received");
+
+        let job = self.jobs.get(id).await?;
+        Ok(Response::new(job.into()))
+    }
+
+    #[instrument(skip(self), err)]
+    async fn list_jobs(
+        &self,
+        request: Request<ListJobsRequest>,
+    ) -> Result<Response<ListJobsReply>, Status> {
+        let request = request.into_inner();
+        info!(request.vault_id, "Job list request received");
+
+        let jobs = self
+            .jobs
+            .list(
+                request.vault_id,
+                request.limit.unwrap_or(DEFAULT_JOBS_LIMIT),
+            )
+            .await?;
+        Ok(Response::new(ListJobsReply {
+            jobs: jobs.into_iter().map(Job::from).collect(),
+        }))
+    }
+
+    #[instrument(skip(self), err)]
+    async fn cancel_job(
+        &self,
+        request: Request<JobIdRequest>,
+    ) -> Result<Response<EmptyReply>, Status> {
+        let id = request.into_inner().id;
+        info!(id, "Job cancel request received");
+
+        self.jobs.cancel(id).await?;
+        Ok(Response::new(EmptyReply {}))
+    }
+
+    #[instrument(skip(self))]
+    async fn watch_job(
+        &self,
+        request: Request<JobIdRequest>,
+    ) -> Result<Response<Self::WatchJobStream>, Status> {
+        let id = request.into_inner().id;
+        info!(id, "Watch job request received");
+
+        let mut job = self.jobs.watch(id).await?;
+        let (tx, updates) = mpsc::channel(1);
+        tokio::spawn(async move {
+            // it changes for every block copied, the last change is how it ended
+            while job.changed().await.is_ok() {
+                let update = Job::from(job.borrow_and_update().clone());
+                if tx.send(Ok(update)).await.is_err() {
+                    return;
+                }
+                tokio::time::sleep(JOB_UPDATE_INTERVAL).await;
+            }
+        });
+        let stream = ReceiverStream::new(updates)
+            .map(Some)
+            .chain(tokio_stream::once(None));
+        let shutdown = WatchStream::new(self.shutdown.clone())
+            .filter(|stopping| *stopping)
+            .map(|_| None);
+        let stream = stream
+            .merge(shutdown)
+            .take_while(Option::is_some)
+            .filter_map(|job| job);
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    #[instrument(skip(self), err)]
+    async fn keep_unlocked(
+        &self,
+        request: Request<IdRequest>,
+    ) -> Result<Response<EmptyReply>, Status> {
+        let id = request.into_inner().id;
+        info!(id, "Keep unlocked request received");
+
+        self.db_get_vault(id).await?;
+        self.auto_lock.keep_unlocked(id);
+        Ok(Response::new(EmptyReply {}))
+    }
 }
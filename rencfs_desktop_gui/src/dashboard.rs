@@ -1,22 +1,27 @@
 use std::borrow::Cow;
 use std::sync;
 use std::sync::RwLock;
+use std::time::{Duration, Instant};
 
 use eframe::egui;
 use eframe::egui::{
-    CentralPanel, Color32, Context, FontId, Margin, RichText, SidePanel, TopBottomPanel,
+    Align2, CentralPanel, Color32, Context, FontId, Key, Margin, RichText, SidePanel, TextEdit,
+    TopBottomPanel, Window,
 };
 use eframe::emath::Align;
 use egui::{Frame, Layout, Ui};
 use egui_notify::Toasts;
+use zeroize::Zeroize;
 
-use rencfs_desktop_common::dao::VaultDao;
-
+use crate::daemon_service::{
+    vault_event, Cipher, CredentialProvider, RestartPolicy, Vault, VaultEvent,
+};
+use crate::detail::daemon_service::DaemonService;
 use crate::detail::ViewGroupDetail;
 use crate::listview::r#trait::ItemTrait;
 use crate::listview::state::State;
 use crate::util::customize_toast;
-use crate::{DB_CONN, ListView};
+use crate::ListView;
 
 static CURRENT_VAULT_ITEM: RwLock<Option<Item>> = RwLock::new(None);
 static CURRENT_VAULT_ID: RwLock<Option<i32>> = RwLock::new(None);
@@ -27,6 +32,7 @@ pub(crate) enum UiReply {
     VaultDeleted,
     GoBack,
     Error(String),
+    VaultEvent(VaultEvent),
 }
 
 #[derive(Clone, Debug)]
@@ -36,6 +42,26 @@ pub struct Item {
     pub mount_point: String,
     pub data_dir: String,
     pub locked: bool,
+    pub restart_policy: RestartPolicy,
+    pub credential_provider: CredentialProvider,
+    pub cipher: Cipher,
+    pub auto_lock_secs: u32,
+}
+
+impl From<Vault> for Item {
+    fn from(v: Vault) -> Self {
+        Item {
+            id: v.id as i32,
+            restart_policy: v.restart_policy(),
+            credential_provider: v.credential_provider(),
+            cipher: v.cipher(),
+            auto_lock_secs: v.auto_lock_secs,
+            name: v.name,
+            mount_point: v.mount_point,
+            data_dir: v.data_dir,
+            locked: v.locked,
+        }
+    }
 }
 
 impl ItemTrait for Item {
@@ -97,10 +123,34 @@ impl ItemTrait for Item {
     }
 }
 
+/// The daemon waits for the password to unlock a vault.
+struct PasswordRequest {
+    request_id: u64,
+    vault_name: String,
+    password: String,
+}
+
+impl Drop for PasswordRequest {
+    fn drop(&mut self) {
+        self.password.zeroize();
+    }
+}
+
+/// The daemon locks an idle vault soon, unless it's kept unlocked.
+struct AutoLockWarning {
+    id: u32,
+    vault_name: String,
+    locks_at: Instant,
+}
+
 pub(crate) struct Dashboard {
     pub(crate) items: Vec<Item>,
     pub(crate) state: Option<State>,
     prev_state: Option<State>,
+    // oldest first, only the first one is shown
+    password_requests: Vec<PasswordRequest>,
+    // one per vault, oldest first, only the first one is shown
+    auto_lock_warnings: Vec<AutoLockWarning>,
 
     tx: sync::mpsc::Sender<UiReply>,
     rx: sync::mpsc::Receiver<UiReply>,
@@ -115,11 +165,22 @@ impl Dashboard {
             items: vec![],
             state: None,
             prev_state: None,
+            password_requests: vec![],
+            auto_lock_warnings: vec![],
             tx,
             rx,
             toasts: Toasts::default(),
         };
         out.items = out.load_items();
+        if let Ok(info) = DaemonService::daemon_info() {
+            if let Some(warning) = DaemonService::rencfs_warning(&info) {
+                customize_toast(out.toasts.warning(warning));
+            }
+            for warning in info.startup_warnings {
+                customize_toast(out.toasts.warning(warning));
+            }
+        }
+        DaemonService::watch_vaults(out.tx.clone());
         if out.items.len() > 0 {
             *CURRENT_VAULT_ID.write().unwrap() = Some(out.items[0].id);
             *CURRENT_VAULT_ITEM.write().unwrap() = Some(out.items[0].clone());
@@ -129,26 +190,186 @@ impl Dashboard {
     }
 
     fn load_items(&mut self) -> Vec<Item> {
-        let binding = DB_CONN.get().unwrap();
-        let mut conn = binding.lock().unwrap();
-        let mut dao = VaultDao::new(&mut conn);
-        dao.get_all(None)
-            .unwrap()
-            .iter()
-            .map(|v| Item {
-                id: v.id,
-                name: v.name.clone(),
-                mount_point: v.mount_point.clone(),
-                data_dir: v.data_dir.clone(),
-                locked: if v.locked == 1 { true } else { false },
-            })
-            .collect()
+        match DaemonService::list_vaults() {
+            Ok(vaults) => vaults.into_iter().map(Item::from).collect(),
+            Err(err) => {
+                customize_toast(self.toasts.error(err));
+                std::mem::take(&mut self.items)
+            }
+        }
+    }
+
+    fn on_vault_event(&mut self, event: VaultEvent) {
+        if let Some(State::Detail(detail)) = self.state.as_mut() {
+            detail.on_vault_event(&event);
+        }
+        if matches!(
+            event.event,
+            Some(vault_event::Event::Locked(_))
+                | Some(vault_event::Event::ProcessDied(_))
+                | Some(vault_event::Event::Deleted(_))
+        ) {
+            self.auto_lock_warnings.retain(|w| w.id != event.id);
+        }
+        match event.event {
+            Some(vault_event::Event::Created(_)) => {
+                self.items = self.load_items();
+                return;
+            }
+            // events were missed, nothing shown can be trusted
+            Some(vault_event::Event::Resync(_)) => {
+                self.items = self.load_items();
+                let items = &self.items;
+                self.auto_lock_warnings
+                    .retain(|w| items.iter().any(|i| i.id == w.id as i32 && !i.locked));
+                if let Some(State::Detail(detail)) = self.state.as_mut() {
+                    if let Some(id) = detail.id {
+                        match items.iter().find(|i| i.id == id) {
+                            Some(item) => detail.on_resync(item),
+                            None => self.state = None,
+                        }
+                    }
+                }
+                return;
+            }
+            Some(vault_event::Event::Deleted(_)) => {
+                self.items.retain(|i| i.id != event.id as i32);
+                if let Some(State::Detail(detail)) = self.state.as_ref() {
+                    if detail.id == Some(event.id as i32) {
+                        self.state = None;
+                    }
+                }
+                return;
+            }
+            Some(vault_event::Event::PasswordRequested(e)) => {
+                let vault_name = self
+                    .items
+                    .iter()
+                    .find(|i| i.id == event.id as i32)
+                    .map(|i| i.name.clone())
+                    .unwrap_or_else(|| format!("vault {}", event.id));
+                self.password_requests.push(PasswordRequest {
+                    request_id: e.request_id,
+                    vault_name,
+                    password: String::new(),
+                });
+                return;
+            }
+            Some(vault_event::Event::AutoLockWarning(e)) => {
+                let vault_name = self
+                    .items
+                    .iter()
+                    .find(|i| i.id == event.id as i32)
+                    .map(|i| i.name.clone())
+                    .unwrap_or_else(|| format!("vault {}", event.id));
+                self.auto_lock_warnings.retain(|w| w.id != event.id);
+                self.auto_lock_warnings.push(AutoLockWarning {
+                    id: event.id,
+                    vault_name,
+                    locks_at: Instant::now() + Duration::from_secs(e.seconds_left as u64),
+                });
+                return;
+            }
+            _ => {}
+        }
+        let Some(item) = self.items.iter_mut().find(|i| i.id == event.id as i32) else {
+            return;
+        };
+        match event.event {
+            Some(vault_event::Event::Locked(_)) => item.locked = true,
+            Some(vault_event::Event::Unlocked(_)) => item.locked = false,
+            Some(vault_event::Event::MountPointChanged(e)) => item.mount_point = e.mount_point,
+            Some(vault_event::Event::DataDirChanged(e)) => item.data_dir = e.data_dir,
+            Some(vault_event::Event::Renamed(e)) => item.name = e.name,
+            Some(vault_event::Event::ProcessDied(e)) => {
+                item.locked = true;
+                let msg = match e.exit_code {
+                    Some(code) => format!("vault {} stopped with exit code {}", item.name, code),
+                    None => format!("vault {} stopped", item.name),
+                };
+                customize_toast(self.toasts.warning(msg));
+            }
+            Some(vault_event::Event::Error(e)) => {
+                customize_toast(self.toasts.error(format!("{}: {}", item.name, e.message)));
+            }
+            Some(vault_event::Event::Created(_))
+            | Some(vault_event::Event::Deleted(_))
+            | Some(vault_event::Event::PasswordRequested(_))
+            | Some(vault_event::Event::DataDirMoving(_))
+            | Some(vault_event::Event::AutoLockWarning(_))
+            | Some(vault_event::Event::Resync(_))
+            | None => {}
+        }
+    }
+
+    fn show_password_request(&mut self, ctx: &Context) {
+        let Some(request) = self.password_requests.first_mut() else {
+            return;
+        };
+        let mut answer = None;
+        Window::new("Password")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!("Enter the password of {}", request.vault_name));
+                let response = ui.add(TextEdit::singleline(&mut request.password).password(true));
+                response.request_focus();
+                let entered = response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+                ui.horizontal(|ui| {
+                    if ui.button("Unlock").clicked() || entered {
+                        answer = Some(Some(std::mem::take(&mut request.password)));
+                    }
+                    if ui.button("Cancel").clicked() {
+                        answer = Some(None);
+                    }
+                });
+            });
+        if let Some(password) = answer {
+            let request = self.password_requests.remove(0);
+            DaemonService::provide_password(request.request_id, password, self.tx.clone());
+        }
+    }
+
+    fn show_auto_lock_warning(&mut self, ctx: &Context) {
+        let Some(warning) = self.auto_lock_warnings.first() else {
+            return;
+        };
+        let seconds_left = warning
+            .locks_at
+            .saturating_duration_since(Instant::now())
+            .as_secs();
+        let mut closed = false;
+        Window::new("Auto lock")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::RIGHT_BOTTOM, [-10.0, -10.0])
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "{} is idle and will be locked in {}s",
+                    warning.vault_name, seconds_left
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("Keep unlocked").clicked() {
+                        DaemonService::keep_unlocked(warning.id, self.tx.clone());
+                        closed = true;
+                    }
+                    if ui.button("Dismiss").clicked() {
+                        closed = true;
+                    }
+                });
+            });
+        if closed {
+            self.auto_lock_warnings.remove(0);
+        }
+        // the countdown
+        ctx.request_repaint_after(Duration::from_secs(1));
     }
 }
 
 impl eframe::App for Dashboard {
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        if let Ok(msg) = self.rx.try_recv() {
+        while let Ok(msg) = self.rx.try_recv() {
             match msg {
                 UiReply::VaultUpdated(show_message) => {
                     if show_message {
@@ -172,6 +393,7 @@ impl eframe::App for Dashboard {
                     self.items = self.load_items();
                 }
                 UiReply::Error(err) => customize_toast(self.toasts.error(err)),
+                UiReply::VaultEvent(event) => self.on_vault_event(event),
             }
         }
 
@@ -257,6 +479,8 @@ impl eframe::App for Dashboard {
             });
         }
 
+        self.show_password_request(ctx);
+        self.show_auto_lock_warning(ctx);
         self.toasts.show(ctx);
     }
 }
@@ -3,67 +3,131 @@ use std::time::Duration;
 use std::{fs, sync};
 use sync::mpsc::Receiver;
 
-use diesel::result::DatabaseErrorKind::UniqueViolation;
-use diesel::result::Error::DatabaseError;
-use diesel::{ExpressionMethods, QueryResult};
 use eframe::egui::Context;
 use eframe::{egui, Frame};
-use egui::{ecolor, Button, Widget};
+use egui::{ecolor, Align2, Button, ComboBox, Key, ProgressBar, TextEdit, Widget, Window};
 use egui_notify::{Toast, Toasts};
-use tracing::{info, instrument};
+use tracing::instrument;
+use zeroize::Zeroize;
 
 use daemon_service::DaemonService;
 use rencfs_desktop_common::is_debug;
-use rencfs_desktop_common::models::NewVault;
-use rencfs_desktop_common::schema::vaults::{data_dir, mount_point, name};
+use rencfs_desktop_common::protocol::capabilities;
+use rencfs_desktop_common::vault_handler::VaultHandlerError;
 use rencfs_desktop_common::vault_service_error::VaultServiceError;
 
-use crate::daemon_service::{EmptyReply, HelloReply};
+use crate::daemon_service::{
+    start_job_request, vault_event, BackupJob, Cipher, CreateVaultRequest, CredentialProvider,
+    DataDirMoveJob, DataDirMovePhase, EmptyReply, Job, JobKind, JobState, ListJobsReply,
+    ListVaultActionsReply, RestartPolicy, VaultAction, VaultEvent, VaultReply,
+};
 use crate::dashboard::{Item, UiReply};
-use crate::detail::db_service::DbService;
+use crate::util::{time_ago, with_hint};
 
-mod daemon_service;
-mod db_service;
+pub(crate) mod daemon_service;
 
 enum ServiceReply {
-    HelloReply(HelloReply),
     UnlockVaultReply(EmptyReply),
     LockVaultReply(EmptyReply),
-    ChangeMountPoint(EmptyReply),
-    ChangeDataDir(EmptyReply),
-    VaultServiceError(VaultServiceError),
+    VaultCreated(VaultReply),
+    Renamed(VaultReply),
+    ChangeMountPoint(VaultReply),
+    ChangeDataDir(VaultReply),
+    VaultDeleted(EmptyReply),
+    RestartPolicyChanged(VaultReply),
+    CredentialProviderChanged(VaultReply),
+    AutoLockChanged(VaultReply),
+    PasswordChanged(EmptyReply),
+    VaultActions(ListVaultActionsReply),
+    JobStarted(Job),
+    JobUpdated(Job),
+    // its updates stopped and it can't be fetched anymore, with why
+    JobLost(String),
+    JobCancelled(EmptyReply),
+    Jobs(ListJobsReply),
+    // with the remediation hint from the daemon
+    VaultServiceError(VaultServiceError, Option<String>),
     Error(String),
 }
 
+/// Asks for the password again after rencfs rejected it.
+#[derive(Default)]
+struct UnlockPrompt {
+    password: String,
+    remember: bool,
+}
+
+impl Drop for UnlockPrompt {
+    fn drop(&mut self) {
+        self.password.zeroize();
+    }
+}
+
+/// The password of a new vault, typed twice.
+#[derive(Default)]
+struct NewPassword {
+    password: String,
+    confirm: String,
+}
+
+impl Drop for NewPassword {
+    fn drop(&mut self) {
+        self.password.zeroize();
+        self.confirm.zeroize();
+    }
+}
+
+#[derive(Default)]
+struct ChangePasswordDialog {
+    current: String,
+    new: String,
+    confirm: String,
+}
+
+impl Drop for ChangePasswordDialog {
+    fn drop(&mut self) {
+        self.current.zeroize();
+        self.new.zeroize();
+        self.confirm.zeroize();
+    }
+}
+
 pub struct ViewGroupDetail {
     pub(crate) id: Option<i32>,
     pub(crate) name: String,
+    // last name known by the daemon
+    saved_name: String,
     pub(crate) mount_point: Option<String>,
     pub(crate) data_dir: Option<String>,
+    remove_old_data_dir: bool,
+    // the running job, like moving the files to a new data dir, the old one is shown until it's done
+    job: Option<Job>,
     pub(crate) locked: bool,
+    restart_policy: RestartPolicy,
+    credential_provider: CredentialProvider,
+    cipher: Cipher,
+    // idle seconds after which the daemon locks it, 0 never
+    auto_lock_secs: u32,
+    new_password: NewPassword,
+    // newest first
+    actions: Vec<VaultAction>,
 
     tx_parent: Sender<UiReply>,
     rx_service: Receiver<ServiceReply>,
 
     daemon_service: DaemonService,
-    db_service: DbService,
 
     confirmation_delete_pending: bool,
+    unlock_prompt: Option<UnlockPrompt>,
+    change_password: Option<ChangePasswordDialog>,
+    // a wrong password in the reply is the current one of the dialog, not an unlock one
+    change_password_pending: bool,
 
     toasts: Toasts,
-
-    // todo:
-    // remove when this is implemented https://github.com/radumarias/rencfs-desktop/issues/14
-    // and call hello() when service is initialized
-    initialized: bool,
 }
 
 impl eframe::App for ViewGroupDetail {
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
-        if !self.initialized {
-            self.daemon_service.hello("test");
-            self.initialized = true;
-        }
         let customize_toast_duration = |t: &mut Toast, seconds: u64| {
             let duration = Some(Duration::from_secs(seconds));
             t.set_closable(false)
@@ -73,34 +137,133 @@ impl eframe::App for ViewGroupDetail {
         let customize_toast = |t: &mut Toast| {
             customize_toast_duration(t, 5);
         };
+        let has_jobs = self.daemon_service.has_capability(capabilities::JOBS);
         if let Ok(reply) = self.rx_service.try_recv() {
             match reply {
+                // state changes come through vault events, see `on_vault_event`
                 ServiceReply::UnlockVaultReply(_) => {
-                    self.locked = false;
                     customize_toast(self.toasts.success("vault unlocked"));
-                    self.tx_parent.send(UiReply::VaultUpdated(false)).unwrap();
                 }
                 ServiceReply::LockVaultReply(_) => {
-                    self.locked = true;
                     customize_toast(self.toasts.success("vault locked"));
-                    self.tx_parent.send(UiReply::VaultUpdated(false)).unwrap();
+                }
+                ServiceReply::VaultCreated(_) => {
+                    self.tx_parent.send(UiReply::VaultInserted).unwrap();
+                    customize_toast(self.toasts.success(format!("vault {} saved", self.name)));
+                }
+                ServiceReply::Renamed(reply) => {
+                    if let Some(vault) = reply.vault {
+                        self.saved_name = vault.name;
+                    }
+                    customize_toast(self.toasts.success("vault renamed"));
+                }
+                ServiceReply::VaultDeleted(_) => {
+                    self.tx_parent.send(UiReply::VaultDeleted).unwrap();
+                    customize_toast(self.toasts.success("vault deleted"))
                 }
                 ServiceReply::ChangeMountPoint(_) => {
-                    self.db_reload();
                     customize_toast(self.toasts.success("mount point changed"));
                 }
                 ServiceReply::ChangeDataDir(_) => {
-                    self.db_reload();
+                    self.job = None;
                     customize_toast(self.toasts.success("data dir changed"));
                 }
-                ServiceReply::VaultServiceError(err) => {
-                    customize_toast(self.toasts.error(err.to_string()))
+                ServiceReply::RestartPolicyChanged(_) => {
+                    customize_toast(self.toasts.success("restart policy changed"));
+                }
+                ServiceReply::CredentialProviderChanged(_) => {
+                    customize_toast(self.toasts.success("password storage changed"));
+                }
+                ServiceReply::AutoLockChanged(_) => {
+                    customize_toast(self.toasts.success("auto lock changed"));
+                }
+                ServiceReply::PasswordChanged(_) => {
+                    self.change_password_pending = false;
+                    customize_toast(self.toasts.success("password changed"));
+                }
+                ServiceReply::VaultServiceError(
+                    VaultServiceError::VaultHandlerError(VaultHandlerError::WrongPassword),
+                    _,
+                ) if self.change_password_pending => {
+                    self.change_password_pending = false;
+                    customize_toast(self.toasts.error("the current password is wrong"));
+                    self.change_password.get_or_insert_with(ChangePasswordDialog::default);
+                }
+                ServiceReply::VaultActions(reply) => self.actions = reply.actions,
+                ServiceReply::JobStarted(job) => {
+                    self.daemon_service.watch_job(job.id);
+                    self.job = Some(job);
+                }
+                ServiceReply::JobUpdated(job) if job.state() == JobState::Running => {
+                    self.job = Some(job)
+                }
+                ServiceReply::JobUpdated(job) => {
+                    self.job = None;
+                    let what = job_kind_label(job.kind());
+                    match job.state() {
+                        JobState::Succeeded => customize_toast(self.toasts.success(format!("{what} done"))),
+                        JobState::Cancelled => customize_toast(self.toasts.info(format!("{what} cancelled"))),
+                        _ => customize_toast(self.toasts.error(format!(
+                            "{what} failed: {}",
+                            job.error.as_deref().unwrap_or("unknown error")
+                        ))),
+                    };
+                }
+                ServiceReply::JobLost(err) => {
+                    if let Some(job) = self.job.take() {
+                        let what = job_kind_label(job.kind());
+                        customize_toast(self.toasts.error(format!("{what} cannot be followed: {err}")));
+                    }
+                }
+                ServiceReply::JobCancelled(_) => {}
+                ServiceReply::Jobs(reply) => {
+                    if let Some(job) = reply.jobs.into_iter().find(|j| j.state() == JobState::Running) {
+                        self.daemon_service.watch_job(job.id);
+                        self.job = Some(job);
+                    }
+                }
+                ServiceReply::VaultServiceError(
+                    VaultServiceError::VaultHandlerError(VaultHandlerError::WrongPassword),
+                    _,
+                ) if self
+                    .daemon_service
+                    .has_capability(capabilities::UNLOCK_WITH_PASSWORD) =>
+                {
+                    customize_toast(self.toasts.error("wrong password"));
+                    self.unlock_prompt.get_or_insert_with(UnlockPrompt::default);
+                }
+                ServiceReply::VaultServiceError(err, hint) => {
+                    self.change_password_pending = false;
+                    // a job only ends through its updates, the error might be from cancelling it
+                    if !has_jobs {
+                        self.job = None;
+                    }
+                    customize_toast(self.toasts.error(with_hint(&err, hint.as_deref())))
                 }
                 ServiceReply::Error(s) => customize_toast(self.toasts.error(s.clone())),
-                ServiceReply::HelloReply(s) => info!("HelloReply: {:?}", s),
             }
         }
 
+        // features the daemon doesn't advertise are disabled
+        let can_delete = self.daemon_service.has_capability(capabilities::DELETE_VAULT);
+        let can_restart = self.daemon_service.has_capability(capabilities::RESTART_POLICY);
+        let has_actions = self.daemon_service.has_capability(capabilities::VAULT_ACTIONS);
+        let has_credentials = self
+            .daemon_service
+            .has_capability(capabilities::CREDENTIAL_PROVIDERS);
+        let can_change_password = self
+            .daemon_service
+            .has_capability(capabilities::CHANGE_PASSWORD);
+        let can_init = self.daemon_service.has_capability(capabilities::INIT_VAULT);
+        let has_cipher = self.daemon_service.has_capability(capabilities::CIPHER);
+        let has_auto_lock = self.daemon_service.has_capability(capabilities::AUTO_LOCK);
+        let can_move_data_dir = self.daemon_service.has_capability(capabilities::DATA_DIR_MOVE);
+        let editable = self.daemon_service.has_capability(if self.id.is_some() {
+            capabilities::UPDATE_VAULT
+        } else {
+            capabilities::CREATE_VAULT
+        });
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.vertical(|ui| {
                 if self.id.is_some() {
@@ -114,7 +277,7 @@ impl eframe::App for ViewGroupDetail {
                                 ui.label(if self.locked { "Unlock the vault" } else { "Lock the vault" });
                             }).clicked() {
                                 if self.locked {
-                                    self.daemon_service.unlock_vault();
+                                    self.daemon_service.unlock_vault(None, false);
                                     // customize_toast_duration(self.toasts.warning("please wait, it takes up to 10 seconds to unlock the vault, you will be notified"), 8);
                                 } else {
                                     self.daemon_service.lock_vault();
@@ -125,7 +288,7 @@ impl eframe::App for ViewGroupDetail {
                 }
                 ui.horizontal(|ui| {
                     ui.label("Name");
-                    if ui.text_edit_singleline(&mut self.name).lost_focus() {
+                    if ui.add_enabled(editable, TextEdit::singleline(&mut self.name)).lost_focus() {
                         self.ui_on_name_lost_focus();
                     }
                 });
@@ -141,7 +304,7 @@ impl eframe::App for ViewGroupDetail {
                             }
                         });
                     });
-                    if ui.button("...").clicked() {
+                    if ui.add_enabled(editable, Button::new("...")).clicked() {
                         if let Some(path) = rfd::FileDialog::new().pick_folder() {
                             if self.id.is_some() && path.to_string_lossy() == self.mount_point.as_ref().unwrap().as_str() {
                                 customize_toast(self.toasts.error("you need to select a different path than existing one"));
@@ -155,9 +318,7 @@ impl eframe::App for ViewGroupDetail {
                                             customize_toast_duration(self.toasts.warning("please wait, it takes up to 10 seconds to change mount point, you will be notified"), 8);
                                             customize_toast_duration(self.toasts.warning("it will lock the vault meanwhile"), 8)
                                         }
-                                        let old_path = self.mount_point.as_ref().unwrap().clone();
-                                        self.db_service.update(mount_point.eq(path.clone()));
-                                        self.daemon_service.change_mount_point(old_path);
+                                        self.daemon_service.change_mount_point(path.clone());
                                     }
                                     self.mount_point = Some(path);
                                 }
@@ -177,7 +338,7 @@ impl eframe::App for ViewGroupDetail {
                             }
                         });
                     });
-                    if ui.button("...").clicked() {
+                    if ui.add_enabled(editable && self.job.is_none(), Button::new("...")).clicked() {
                         if let Some(path) = rfd::FileDialog::new().pick_folder() {
                             if self.id.is_some() && path.to_string_lossy() == self.data_dir.as_ref().unwrap().as_str() {
                                 customize_toast(self.toasts.error("you need to select a different path than existing one"));
@@ -188,49 +349,170 @@ impl eframe::App for ViewGroupDetail {
                                     let path = path.display().to_string();
                                     if self.id.is_some() {
                                         if !self.locked {
-                                            customize_toast_duration(self.toasts.warning("it could take longer to move the data to the new location, you will be notified"), 8);
                                             customize_toast_duration(self.toasts.warning("it will lock the vault meanwhile"), 8)
                                         }
-                                        let old_path = self.data_dir.as_ref().unwrap().clone();
-                                        self.db_service.update(data_dir.eq(path.clone()));
-                                        self.daemon_service.change_data_dir(old_path);
+                                        // shown once the files are moved, see `on_vault_event`
+                                        if has_jobs {
+                                            self.daemon_service.start_job(start_job_request::Job::DataDirMove(DataDirMoveJob {
+                                                data_dir: path.clone(),
+                                                remove_old_data_dir: self.remove_old_data_dir,
+                                            }));
+                                        } else {
+                                            self.daemon_service.change_data_dir(path.clone(), self.remove_old_data_dir);
+                                        }
+                                        self.job = Some(Job { kind: JobKind::DataDirMove.into(), ..Default::default() });
+                                    } else {
+                                        self.data_dir = Some(path);
                                     }
-                                    self.data_dir = Some(path);
                                 }
                             }
                         }
                     }
+                    if self.id.is_some() && can_move_data_dir {
+                        ui.add_enabled(self.job.is_none(), egui::Checkbox::new(&mut self.remove_old_data_dir, "Remove the old one"))
+                            .on_hover_text("remove the old data dir once the files are moved and checked");
+                    }
                 });
+                if let Some(job) = &self.job {
+                    let mut cancel = None;
+                    ui.horizontal(|ui| {
+                        ui.add(ProgressBar::new(job.percent as f32 / 100.0).desired_width(320.0).text(format!(
+                            "{}, {} {:.1} / {:.1} MiB",
+                            job_kind_label(job.kind()),
+                            move_phase_label(job.phase()).to_lowercase(),
+                            job.done_bytes as f64 / MIB,
+                            job.total_bytes as f64 / MIB,
+                        )));
+                        // until the daemon replies there is no job to cancel yet
+                        if has_jobs && ui.add_enabled(job.id != 0, Button::new("Cancel")).clicked() {
+                            cancel = Some(job.id);
+                        }
+                    });
+                    if let Some(id) = cancel {
+                        self.daemon_service.cancel_job(id);
+                    }
+                }
+                if can_restart {
+                    ui.horizontal(|ui| {
+                        ui.label("Restart");
+                        let old = self.restart_policy;
+                        ui.add_enabled_ui(editable, |ui| {
+                            ComboBox::from_id_salt("restart_policy")
+                                .selected_text(restart_policy_label(self.restart_policy))
+                                .show_ui(ui, |ui| {
+                                    for policy in [RestartPolicy::Never, RestartPolicy::OnFailure, RestartPolicy::Always] {
+                                        ui.selectable_value(&mut self.restart_policy, policy, restart_policy_label(policy));
+                                    }
+                                });
+                        }).response.on_hover_text("what to do when rencfs stops on its own");
+                        if self.id.is_some() && old != self.restart_policy {
+                            self.daemon_service.change_restart_policy(self.restart_policy);
+                        }
+                    });
+                }
+                if has_auto_lock {
+                    ui.horizontal(|ui| {
+                        ui.label("Auto lock");
+                        let old = self.auto_lock_secs;
+                        ui.add_enabled_ui(editable, |ui| {
+                            ComboBox::from_id_salt("auto_lock")
+                                .selected_text(auto_lock_label(self.auto_lock_secs))
+                                .show_ui(ui, |ui| {
+                                    for secs in AUTO_LOCK_CHOICES {
+                                        ui.selectable_value(&mut self.auto_lock_secs, secs, auto_lock_label(secs));
+                                    }
+                                });
+                        }).response.on_hover_text("lock it when its files are not used for a while");
+                        if self.id.is_some() && old != self.auto_lock_secs {
+                            self.daemon_service.change_auto_lock(self.auto_lock_secs);
+                        }
+                    });
+                }
+                if has_credentials {
+                    ui.horizontal(|ui| {
+                        ui.label("Password");
+                        let old = self.credential_provider;
+                        ui.add_enabled_ui(editable, |ui| {
+                            ComboBox::from_id_salt("credential_provider")
+                                .selected_text(credential_provider_label(self.credential_provider))
+                                .show_ui(ui, |ui| {
+                                    for provider in [CredentialProvider::Prompt, CredentialProvider::SecretService, CredentialProvider::KeyFile] {
+                                        ui.selectable_value(&mut self.credential_provider, provider, credential_provider_label(provider));
+                                    }
+                                });
+                        }).response.on_hover_text("where the password is kept, it's asked for on the first unlock");
+                        if self.id.is_some() && old != self.credential_provider {
+                            self.daemon_service.change_credential_provider(self.credential_provider);
+                        }
+                    });
+                }
+                if has_cipher {
+                    ui.horizontal(|ui| {
+                        ui.label("Cipher");
+                        // changing it would need re-encrypting the whole vault
+                        ui.add_enabled_ui(editable && self.id.is_none(), |ui| {
+                            ComboBox::from_id_salt("cipher")
+                                .selected_text(cipher_label(self.cipher))
+                                .show_ui(ui, |ui| {
+                                    for cipher in [Cipher::Chacha20Poly1305, Cipher::Aes256Gcm] {
+                                        ui.selectable_value(&mut self.cipher, cipher, cipher_label(cipher));
+                                    }
+                                });
+                        }).response.on_disabled_hover_text("it can't be changed without re-encrypting the vault");
+                    });
+                }
+                if self.id.is_none() && can_init {
+                    ui.horizontal(|ui| {
+                        ui.label("Password");
+                        ui.add(TextEdit::singleline(&mut self.new_password.password).password(true));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Confirm");
+                        ui.add(TextEdit::singleline(&mut self.new_password.confirm).password(true));
+                    });
+                }
                 ui.separator();
                 ui.horizontal(|ui| {
                     if self.id.is_none() {
-                        if ui.button("Save").clicked() {
+                        if ui.add_enabled(editable, Button::new("Save")).clicked() {
                             self.name = self.name.trim().to_string();
 
-                            let mut msg = None;
                             let mut err = None;
                             if self.name.is_empty() {
-                                err = Some("invalid name".into());
+                                err = Some("invalid name");
                             } else if self.mount_point.is_none() {
-                                err = Some("invalid mount point".into());
+                                err = Some("invalid mount point");
                             } else if self.data_dir.is_none() {
-                                err = Some("invalid data dir".into());
+                                err = Some("invalid data dir");
+                            } else if can_init && self.new_password.password.is_empty() {
+                                err = Some("enter the password");
+                            } else if can_init && self.new_password.password != self.new_password.confirm {
+                                err = Some("the passwords don't match");
+                            } else if can_init {
+                                customize_toast_duration(self.toasts.warning("please wait, the vault is created and unlocked once to check the password"), 8);
+                                self.daemon_service.init_vault(
+                                    CreateVaultRequest {
+                                        name: self.name.clone(),
+                                        mount_point: self.mount_point.as_ref().unwrap().clone(),
+                                        data_dir: self.data_dir.as_ref().unwrap().clone(),
+                                        restart_policy: self.restart_policy.into(),
+                                        credential_provider: self.credential_provider.into(),
+                                        cipher: self.cipher.into(),
+                                        auto_lock_secs: self.auto_lock_secs,
+                                    },
+                                    std::mem::take(&mut self.new_password.password),
+                                );
+                                self.new_password.confirm.zeroize();
                             } else {
-                                match self.db_insert() {
-                                    Ok(_) => {
-                                        self.tx_parent.send(UiReply::VaultInserted).unwrap();
-                                        msg = Some(format!("vault {} saved", self.name));
-                                    }
-                                    Err(DatabaseError(UniqueViolation, _)) => {
-                                        err = Some(format!("another vault named {} exists", self.name));
-                                    }
-                                    Err(err2) => {
-                                        err = Some(format!("failed to save: {:?}", err2));
-                                    }
-                                }
-                            }
-                            if msg.is_some() {
-                                customize_toast(self.toasts.success(msg.unwrap()))
+                                self.daemon_service.create_vault(
+                                    self.name.clone(),
+                                    self.mount_point.as_ref().unwrap().clone(),
+                                    self.data_dir.as_ref().unwrap().clone(),
+                                    self.restart_policy,
+                                    self.credential_provider,
+                                    self.cipher,
+                                    self.auto_lock_secs,
+                                );
                             }
                             if err.is_some() {
                                 customize_toast(self.toasts.error(err.unwrap()))
@@ -238,12 +520,34 @@ impl eframe::App for ViewGroupDetail {
                         }
                     }
 
+                    if self.id.is_some() && has_jobs {
+                        if ui.add_enabled(self.job.is_none(), Button::new("Back up..."))
+                            .on_hover_text("copy the encrypted files to an empty dir").clicked() {
+                            if let Some(path) = rfd::FileDialog::new().pick_folder() {
+                                if !self.locked {
+                                    customize_toast_duration(self.toasts.warning("it will lock the vault meanwhile"), 8)
+                                }
+                                self.daemon_service.start_job(start_job_request::Job::Backup(BackupJob {
+                                    dir: path.display().to_string(),
+                                }));
+                                self.job = Some(Job { kind: JobKind::Backup.into(), ..Default::default() });
+                            }
+                        }
+                    }
+
+                    if self.id.is_some() && can_change_password {
+                        if ui.add_enabled(self.locked && !self.change_password_pending, Button::new("Change password"))
+                            .on_disabled_hover_text("lock the vault first").clicked() {
+                            self.change_password = Some(ChangePasswordDialog::default());
+                        }
+                    }
+
                     if self.id.is_some() {
-                        if Button::new(if !self.confirmation_delete_pending { "Delete" } else { "Confirm DELETE" })
-                            .fill(ecolor::Color32::DARK_RED)
-                            .ui(ui).on_hover_ui(|ui| {
+                        if ui.add_enabled(can_delete, Button::new(if !self.confirmation_delete_pending { "Delete" } else { "Confirm DELETE" })
+                            .fill(ecolor::Color32::DARK_RED))
+                            .on_hover_ui(|ui| {
                             ui.label("Delete vault");
-                        }).clicked() {
+                        }).on_disabled_hover_text("the daemon doesn't support deleting vaults, please upgrade it").clicked() {
                             if !self.confirmation_delete_pending {
                                 // ask for confirmation
                                 self.confirmation_delete_pending = true;
@@ -251,13 +555,7 @@ impl eframe::App for ViewGroupDetail {
                             } else {
                                 // confirmed, delete
                                 self.confirmation_delete_pending = false;
-                                // TODO move to service
-                                if let Err(err) = self.db_service.delete() {
-                                    customize_toast(self.toasts.error(format!("failed to delete: {:?}", err)))
-                                } else {
-                                    self.tx_parent.send(UiReply::VaultDeleted).unwrap();
-                                    customize_toast(self.toasts.success("vault deleted"))
-                                }
+                                self.daemon_service.delete_vault();
                             }
                         }
                         if self.confirmation_delete_pending {
@@ -271,9 +569,27 @@ impl eframe::App for ViewGroupDetail {
                         }
                     }
                 });
+                if self.id.is_some() && has_actions {
+                    ui.collapsing("History", |ui| {
+                        if self.actions.is_empty() {
+                            ui.label("nothing happened yet");
+                        }
+                        for action in &self.actions {
+                            ui.horizontal(|ui| {
+                                ui.label(time_ago(action.created_at));
+                                ui.strong(action.action.replace('_', " "));
+                            });
+                            if !action.message.is_empty() {
+                                ui.monospace(&action.message);
+                            }
+                        }
+                    });
+                }
             });
         });
 
+        self.show_unlock_prompt(ctx);
+        self.show_change_password(ctx);
         self.toasts.show(ctx);
     }
 }
@@ -291,16 +607,26 @@ impl ViewGroupDetail {
         Ok(ViewGroupDetail {
             id: None,
             name: "".to_string(),
+            saved_name: "".to_string(),
             mount_point: None,
             data_dir: None,
+            remove_old_data_dir: false,
+            job: None,
             locked: true,
+            restart_policy: RestartPolicy::Never,
+            credential_provider: CredentialProvider::Prompt,
+            cipher: Cipher::Chacha20Poly1305,
+            auto_lock_secs: 0,
+            new_password: NewPassword::default(),
+            actions: vec![],
             confirmation_delete_pending: false,
+            unlock_prompt: None,
+            change_password: None,
+            change_password_pending: false,
             rx_service,
             tx_parent: tx_parent.clone(),
             daemon_service,
-            db_service: DbService::new(None, tx_parent),
             toasts: Toasts::default(),
-            initialized: false,
         })
     }
 
@@ -312,49 +638,253 @@ impl ViewGroupDetail {
         if let Err(err) = daemon_service {
             return Err(err);
         }
-        let daemon_service = daemon_service.unwrap();
+        let mut daemon_service = daemon_service.unwrap();
+        if daemon_service.has_capability(capabilities::VAULT_ACTIONS) {
+            daemon_service.list_actions();
+        }
+        if daemon_service.has_capability(capabilities::JOBS) {
+            daemon_service.list_jobs();
+        }
 
         Ok(ViewGroupDetail {
             id: Some(item.id),
+            saved_name: item.name.clone(),
             name: item.name,
             mount_point: Some(item.mount_point),
             data_dir: Some(item.data_dir),
+            remove_old_data_dir: false,
+            job: None,
             locked: item.locked,
+            restart_policy: item.restart_policy,
+            credential_provider: item.credential_provider,
+            cipher: item.cipher,
+            auto_lock_secs: item.auto_lock_secs,
+            new_password: NewPassword::default(),
+            actions: vec![],
             confirmation_delete_pending: false,
+            unlock_prompt: None,
+            change_password: None,
+            change_password_pending: false,
             rx_service,
             tx_parent: tx_parent.clone(),
             daemon_service,
-            db_service: DbService::new(Some(item.id), tx_parent),
             toasts: Toasts::default(),
-            initialized: false,
         })
     }
 
-    fn db_insert(&mut self) -> QueryResult<()> {
-        let new_vault = NewVault {
-            name: self.name.clone(),
-            mount_point: self.mount_point.as_ref().unwrap().clone(),
-            data_dir: self.data_dir.as_ref().unwrap().clone(),
+    pub(crate) fn on_vault_event(&mut self, event: &VaultEvent) {
+        if self.id != Some(event.id as i32) {
+            return;
+        }
+        match &event.event {
+            Some(vault_event::Event::Locked(_)) => self.locked = true,
+            Some(vault_event::Event::Unlocked(_)) => self.locked = false,
+            Some(vault_event::Event::MountPointChanged(e)) => {
+                self.mount_point = Some(e.mount_point.clone())
+            }
+            Some(vault_event::Event::DataDirChanged(e)) => {
+                self.data_dir = Some(e.data_dir.clone());
+            }
+            // the daemon has no jobs to watch, the move is followed through the events
+            Some(vault_event::Event::DataDirMoving(e))
+                if !self.daemon_service.has_capability(capabilities::JOBS) =>
+            {
+                let percent = (e.done_bytes * 100).checked_div(e.total_bytes).unwrap_or(0);
+                self.job = Some(Job {
+                    kind: JobKind::DataDirMove.into(),
+                    phase: e.phase,
+                    percent: percent as u32,
+                    done_bytes: e.done_bytes,
+                    total_bytes: e.total_bytes,
+                    ..Default::default()
+                });
+            }
+            Some(vault_event::Event::DataDirMoving(_)) => {}
+            Some(vault_event::Event::Renamed(e)) => {
+                self.name = e.name.clone();
+                self.saved_name = e.name.clone();
+            }
+            Some(vault_event::Event::ProcessDied(_)) => self.locked = true,
+            Some(vault_event::Event::Error(_))
+            | Some(vault_event::Event::Created(_))
+            | Some(vault_event::Event::Deleted(_))
+            | Some(vault_event::Event::PasswordRequested(_))
+            | Some(vault_event::Event::AutoLockWarning(_))
+            | Some(vault_event::Event::Resync(_))
+            | None => {}
+        }
+        // the daemon records what happens to the process
+        if matches!(
+            &event.event,
+            Some(vault_event::Event::ProcessDied(_))
+                | Some(vault_event::Event::Unlocked(_))
+                | Some(vault_event::Event::Error(_))
+        ) && self.daemon_service.has_capability(capabilities::VAULT_ACTIONS)
+        {
+            self.daemon_service.list_actions();
+        }
+    }
+
+    /// Takes what the events would have changed from `item`, reloaded after some were missed.
+    pub(crate) fn on_resync(&mut self, item: &Item) {
+        self.name = item.name.clone();
+        self.saved_name = item.name.clone();
+        self.mount_point = Some(item.mount_point.clone());
+        self.data_dir = Some(item.data_dir.clone());
+        self.locked = item.locked;
+        if self.daemon_service.has_capability(capabilities::VAULT_ACTIONS) {
+            self.daemon_service.list_actions();
+        }
+    }
+
+    fn show_unlock_prompt(&mut self, ctx: &Context) {
+        let Some(prompt) = self.unlock_prompt.as_mut() else {
+            return;
         };
-        self.db_service.insert(new_vault)
+        // a prompted password is stored the same way, nothing to choose
+        let can_remember = self.credential_provider != CredentialProvider::Prompt;
+        let mut answer = None;
+        Window::new("Wrong password")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!("The password of {} is wrong, enter it again", self.name));
+                let response = ui.add(TextEdit::singleline(&mut prompt.password).password(true));
+                response.request_focus();
+                let entered = response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+                if can_remember {
+                    ui.checkbox(&mut prompt.remember, "Remember it")
+                        .on_hover_text("replace the stored password with this one");
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Unlock").clicked() || entered {
+                        answer = Some(Some((std::mem::take(&mut prompt.password), prompt.remember)));
+                    }
+                    if ui.button("Cancel").clicked() {
+                        answer = Some(None);
+                    }
+                });
+            });
+        if let Some(answer) = answer {
+            self.unlock_prompt = None;
+            if let Some((password, remember)) = answer {
+                self.daemon_service.unlock_vault(Some(password), remember && can_remember);
+            }
+        }
     }
 
-    fn db_reload(&mut self) {
-        let vault = self.db_service.get_vault().unwrap();
-        self.name = vault.name;
-        self.mount_point = Some(vault.mount_point);
-        self.data_dir = Some(vault.data_dir);
-        self.locked = vault.locked == 1;
-        self.tx_parent.send(UiReply::VaultUpdated(false)).unwrap();
+    fn show_change_password(&mut self, ctx: &Context) {
+        let Some(dialog) = self.change_password.as_mut() else {
+            return;
+        };
+        let mut submit = false;
+        let mut cancel = false;
+        Window::new("Change password")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                egui::Grid::new("change_password").show(ui, |ui| {
+                    ui.label("Current password");
+                    ui.add(TextEdit::singleline(&mut dialog.current).password(true));
+                    ui.end_row();
+                    ui.label("New password");
+                    ui.add(TextEdit::singleline(&mut dialog.new).password(true));
+                    ui.end_row();
+                    ui.label("Confirm");
+                    let response = ui.add(TextEdit::singleline(&mut dialog.confirm).password(true));
+                    submit = response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+                    ui.end_row();
+                });
+                ui.horizontal(|ui| {
+                    submit |= ui.button("Change").clicked();
+                    cancel = ui.button("Cancel").clicked();
+                });
+            });
+        if cancel {
+            self.change_password = None;
+        } else if submit {
+            let err = if dialog.current.is_empty() {
+                Some("enter the current password")
+            } else if dialog.new.is_empty() {
+                Some("enter the new password")
+            } else if dialog.new != dialog.confirm {
+                Some("the new passwords don't match")
+            } else {
+                None
+            };
+            if let Some(err) = err {
+                self.toasts
+                    .error(err)
+                    .set_closable(false)
+                    .set_duration(Some(Duration::from_secs(5)))
+                    .set_show_progress_bar(false);
+                return;
+            }
+            let current = std::mem::take(&mut dialog.current);
+            let new = std::mem::take(&mut dialog.new);
+            self.change_password = None;
+            self.change_password_pending = true;
+            self.daemon_service.change_password(current, new);
+        }
     }
 
     fn ui_on_name_lost_focus(&mut self) {
-        if let Some(_) = self.id {
-            let old_name = self.db_service.get_vault().unwrap().name;
-            if old_name != self.name {
-                self.db_service.update(name.eq(self.name.clone()));
-                self.tx_parent.send(UiReply::VaultUpdated(true)).unwrap();
-            }
+        if self.id.is_some() && self.saved_name != self.name {
+            self.daemon_service.rename(self.name.clone());
         }
     }
 }
+
+fn restart_policy_label(policy: RestartPolicy) -> &'static str {
+    match policy {
+        RestartPolicy::Never => "never",
+        RestartPolicy::OnFailure => "on failure",
+        RestartPolicy::Always => "always",
+    }
+}
+
+fn credential_provider_label(provider: CredentialProvider) -> &'static str {
+    match provider {
+        CredentialProvider::Prompt => "ask every time",
+        CredentialProvider::SecretService => "system keyring",
+        CredentialProvider::KeyFile => "key file",
+    }
+}
+
+/// Idle seconds, the daemon takes 0 or at least a minute.
+const AUTO_LOCK_CHOICES: [u32; 6] = [0, 5 * 60, 15 * 60, 30 * 60, 60 * 60, 4 * 60 * 60];
+
+fn auto_lock_label(secs: u32) -> String {
+    match secs {
+        0 => "never".to_string(),
+        s if s % 3600 == 0 => format!("after {} h idle", s / 3600),
+        s if s % 60 == 0 => format!("after {} min idle", s / 60),
+        s => format!("after {s} s idle"),
+    }
+}
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+fn move_phase_label(phase: DataDirMovePhase) -> &'static str {
+    match phase {
+        DataDirMovePhase::Copying => "Copying",
+        DataDirMovePhase::Verifying => "Verifying",
+        DataDirMovePhase::RemovingOld => "Removing the old data dir",
+    }
+}
+
+fn job_kind_label(kind: JobKind) -> &'static str {
+    match kind {
+        JobKind::DataDirMove => "Moving the data dir",
+        JobKind::Backup => "Backup",
+    }
+}
+
+fn cipher_label(cipher: Cipher) -> &'static str {
+    match cipher {
+        Cipher::Chacha20Poly1305 => "ChaCha20-Poly1305",
+        Cipher::Aes256Gcm => "AES-256-GCM",
+    }
+}
@@ -1,19 +1,30 @@
 use crate::daemon_service::vault_service_client::VaultServiceClient;
-use crate::daemon_service::{EmptyReply, HelloRequest, IdRequest, StringIdRequest};
+use crate::daemon_service::{
+    start_job_request, ChangePasswordRequest, Cipher, CreateVaultRequest, CredentialProvider,
+    DaemonInfo, GetDaemonInfoRequest, IdRequest, InitVaultRequest, JobIdRequest, JobState,
+    ListJobsRequest, ListVaultActionsRequest, ListVaultsRequest, ProvidePasswordRequest,
+    RencfsStatus, RestartPolicy, StartJobRequest, UnlockRequest, UpdateVaultRequest, Vault,
+    VaultReply, WatchVaultsRequest,
+};
 use crate::dashboard::UiReply;
 use crate::detail::ServiceReply;
+use crate::util::with_hint;
 use crate::RT;
+use rencfs_desktop_common::protocol;
+use rencfs_desktop_common::protocol::capabilities;
 use rencfs_desktop_common::vault_service_error::VaultServiceError;
 use std::sync::mpsc::Sender;
+use std::time::Duration;
 use tonic::transport::{Channel, Error};
-use tonic::{Response, Status};
-use tracing::{error, instrument};
+use tonic::{Code, Response, Status};
+use tracing::{error, info, instrument, warn};
 
-pub(super) struct DaemonService {
+pub(crate) struct DaemonService {
     id: Option<i32>,
     tx_service: Sender<ServiceReply>,
     tx_parent: Sender<UiReply>,
     client: VaultServiceClient<Channel>,
+    info: DaemonInfo,
 }
 
 impl DaemonService {
@@ -24,79 +35,38 @@ impl DaemonService {
         tx_parent: Sender<UiReply>,
     ) -> Result<Self, String> {
         let (tx2, tx_p2) = (tx_service.clone(), tx_parent.clone());
-        RT.block_on(async { Self::create_client(tx2, tx_p2).await })
-            .map_or_else(
-                |err| Err(format!("failed to connect to daemon: {}", err.to_string())),
-                |client| {
-                    Ok(Self {
-                        id,
-                        tx_service,
-                        tx_parent,
-                        client,
-                    })
-                },
-            )
-    }
-
-    pub(super) fn hello(&mut self, name: &str) {
-        let tx = self.tx_service.clone();
-        let tx_parent = self.tx_parent.clone();
-        let mut client = self.client.clone();
-        let name = name.to_string();
-        RT.spawn(async move {
-            let request = tonic::Request::new(HelloRequest { name });
-            match client.hello(request).await {
-                Ok(response) => {
-                    let _ = tx
-                        .send(ServiceReply::HelloReply(response.into_inner()))
-                        .map_err(|_| {
-                            // in case the component is destroyed before the response is received,
-                            // we will not be able
-                            // to notify service reply because the rx is closed
-                            // in that case notify parent with error because it's rx is still open
-                            let _ = tx_parent.send(UiReply::VaultUpdated(true));
-                        });
-                }
-                Err(err) => {
-                    let vault_service_error: Result<VaultServiceError, _> = err.clone().try_into();
-                    match vault_service_error {
-                        Ok(err2) => {
-                            error!(err2 = %err2);
-                            let _ = tx
-                                .send(ServiceReply::VaultServiceError(err2.clone()))
-                                .map_err(|_| {
-                                    // in case the component is destroyed before the response is received,
-                                    // we will not be able
-                                    // to notify service reply because the rx is closed
-                                    // in that case notify parent with error because it's rx is still open
-                                    let _ = tx_parent.send(UiReply::Error(err2.to_string()));
-                                });
-                        }
-                        _ => {
-                            error!(err = %err);
-                            let res = tx.send(ServiceReply::Error(format!("Error: {}", err)));
-                            if let Err(err) = res {
-                                // in case the component is destroyed before the response is received,
-                                // we will not be able
-                                // to notify service reply because the rx is closed
-                                // in that case notify parent with error because it's rx is still open
-                                let _ = tx_parent.send(UiReply::Error(err.to_string()));
-                            }
-                        }
-                    }
-                }
-            }
-        });
+        let mut client = RT
+            .block_on(async { Self::create_client(tx2, tx_p2).await })
+            .map_err(|err| format!("failed to connect to daemon: {}", err.to_string()))?;
+        let info = RT.block_on(Self::handshake(&mut client))?;
+
+        Ok(Self {
+            id,
+            tx_service,
+            tx_parent,
+            client,
+            info,
+        })
+    }
+
+    /// If the connected daemon advertises the capability, see [`capabilities`].
+    pub(super) fn has_capability(&self, capability: &str) -> bool {
+        self.info.capabilities.iter().any(|c| c == capability)
     }
 
-    pub(super) fn unlock_vault(&mut self) {
+    /// Unlocks with `password` if set, or with the one from the credential provider of the vault.
+    pub(super) fn unlock_vault(&mut self, password: Option<String>, remember: bool) {
         let id = self.id.as_ref().unwrap().clone() as u32;
         let tx = self.tx_service.clone();
         let tx_parent = self.tx_parent.clone();
         let mut client = self.client.clone();
         RT.spawn(async move {
-            let request = tonic::Request::new(IdRequest { id });
-            Self::handle_empty_response(
+            let request = tonic::Request::new(UnlockRequest {
+                id,
+                password,
+                remember,
+            });
+            Self::handle_response(
                 client.unlock(request).await,
                 ServiceReply::UnlockVaultReply,
                 tx,
@@ -112,7 +82,7 @@ impl DaemonService {
         let mut client = self.client.clone();
         RT.spawn(async move {
             let request = tonic::Request::new(IdRequest { id });
-            Self::handle_empty_response(
+            Self::handle_response(
                 client.lock(request).await,
                 ServiceReply::LockVaultReply,
                 tx,
@@ -121,42 +91,471 @@ impl DaemonService {
         });
     }
 
+    pub(super) fn create_vault(
+        &mut self,
+        name: String,
+        mount_point: String,
+        data_dir: String,
+        restart_policy: RestartPolicy,
+        credential_provider: CredentialProvider,
+        cipher: Cipher,
+        auto_lock_secs: u32,
+    ) {
+        let tx = self.tx_service.clone();
+        let tx_parent = self.tx_parent.clone();
+        let mut client = self.client.clone();
+        RT.spawn(async move {
+            let request = tonic::Request::new(CreateVaultRequest {
+                name,
+                mount_point,
+                data_dir,
+                restart_policy: restart_policy.into(),
+                credential_provider: credential_provider.into(),
+                cipher: cipher.into(),
+                auto_lock_secs,
+            });
+            Self::handle_response(
+                client.create_vault(request).await,
+                ServiceReply::VaultCreated,
+                tx,
+                tx_parent,
+            );
+        });
+    }
+
+    /// Like [`Self::create_vault`], also creating the encrypted store with `password`.
+    pub(super) fn init_vault(&mut self, vault: CreateVaultRequest, password: String) {
+        let tx = self.tx_service.clone();
+        let tx_parent = self.tx_parent.clone();
+        let mut client = self.client.clone();
+        RT.spawn(async move {
+            let request = tonic::Request::new(InitVaultRequest {
+                vault: Some(vault),
+                password,
+            });
+            Self::handle_response(
+                client.init_vault(request).await,
+                ServiceReply::VaultCreated,
+                tx,
+                tx_parent,
+            );
+        });
+    }
+
+    pub(super) fn rename(&mut self, name: String) {
+        self.update_vault(
+            UpdateVaultRequest {
+                name: Some(name),
+                ..Default::default()
+            },
+            ServiceReply::Renamed,
+        );
+    }
+
     pub(super) fn change_mount_point(&mut self, value: String) {
-        let id = self.id.as_ref().unwrap().clone() as u32;
+        self.update_vault(
+            UpdateVaultRequest {
+                mount_point: Some(value),
+                ..Default::default()
+            },
+            ServiceReply::ChangeMountPoint,
+        );
+    }
+
+    pub(super) fn change_data_dir(&mut self, value: String, remove_old: bool) {
+        self.update_vault(
+            UpdateVaultRequest {
+                data_dir: Some(value),
+                remove_old_data_dir: remove_old,
+                ..Default::default()
+            },
+            ServiceReply::ChangeDataDir,
+        );
+    }
+
+    pub(super) fn change_restart_policy(&mut self, value: RestartPolicy) {
+        self.update_vault(
+            UpdateVaultRequest {
+                restart_policy: Some(value.into()),
+                ..Default::default()
+            },
+            ServiceReply::RestartPolicyChanged,
+        );
+    }
+
+    pub(super) fn change_credential_provider(&mut self, value: CredentialProvider) {
+        self.update_vault(
+            UpdateVaultRequest {
+                credential_provider: Some(value.into()),
+                ..Default::default()
+            },
+            ServiceReply::CredentialProviderChanged,
+        );
+    }
+
+    /// `0` never locks it.
+    pub(super) fn change_auto_lock(&mut self, secs: u32) {
+        self.update_vault(
+            UpdateVaultRequest {
+                auto_lock_secs: Some(secs),
+                ..Default::default()
+            },
+            ServiceReply::AutoLockChanged,
+        );
+    }
+
+    pub(super) fn change_password(&mut self, current_password: String, new_password: String) {
+        let id = *self.id.as_ref().unwrap() as u32;
         let tx = self.tx_service.clone();
         let tx_parent = self.tx_parent.clone();
         let mut client = self.client.clone();
         RT.spawn(async move {
-            let request = tonic::Request::new(StringIdRequest { id, value });
-            Self::handle_empty_response(
-                client.change_mount_point(request).await,
-                ServiceReply::ChangeMountPoint,
+            let request = tonic::Request::new(ChangePasswordRequest {
+                id,
+                current_password,
+                new_password,
+            });
+            Self::handle_response(
+                client.change_password(request).await,
+                ServiceReply::PasswordChanged,
                 tx,
                 tx_parent,
             );
         });
     }
 
-    pub(super) fn change_data_dir(&mut self, value: String) {
-        let id = self.id.as_ref().unwrap().clone() as u32;
+    pub(super) fn list_actions(&mut self) {
+        let id = *self.id.as_ref().unwrap() as u32;
+        let tx = self.tx_service.clone();
+        let tx_parent = self.tx_parent.clone();
+        let mut client = self.client.clone();
+        RT.spawn(async move {
+            let request = tonic::Request::new(ListVaultActionsRequest { id, limit: None });
+            Self::handle_response(
+                client.list_vault_actions(request).await,
+                ServiceReply::VaultActions,
+                tx,
+                tx_parent,
+            );
+        });
+    }
+
+    /// Starts `job` in the background, see [`Self::watch_job`] for its progress.
+    pub(super) fn start_job(&mut self, job: start_job_request::Job) {
+        let vault_id = *self.id.as_ref().unwrap() as u32;
+        let tx = self.tx_service.clone();
+        let tx_parent = self.tx_parent.clone();
+        let mut client = self.client.clone();
+        RT.spawn(async move {
+            let request = tonic::Request::new(StartJobRequest {
+                vault_id,
+                job: Some(job),
+            });
+            Self::handle_response(
+                client.start_job(request).await,
+                ServiceReply::JobStarted,
+                tx,
+                tx_parent,
+            );
+        });
+    }
+
+    /// Sends every change of the job until it ends, the last one with how it ended.
+    ///
+    /// If the updates stop before that the job is fetched again, [`ServiceReply::JobLost`] is sent
+    /// when that fails too.
+    pub(super) fn watch_job(&mut self, id: u32) {
+        let tx = self.tx_service.clone();
+        let mut client = self.client.clone();
+        RT.spawn(async move {
+            loop {
+                match client
+                    .watch_job(tonic::Request::new(JobIdRequest { id }))
+                    .await
+                {
+                    Ok(response) => {
+                        let mut stream = response.into_inner();
+                        loop {
+                            match stream.message().await {
+                                Ok(Some(job)) => {
+                                    let ended = job.state() != JobState::Running;
+                                    if tx.send(ServiceReply::JobUpdated(job)).is_err() || ended {
+                                        // the view was closed, or that was the last one
+                                        return;
+                                    }
+                                }
+                                Ok(None) => break,
+                                Err(err) => {
+                                    warn!(id, err = %err, "Job stream failed");
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Err(err) => warn!(id, err = %err, "Cannot watch job"),
+                }
+
+                // the daemon might be stopping, or it was restarted and failed the job
+                let reply = match client
+                    .get_job(tonic::Request::new(JobIdRequest { id }))
+                    .await
+                {
+                    Ok(response) => {
+                        let job = response.into_inner();
+                        if job.state() == JobState::Running {
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                            continue;
+                        }
+                        ServiceReply::JobUpdated(job)
+                    }
+                    Err(err) => ServiceReply::JobLost(err.message().to_string()),
+                };
+                let _ = tx.send(reply);
+                return;
+            }
+        });
+    }
+
+    pub(super) fn cancel_job(&mut self, id: u32) {
         let tx = self.tx_service.clone();
         let tx_parent = self.tx_parent.clone();
         let mut client = self.client.clone();
         RT.spawn(async move {
-            let request = tonic::Request::new(StringIdRequest { id, value });
-            Self::handle_empty_response(
-                client.change_data_dir(request).await,
-                ServiceReply::ChangeDataDir,
+            let request = tonic::Request::new(JobIdRequest { id });
+            Self::handle_response(
+                client.cancel_job(request).await,
+                ServiceReply::JobCancelled,
                 tx,
                 tx_parent,
             );
         });
     }
 
-    #[instrument(skip(f))]
-    fn handle_empty_response(
-        result: Result<Response<EmptyReply>, Status>,
-        f: impl FnOnce(EmptyReply) -> ServiceReply,
+    /// The last job of the vault, to show its progress if it's still running.
+    pub(super) fn list_jobs(&mut self) {
+        let vault_id = *self.id.as_ref().unwrap() as u32;
+        let tx = self.tx_service.clone();
+        let tx_parent = self.tx_parent.clone();
+        let mut client = self.client.clone();
+        RT.spawn(async move {
+            let request = tonic::Request::new(ListJobsRequest {
+                vault_id: Some(vault_id),
+                limit: Some(1),
+            });
+            Self::handle_response(
+                client.list_jobs(request).await,
+                ServiceReply::Jobs,
+                tx,
+                tx_parent,
+            );
+        });
+    }
+
+    pub(super) fn delete_vault(&mut self) {
+        let id = *self.id.as_ref().unwrap() as u32;
+        let tx = self.tx_service.clone();
+        let tx_parent = self.tx_parent.clone();
+        let mut client = self.client.clone();
+        RT.spawn(async move {
+            let request = tonic::Request::new(IdRequest { id });
+            Self::handle_response(
+                client.delete_vault(request).await,
+                ServiceReply::VaultDeleted,
+                tx,
+                tx_parent,
+            );
+        });
+    }
+
+    fn update_vault(
+        &mut self,
+        request: UpdateVaultRequest,
+        f: impl FnOnce(VaultReply) -> ServiceReply + Send + 'static,
+    ) {
+        let id = *self.id.as_ref().unwrap() as u32;
+        let tx = self.tx_service.clone();
+        let tx_parent = self.tx_parent.clone();
+        let mut client = self.client.clone();
+        RT.spawn(async move {
+            let request = tonic::Request::new(UpdateVaultRequest { id, ..request });
+            Self::handle_response(client.update_vault(request).await, f, tx, tx_parent);
+        });
+    }
+
+    /// Blocks until the vaults are loaded from the daemon.
+    pub(crate) fn list_vaults() -> Result<Vec<Vault>, String> {
+        RT.block_on(async {
+            let channel = rencfs_desktop_common::ipc::connect()
+                .await
+                .map_err(|err| format!("failed to connect to daemon: {err}"))?;
+            let mut client = VaultServiceClient::new(channel);
+            Self::handshake(&mut client).await?;
+            client
+                .list_vaults(tonic::Request::new(ListVaultsRequest { limit: None }))
+                .await
+                .map(|response| response.into_inner().vaults)
+                .map_err(|err| {
+                    let vault_service_error: Result<VaultServiceError, _> = err.clone().try_into();
+                    match vault_service_error {
+                        Ok(err2) => {
+                            with_hint(&err2, VaultServiceError::hint_from_status(&err).as_deref())
+                        }
+                        Err(_) => format!("Error: {err}"),
+                    }
+                })
+        })
+    }
+
+    pub(crate) fn daemon_info() -> Result<DaemonInfo, String> {
+        RT.block_on(async {
+            let channel = rencfs_desktop_common::ipc::connect()
+                .await
+                .map_err(|err| format!("failed to connect to daemon: {err}"))?;
+            Self::handshake(&mut VaultServiceClient::new(channel)).await
+        })
+    }
+
+    /// Answers a password request of the daemon, `None` to cancel it, errors go to `tx_parent`.
+    pub(crate) fn provide_password(
+        request_id: u64,
+        password: Option<String>,
+        tx_parent: Sender<UiReply>,
+    ) {
+        RT.spawn(async move {
+            let res = async {
+                let channel = rencfs_desktop_common::ipc::connect()
+                    .await
+                    .map_err(|err| format!("failed to connect to daemon: {err}"))?;
+                VaultServiceClient::new(channel)
+                    .provide_password(tonic::Request::new(ProvidePasswordRequest {
+                        request_id,
+                        password,
+                    }))
+                    .await
+                    .map_err(Self::status_message)
+            };
+            if let Err(err) = res.await {
+                let _ = tx_parent.send(UiReply::Error(err));
+            }
+        });
+    }
+
+    /// Answers an `AutoLockWarningEvent`, the vault is idle from now on.
+    pub(crate) fn keep_unlocked(id: u32, tx_parent: Sender<UiReply>) {
+        RT.spawn(async move {
+            let res = async {
+                let channel = rencfs_desktop_common::ipc::connect()
+                    .await
+                    .map_err(|err| format!("failed to connect to daemon: {err}"))?;
+                VaultServiceClient::new(channel)
+                    .keep_unlocked(tonic::Request::new(IdRequest { id }))
+                    .await
+                    .map_err(Self::status_message)
+            };
+            if let Err(err) = res.await {
+                let _ = tx_parent.send(UiReply::Error(err));
+            }
+        });
+    }
+
+    fn status_message(err: Status) -> String {
+        let vault_service_error: Result<VaultServiceError, _> = err.clone().try_into();
+        match vault_service_error {
+            Ok(err2) => with_hint(&err2, VaultServiceError::hint_from_status(&err).as_deref()),
+            Err(_) => format!("Error: {err}"),
+        }
+    }
+
+    /// What to tell the user if the daemon cannot run rencfs.
+    pub(crate) fn rencfs_warning(info: &DaemonInfo) -> Option<String> {
+        if !info
+            .capabilities
+            .iter()
+            .any(|c| c == capabilities::RENCFS_INFO)
+        {
+            return None;
+        }
+        let path = info.rencfs_path.as_deref().unwrap_or("PATH");
+        match info.rencfs_status() {
+            RencfsStatus::Ok => None,
+            RencfsStatus::Missing => Some(format!(
+                "rencfs was not found in {path}, vaults cannot be unlocked until it's installed"
+            )),
+            RencfsStatus::UnknownVersion => Some(format!(
+                "cannot get the version of rencfs at {path}, it might not work"
+            )),
+            RencfsStatus::Unsupported => Some(format!(
+                "rencfs {} at {path} is not supported, install {}",
+                info.rencfs_version.as_deref().unwrap_or("unknown"),
+                info.supported_rencfs_versions
+            )),
+        }
+    }
+
+    /// Forwards vault events to `tx_parent` until it's closed, reconnecting if the daemon goes away.
+    pub(crate) fn watch_vaults(tx_parent: Sender<UiReply>) {
+        RT.spawn(async move {
+            loop {
+                match rencfs_desktop_common::ipc::connect().await {
+                    Ok(channel) => {
+                        let mut client = VaultServiceClient::new(channel);
+                        // the daemon might be restarting, or upgraded to one that can send them
+                        let can_watch = match Self::handshake(&mut client).await {
+                            Ok(info) => {
+                                let can_watch = info
+                                    .capabilities
+                                    .iter()
+                                    .any(|c| c == capabilities::WATCH_VAULTS);
+                                if !can_watch {
+                                    warn!("Daemon cannot send vault events");
+                                }
+                                can_watch
+                            }
+                            Err(err) => {
+                                warn!(err, "Cannot watch vaults");
+                                false
+                            }
+                        };
+                        if can_watch {
+                            let request = tonic::Request::new(WatchVaultsRequest { ids: vec![] });
+                            match client.watch_vaults(request).await {
+                                Ok(response) => {
+                                    info!("Watching vaults");
+                                    let mut stream = response.into_inner();
+                                    loop {
+                                        match stream.message().await {
+                                            Ok(Some(event)) => {
+                                                if tx_parent
+                                                    .send(UiReply::VaultEvent(event))
+                                                    .is_err()
+                                                {
+                                                    // nobody is listening anymore
+                                                    return;
+                                                }
+                                            }
+                                            Ok(None) => break,
+                                            Err(err) => {
+                                                warn!(err = %err, "Vault events stream failed");
+                                                break;
+                                            }
+                                        }
+                                    }
+                                }
+                                Err(err) => warn!(err = %err, "Cannot watch vaults"),
+                            }
+                        }
+                    }
+                    Err(err) => warn!(err = %err, "Cannot connect to daemon to watch vaults"),
+                }
+                tokio::time::sleep(Duration::from_secs(2)).await;
+            }
+        });
+    }
+
+    #[instrument(skip(result, f))]
+    fn handle_response<T>(
+        result: Result<Response<T>, Status>,
+        f: impl FnOnce(T) -> ServiceReply,
         tx: Sender<ServiceReply>,
         tx_parent: Sender<UiReply>,
     ) {
@@ -175,14 +574,16 @@ impl DaemonService {
                 match vault_service_error {
                     Ok(err2) => {
                         error!(err2 = %err2);
+                        let hint = VaultServiceError::hint_from_status(&err);
+                        let msg = with_hint(&err2, hint.as_deref());
                         let _ = tx
-                            .send(ServiceReply::VaultServiceError(err2.clone()))
+                            .send(ServiceReply::VaultServiceError(err2, hint))
                             .map_err(|_| {
                                 // in case the component is destroyed before the response is received,
                                 // we will not be able
                                 // to notify service reply because the rx is closed
                                 // in that case notify parent with error because it's rx is still open
-                                let _ = tx_parent.send(UiReply::Error(err2.to_string()));
+                                let _ = tx_parent.send(UiReply::Error(msg));
                             });
                     }
                     _ => {
@@ -201,13 +602,48 @@ impl DaemonService {
         }
     }
 
+    /// Gets the daemon info and checks we can talk to it, the error is meant for the user.
+    #[instrument(skip(client), err)]
+    async fn handshake(client: &mut VaultServiceClient<Channel>) -> Result<DaemonInfo, String> {
+        let request = tonic::Request::new(GetDaemonInfoRequest {
+            protocol_version: protocol::PROTOCOL_VERSION,
+        });
+        let info = match client.get_daemon_info(request).await {
+            Ok(response) => response.into_inner(),
+            // daemons before the handshake was added
+            Err(err) if err.code() == Code::Unimplemented => {
+                return Err(protocol::ProtocolError::DaemonTooOld {
+                    daemon_version: "unknown".to_string(),
+                    protocol_version: 0,
+                }
+                .to_string());
+            }
+            Err(err) => return Err(format!("cannot get daemon info: {}", err.message())),
+        };
+        protocol::check_daemon(
+            &info.daemon_version,
+            info.protocol_version,
+            info.min_protocol_version,
+        )
+        .map_err(|err| err.to_string())?;
+        info!(
+            daemon_version = info.daemon_version,
+            protocol_version = info.protocol_version,
+            rencfs_version = info.rencfs_version,
+            rencfs_path = info.rencfs_path,
+            "Connected to daemon"
+        );
+
+        Ok(info)
+    }
+
     async fn create_client(
         tx: Sender<ServiceReply>,
         tx_parent: Sender<UiReply>,
     ) -> Result<VaultServiceClient<Channel>, Error> {
-        // TODO: https://github.com/radumarias/rencfs-desktop/issues/13
-        VaultServiceClient::connect("http://[::1]:50051")
+        rencfs_desktop_common::ipc::connect()
             .await
+            .map(VaultServiceClient::new)
             .map_err(|err| {
                 let _ = tx
                     .send(ServiceReply::Error(format!("{err:?}")))
@@ -1,5 +1,6 @@
 use egui_notify::Toast;
-use std::time::Duration;
+use std::fmt::Display;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub(crate) fn customize_toast_duration(t: &mut Toast, seconds: u64) {
     let duration = Some(Duration::from_secs(seconds));
@@ -11,3 +12,25 @@ pub(crate) fn customize_toast_duration(t: &mut Toast, seconds: u64) {
 pub(crate) fn customize_toast(t: &mut Toast) {
     customize_toast_duration(t, 5);
 }
+
+/// Error message followed by the hint on what to do about it, if any.
+pub(crate) fn with_hint(err: &impl Display, hint: Option<&str>) -> String {
+    match hint {
+        Some(hint) => format!("{err}\n{hint}"),
+        None => err.to_string(),
+    }
+}
+
+/// How long ago, like `5 min ago`, for seconds since the Unix epoch.
+pub(crate) fn time_ago(secs: i64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    match (now - secs).max(0) {
+        s if s < 60 => format!("{s} s ago"),
+        s if s < 3600 => format!("{} min ago", s / 60),
+        s if s < 86400 => format!("{} h ago", s / 3600),
+        s => format!("{} days ago", s / 86400),
+    }
+}